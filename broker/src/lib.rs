@@ -1,23 +1,141 @@
 use std::{
-    net::TcpStream,
+    collections::HashMap,
+    net::{Shutdown, SocketAddr, TcpStream},
     path::PathBuf,
     sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 use partition::PartitionDetails;
-use shared_structures::{Broadcast, DirManager, EntityType, Message, Metadata, Status, Topic};
+use shared_structures::{
+    metadata::BrokerDetails,
+    record::{self, Record},
+    tracing::{Span, SpanKind},
+    Broadcast, DirManager, EntityType, Message, Metadata, Metrics, Status, Topic,
+};
 use uuid::Uuid;
 
+pub mod metrics;
 mod partition;
 
 pub use partition::Partition;
 
+// Longest a fetch waits for records, however long the consumer asks to wait
+const MAX_FETCH_WAIT: Duration = Duration::from_secs(60);
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct LocalMetadata {
     id: String,
     pub partitions: Vec<Partition>,
 }
 
+impl LocalMetadata {
+    /// Replicas the cluster has assigned to this broker that don't exist locally,
+    /// e.g. replicas that were assigned while the broker was offline.
+    pub fn missing_replicas(&self, cluster_metadata: &Metadata) -> Vec<PartitionDetails> {
+        let broker_details = match self.find_broker_details(cluster_metadata) {
+            Some(b) => b,
+            None => return vec![],
+        };
+
+        broker_details
+            .partitions
+            .iter()
+            .filter(|p| {
+                !self
+                    .partitions
+                    .iter()
+                    .any(|l| l.details.replica_id == p.replica_id)
+            })
+            .map(|p| PartitionDetails {
+                id: p.id.clone(),
+                replica_id: p.replica_id.clone(),
+                status: Status::Up,
+                topic: p.topic.clone(),
+                role: p.role,
+                partition_number: p.partition_number,
+                replica_number: p.replica_count,
+            })
+            .collect()
+    }
+
+    /// Ids of the local replicas the cluster doesn't know of on this broker anymore.
+    pub fn orphaned_replicas(&self, cluster_metadata: &Metadata) -> Vec<String> {
+        let broker_details = match self.find_broker_details(cluster_metadata) {
+            Some(b) => b,
+            None => return vec![],
+        };
+
+        self.partitions
+            .iter()
+            .filter(|l| {
+                !broker_details
+                    .partitions
+                    .iter()
+                    .any(|p| p.replica_id == l.details.replica_id)
+            })
+            .map(|l| l.details.replica_id.clone())
+            .collect()
+    }
+
+    /// Brings the roles, statuses and topic configurations of the local replicas known to the cluster
+    /// in line with the cluster metadata. Returns whether anything has changed.
+    pub fn reconcile(&mut self, cluster_metadata: &Metadata) -> bool {
+        let broker_details = match cluster_metadata.brokers.iter().find(|b| b.id == self.id) {
+            Some(b) => b,
+            None => return false,
+        };
+
+        let mut changed = false;
+
+        for partition in self.partitions.iter_mut() {
+            let cluster_partition = match broker_details
+                .partitions
+                .iter()
+                .find(|p| p.replica_id == partition.details.replica_id)
+            {
+                Some(p) => p,
+                None => continue,
+            };
+
+            let role = cluster_partition.role;
+
+            if partition.details.topic != cluster_partition.topic {
+                log::info!(
+                    "Replica {} received topic `{}` configuration: {}",
+                    partition.details.replica_id,
+                    cluster_partition.topic.name,
+                    cluster_partition.topic.config
+                );
+                partition.details.topic = cluster_partition.topic.clone();
+                changed = true;
+            }
+
+            if partition.details.role != role || partition.details.status != Status::Up {
+                log::info!(
+                    "Replica {} reconciled with the cluster: {:?} -> {:?}, {:?} -> {:?}",
+                    partition.details.replica_id,
+                    partition.details.role,
+                    role,
+                    partition.details.status,
+                    Status::Up
+                );
+                partition.details.role = role;
+                partition.details.status = Status::Up;
+                changed = true;
+            }
+        }
+
+        changed
+    }
+
+    // Cluster metadata can be received before the Observer registered this broker,
+    // in which case there is nothing to reconcile against.
+    fn find_broker_details<'a>(&self, cluster_metadata: &'a Metadata) -> Option<&'a BrokerDetails> {
+        cluster_metadata.brokers.iter().find(|b| b.id == self.id)
+    }
+}
+
 const METADATA_FILE: &str = "metadata.json";
 
 #[derive(Debug)]
@@ -27,16 +145,26 @@ pub struct Broker {
     pub cluster_metadata: Metadata,
     pub stream: TcpStream,
     pub connected_producers: Arc<Mutex<Vec<TcpStream>>>,
+    // Held while writing to a producer or consumer, shared with the writers of the connection's subscriptions
+    pub client_write_locks: HashMap<SocketAddr, Arc<Mutex<()>>>,
     pub addr: String,
-    pub custom_dir: Option<PathBuf>,
+    pub rack: Option<String>,
+    // Set once the broker has asked the Observer to leave the cluster, new producers and messages are refused
+    pub leaving: bool,
+    // Set once the Observer has moved the leadership off the broker's replicas
+    pub left: bool,
+    pub metrics: Arc<Metrics>,
 }
 
 impl Broker {
-    /// Broker will automatically initiate a handshake with the Observer
+    /// Broker will automatically initiate a handshake with the Observer, `addr` is the address
+    /// advertised to the Observer and `data_dir` replaces the default Nyx folder when provided.
     pub fn new(
         stream: TcpStream,
         addr: String,
         name: Option<&String>,
+        rack: Option<&String>,
+        data_dir: Option<&PathBuf>,
     ) -> Result<Arc<Mutex<Self>>, String> {
         let custom_dir: Option<PathBuf> = name.map(|f| format!("/broker/{}", f).into());
 
@@ -44,15 +172,20 @@ impl Broker {
 
         let connected_producers = Arc::new(Mutex::new(vec![]));
 
-        let dir_manager = DirManager::with_dir(custom_dir.as_ref());
+        let metrics = Arc::new(Metrics::new());
 
-        let mut broker = match dir_manager.open::<LocalMetadata>(METADATA_FILE) {
-            Ok(mut local_metadata) => {
+        let rack = rack.cloned();
+
+        let dir_manager = DirManager::with_root(data_dir, custom_dir.as_ref());
+
+        // A corrupted metadata file fails the startup, a new identity would orphan all the local replicas
+        let mut broker = match dir_manager.open_optional::<LocalMetadata>(METADATA_FILE)? {
+            Some(mut local_metadata) => {
                 // Making sure to instantiatte a database for each local partition
                 local_metadata.partitions = local_metadata
                     .partitions
                     .iter_mut()
-                    .map(|p| Partition::from(p.details.clone(), custom_dir.as_ref()).unwrap())
+                    .map(|p| Partition::from(p.details.clone(), &dir_manager, &metrics).unwrap())
                     .collect();
 
                 Self {
@@ -61,11 +194,15 @@ impl Broker {
                     dir_manager,
                     cluster_metadata,
                     connected_producers,
+                    client_write_locks: HashMap::new(),
                     addr,
-                    custom_dir,
+                    rack,
+                    leaving: false,
+                    left: false,
+                    metrics,
                 }
             }
-            Err(_e) => {
+            None => {
                 let id = Uuid::new_v4().to_string();
 
                 let local_metadata = LocalMetadata {
@@ -81,8 +218,12 @@ impl Broker {
                     dir_manager,
                     cluster_metadata,
                     connected_producers,
+                    client_write_locks: HashMap::new(),
                     addr,
-                    custom_dir,
+                    rack,
+                    leaving: false,
+                    left: false,
+                    metrics,
                 }
             }
         };
@@ -102,18 +243,382 @@ impl Broker {
                 Message::BrokerConnectionDetails {
                     id: self.local_metadata.id.clone(),
                     addr: self.addr.clone(),
+                    rack: self.rack.clone(),
                 },
             ],
         )
     }
 
+    pub fn send_heartbeat(&mut self) -> Result<(), String> {
+        Broadcast::to(&mut self.stream, &Message::Heartbeat)
+    }
+
+    /// Removes the records of every replica that are past the retention period of their topic,
+    /// a replica that fails to clean up is retried on the next call.
+    pub fn clean_up_partitions(&mut self) {
+        let now = record::now_millis();
+
+        for partition in self.local_metadata.partitions.iter_mut() {
+            match partition.clean_up(now) {
+                Ok(0) => {}
+                Ok(removed) => log::info!(
+                    "Removed {} records of replica {} past the retention period ({:?})",
+                    removed,
+                    partition.details.replica_id,
+                    partition.details.topic.config.cleanup_policy
+                ),
+                Err(e) => log::warn!(
+                    "Failed to clean up replica {}: {}",
+                    partition.details.replica_id,
+                    e
+                ),
+            }
+        }
+    }
+
+    /// Tells the Observer that the broker is shutting down, the Observer answers with
+    /// `LeaveAcknowledged` once the leadership has moved off the broker's replicas.
+    pub fn request_leave(&mut self) -> Result<(), String> {
+        self.leaving = true;
+        Broadcast::to(&mut self.stream, &Message::BrokerLeaving)
+    }
+
+    /// Flushes and closes the database of every replica, saves the local metadata and closes the
+    /// connections with the producers and the Observer. The broker can't be used afterwards.
+    pub fn close(&mut self) -> Result<(), String> {
+        self.leaving = true;
+
+        for partition in self.local_metadata.partitions.iter_mut() {
+            partition.close()?;
+        }
+
+        self.dir_manager.save(METADATA_FILE, &self.local_metadata)?;
+
+        for producer in self.connected_producers.lock().unwrap().drain(..) {
+            // The producer may have disconnected already
+            let _ = producer.shutdown(Shutdown::Both);
+        }
+
+        let _ = self.stream.shutdown(Shutdown::Both);
+
+        Ok(())
+    }
+
+    /// Replaces the connection with the Observer and repeats the handshake on it,
+    /// used when the broker is redirected to the leader Observer.
+    pub fn reconnect(&mut self, stream: TcpStream) -> Result<(), String> {
+        self.stream = stream;
+        self.handshake()
+    }
+
+    /// Handles a message read from a producer, consumer or the Observer, `payload` holds
+    /// the binary payload following the message, see `Reader::read_payload`.
     pub fn handle_raw_message(
         &mut self,
         raw_data: &str,
+        payload: &[u8],
         remote: Option<&mut TcpStream>,
     ) -> Result<(), String> {
         let message = serde_json::from_str::<Message>(raw_data).map_err(|e| e.to_string())?;
-        self.handle_message(&message, remote)
+        let request_name = metrics::request_name(&message);
+
+        // Only the produce and replication paths are traced, the cluster metadata exchanged
+        // with the Observer would drown them
+        let mut span = match &message {
+            Message::ProducerMessage { traceparent, .. }
+            | Message::CreatePartition { traceparent, .. } => {
+                let mut span = Span::start_remote(
+                    format!("broker {}", request_name),
+                    SpanKind::Server,
+                    *traceparent,
+                );
+                span.set_attribute("message.size", raw_data.len() + payload.len());
+                Some(span)
+            }
+            _ => None,
+        };
+
+        let started = Instant::now();
+
+        // Producers are answered with the offset of their record or why it was rejected, the connection stays open
+        if let Message::ProducerMessage { replica_id, .. } = &message {
+            let response = self.produce(replica_id, payload);
+
+            observe_request(
+                &self.metrics,
+                request_name,
+                started,
+                &response,
+                span.as_mut(),
+            );
+
+            return match remote {
+                Some(remote) => Broadcast::to(remote, &Message::ProduceResponse { response }),
+                None => response.map(|_| ()),
+            };
+        }
+
+        let result = self.handle_message(&message, remote);
+
+        observe_request(&self.metrics, request_name, started, &result, span.as_mut());
+
+        result
+    }
+
+    /// Appends the record in `payload` to the replica, returns its offset. Records larger than the
+    /// `max_message_size` of the topic, or produced while fewer than `min_insync_replicas` replicas
+    /// of the partition are up, are rejected.
+    fn produce(&mut self, replica_id: &str, payload: &[u8]) -> Result<u64, String> {
+        if self.leaving {
+            return Err("Broker is shutting down, the message was not stored.".to_string());
+        }
+
+        let (record, record_size) = Record::decode(payload)?;
+
+        if record_size != payload.len() {
+            return Err(format!(
+                "Record of {} bytes doesn't match the declared size of {} bytes.",
+                record_size,
+                payload.len()
+            ));
+        }
+
+        log::trace!("Record for partition replica {}: {:?}", replica_id, record);
+
+        let insync_replicas = self.insync_replicas(replica_id);
+
+        let partition = self
+            .local_metadata
+            .partitions
+            .iter_mut()
+            .find(|p| p.details.replica_id == *replica_id)
+            .ok_or("No corresponding partition replica was found on the broker.")?;
+
+        let topic_config = &partition.details.topic.config;
+        let message_size = record.size();
+        let topic_name = partition.details.topic.name.clone();
+        let partition_number = partition.details.partition_number.to_string();
+        let labels = [
+            ("topic", topic_name.as_str()),
+            ("partition", partition_number.as_str()),
+        ];
+
+        if message_size > topic_config.max_message_size {
+            self.metrics
+                .increment(&metrics::MESSAGES_REJECTED, &labels, 1.0);
+
+            return Err(format!(
+                "Message of {} bytes exceeds the max message size of topic `{}` ({} bytes).",
+                message_size, topic_name, topic_config.max_message_size
+            ));
+        }
+
+        if insync_replicas < topic_config.min_insync_replicas {
+            self.metrics
+                .increment(&metrics::MESSAGES_REJECTED, &labels, 1.0);
+
+            return Err(format!(
+                "Partition {} of topic `{}` has {} in-sync replicas, {} are required, the message was not stored.",
+                partition_number, topic_name, insync_replicas, topic_config.min_insync_replicas
+            ));
+        }
+
+        let offset = partition.put(record)?;
+
+        self.metrics.increment(&metrics::MESSAGES_IN, &labels, 1.0);
+        self.metrics
+            .increment(&metrics::BYTES_IN, &labels, message_size as f64);
+
+        Ok(offset)
+    }
+
+    // Replicas of the partition that are up, this replica along with those on the other brokers which are up
+    fn insync_replicas(&self, replica_id: &str) -> usize {
+        let Some(partition) = self
+            .local_metadata
+            .partitions
+            .iter()
+            .find(|p| p.details.replica_id == *replica_id)
+        else {
+            return 0;
+        };
+
+        let other_replicas = self
+            .cluster_metadata
+            .brokers
+            .iter()
+            .filter(|b| b.id != self.local_metadata.id && b.status == Status::Up)
+            .filter(|b| b.partitions.iter().any(|p| p.id == partition.details.id))
+            .count();
+
+        1 + other_replicas
+    }
+
+    /// Handles a message read from a producer or consumer. Fetches which can't be answered yet
+    /// wait for records to be appended to the partition, the broker is unlocked in the meantime.
+    pub fn handle_client_message(
+        broker: &Mutex<Broker>,
+        raw_data: &str,
+        payload: &[u8],
+        stream: &mut TcpStream,
+    ) -> Result<(), String> {
+        let message = serde_json::from_str::<Message>(raw_data).map_err(|e| e.to_string())?;
+        let request_name = metrics::request_name(&message);
+
+        let Message::Fetch {
+            replica_id,
+            offset,
+            max_records,
+            min_bytes,
+            max_bytes,
+            max_wait_ms,
+            traceparent,
+        } = message
+        else {
+            // Held before the broker, the writers of the connection's subscriptions only take this lock
+            let write_lock = broker.lock().unwrap().client_write_lock(stream)?;
+            let _writing = write_lock.lock().unwrap();
+
+            return broker
+                .lock()
+                .unwrap()
+                .handle_raw_message(raw_data, payload, Some(stream));
+        };
+
+        let mut span = Span::start_remote(
+            format!("broker {}", request_name),
+            SpanKind::Server,
+            traceparent,
+        );
+        span.set_attribute("message.size", raw_data.len());
+        span.set_attribute("replica", replica_id.as_str());
+        span.set_attribute("offset", offset);
+
+        let metrics = broker.lock().unwrap().metrics.clone();
+        let started = Instant::now();
+        let deadline = started + Duration::from_millis(max_wait_ms).min(MAX_FETCH_WAIT);
+
+        let records = loop {
+            let broker_lock = broker.lock().unwrap();
+
+            let Some(partition) = broker_lock
+                .local_metadata
+                .partitions
+                .iter()
+                .find(|p| p.details.replica_id == replica_id)
+            else {
+                break Err(
+                    "No corresponding partition replica was found on the broker.".to_string(),
+                );
+            };
+
+            // Records are appended while the broker is locked, so no append
+            // can slip in between the read and the wait
+            let appends = partition.appends.clone();
+            let seen = appends.count();
+
+            match partition.read(offset, max_records, max_bytes) {
+                Ok(records) => {
+                    let bytes: u64 = records.iter().map(|(_, r)| r.size()).sum();
+
+                    if bytes >= min_bytes as u64 || Instant::now() >= deadline {
+                        partition.count_out(&records);
+                        break Ok(records);
+                    }
+                }
+                Err(e) => break Err(e),
+            }
+
+            drop(broker_lock);
+            appends.wait(seen, deadline);
+        };
+
+        if let Ok(records) = &records {
+            span.set_attribute("records", records.len());
+        }
+
+        let write_lock = broker.lock().unwrap().client_write_lock(stream)?;
+        let _writing = write_lock.lock().unwrap();
+
+        // Failures are answered to the consumer, the connection stays open
+        let result = match records {
+            Ok(records) => {
+                let batch = record::encode_batch(&records);
+                Broadcast::with_payload(
+                    stream,
+                    &Message::FetchResponse {
+                        response: Ok(batch.len()),
+                    },
+                    &batch,
+                )
+            }
+            Err(e) => {
+                span.set_error(&e);
+                Broadcast::to(stream, &Message::FetchResponse { response: Err(e) })
+            }
+        };
+
+        observe_request(&metrics, request_name, started, &result, Some(&mut span));
+
+        result
+    }
+
+    /// Removes the subscriptions of a disconnected producer or consumer.
+    pub fn remove_subscriptions(&mut self, peer_addr: SocketAddr) {
+        for partition in self.local_metadata.partitions.iter_mut() {
+            partition.unsubscribe(peer_addr);
+        }
+
+        self.client_write_locks.remove(&peer_addr);
+    }
+
+    // Every message written to a producer or consumer is written while holding the lock of its connection
+    fn client_write_lock(&mut self, stream: &TcpStream) -> Result<Arc<Mutex<()>>, String> {
+        let peer_addr = stream.peer_addr().map_err(|e| e.to_string())?;
+
+        Ok(self
+            .client_write_locks
+            .entry(peer_addr)
+            .or_default()
+            .clone())
+    }
+
+    /// Refreshes the gauges read from the state of the broker, called before every metrics scrape.
+    pub fn collect_metrics(&self) {
+        self.metrics.clear(&metrics::PARTITION_LOG_SIZE);
+        self.metrics.clear(&metrics::PARTITION_STORAGE_SIZE);
+
+        for partition in self.local_metadata.partitions.iter() {
+            let partition_number = partition.details.partition_number.to_string();
+            let labels = [
+                ("topic", partition.details.topic.name.as_str()),
+                ("partition", partition_number.as_str()),
+            ];
+
+            self.metrics.set(
+                &metrics::PARTITION_LOG_SIZE,
+                &labels,
+                partition.log_size() as f64,
+            );
+
+            match partition.storage_size() {
+                Ok(size) => {
+                    self.metrics
+                        .set(&metrics::PARTITION_STORAGE_SIZE, &labels, size as f64)
+                }
+                Err(e) => log::warn!(
+                    "Failed to read the storage size of replica {}: {}",
+                    partition.details.replica_id,
+                    e
+                ),
+            }
+        }
+
+        self.metrics.set(
+            &metrics::CONNECTED_PRODUCERS,
+            &[],
+            self.connected_producers.lock().unwrap().len() as f64,
+        );
     }
 
     // Messages from Producers and Observers are all processed here
@@ -130,6 +635,7 @@ impl Broker {
                 topic,
                 replica_count,
                 partition_number,
+                ..
             } => self.handle_create_partition(
                 id,
                 replica_id,
@@ -138,9 +644,9 @@ impl Broker {
                 *partition_number,
             ),
             Message::ClusterMetadata { metadata } => {
-                println!("New metadata received from the cluster: {:#?}", metadata);
+                log::debug!("New metadata received from the cluster: {:?}", metadata);
                 self.cluster_metadata = metadata.clone();
-                Ok(())
+                self.reconcile_with_cluster()
             }
             Message::RequestClusterMetadata => {
                 if let Some(remote) = remote {
@@ -157,12 +663,109 @@ impl Broker {
                     )
                 }
             }
-            Message::ProducerMessage {
+            Message::LeaveAcknowledged => {
+                log::info!("Observer has acknowledged that the broker is leaving.");
+                self.left = true;
+                Ok(())
+            }
+            Message::ListOffsets { replica_id, spec } => {
+                let remote = remote.ok_or("ListOffsets is missing the requesting remote stream")?;
+
+                let response = self
+                    .local_metadata
+                    .partitions
+                    .iter()
+                    .find(|p| p.details.replica_id == *replica_id)
+                    .ok_or(
+                        "No corresponding partition replica was found on the broker.".to_string(),
+                    )
+                    .and_then(|partition| partition.list_offset(*spec));
+
+                Broadcast::to(remote, &Message::ListOffsetsResponse { response })
+            }
+            Message::CommitOffset {
+                replica_id,
+                group,
+                offset,
+            } => {
+                let remote =
+                    remote.ok_or("CommitOffset is missing the requesting remote stream")?;
+
+                let response = self
+                    .local_metadata
+                    .partitions
+                    .iter()
+                    .find(|p| p.details.replica_id == *replica_id)
+                    .ok_or(
+                        "No corresponding partition replica was found on the broker.".to_string(),
+                    )
+                    .and_then(|partition| partition.commit_offset(group, *offset));
+
+                Broadcast::to(remote, &Message::CommitOffsetResponse { response })
+            }
+            Message::FetchCommittedOffset { replica_id, group } => {
+                let remote =
+                    remote.ok_or("FetchCommittedOffset is missing the requesting remote stream")?;
+
+                let response = self
+                    .local_metadata
+                    .partitions
+                    .iter()
+                    .find(|p| p.details.replica_id == *replica_id)
+                    .ok_or(
+                        "No corresponding partition replica was found on the broker.".to_string(),
+                    )
+                    .and_then(|partition| partition.committed_offset(group));
+
+                Broadcast::to(remote, &Message::FetchCommittedOffsetResponse { response })
+            }
+            Message::Subscribe {
                 replica_id,
-                payload,
+                offset,
+                credit,
             } => {
-                println!("Received a message for partition replica {}!!!", replica_id);
-                println!("Message: {:#?}", payload);
+                let remote = remote.ok_or("Subscribe is missing the requesting remote stream")?;
+                let write_lock = self.client_write_lock(remote)?;
+
+                let Some(partition) = self
+                    .local_metadata
+                    .partitions
+                    .iter_mut()
+                    .find(|p| p.details.replica_id == *replica_id)
+                else {
+                    return Broadcast::to(
+                        remote,
+                        &Message::SubscribeResponse {
+                            response: Err(
+                                "No corresponding partition replica was found on the broker."
+                                    .to_string(),
+                            ),
+                        },
+                    );
+                };
+
+                // The writer of the subscription waits for the connection's write lock, which is held
+                // while this request is handled, so the response is written before the first records
+                let response = partition.subscribe(remote, write_lock, *offset, *credit);
+
+                Broadcast::to(remote, &Message::SubscribeResponse { response })
+            }
+            Message::Credit { replica_id, credit } => {
+                let remote = remote.ok_or("Credit is missing the requesting remote stream")?;
+                let peer_addr = remote.peer_addr().map_err(|e| e.to_string())?;
+
+                let partition = self
+                    .local_metadata
+                    .partitions
+                    .iter_mut()
+                    .find(|p| p.details.replica_id == *replica_id)
+                    .ok_or("No corresponding partition replica was found on the broker.")?;
+
+                partition.add_credit(peer_addr, *credit)
+            }
+            Message::Unsubscribe { replica_id } => {
+                let remote = remote.ok_or("Unsubscribe is missing the requesting remote stream")?;
+                let peer_addr = remote.peer_addr().map_err(|e| e.to_string())?;
 
                 if let Some(partition) = self
                     .local_metadata
@@ -170,10 +773,15 @@ impl Broker {
                     .iter_mut()
                     .find(|p| p.details.replica_id == *replica_id)
                 {
-                    partition.put(payload)
-                } else {
-                    Err("No corresponding partition replica was found on the broker.".to_string())
+                    partition.unsubscribe(peer_addr);
                 }
+
+                Broadcast::to(
+                    remote,
+                    &Message::Unsubscribed {
+                        replica_id: replica_id.clone(),
+                    },
+                )
             }
             _ => Err(format!(
                 "Message {:?} is not handled in `handle_message`.",
@@ -182,6 +790,59 @@ impl Broker {
         }
     }
 
+    /// Reconciles the local replicas with the last received cluster metadata, replicas assigned
+    /// to this broker are created, replicas unknown to the cluster are quarantined and the rest
+    /// get their roles and statuses from the cluster.
+    pub fn reconcile_with_cluster(&mut self) -> Result<(), String> {
+        let missing_replicas = self.local_metadata.missing_replicas(&self.cluster_metadata);
+        let orphaned_replicas = self
+            .local_metadata
+            .orphaned_replicas(&self.cluster_metadata);
+
+        let mut changed = !missing_replicas.is_empty() || !orphaned_replicas.is_empty();
+
+        for partition_details in missing_replicas {
+            log::info!(
+                "Creating replica {} of partition {} assigned by the cluster",
+                partition_details.replica_id,
+                partition_details.id
+            );
+            // A single broken replica shouldn't keep the broker from serving the rest
+            match Partition::from(partition_details, &self.dir_manager, &self.metrics) {
+                Ok(partition) => self.local_metadata.partitions.push(partition),
+                Err(e) => log::error!("Failed to create the missing replica: {}", e),
+            }
+        }
+
+        for replica_id in orphaned_replicas {
+            let position = self
+                .local_metadata
+                .partitions
+                .iter()
+                .position(|p| p.details.replica_id == replica_id);
+
+            if let Some(position) = position {
+                let partition = self.local_metadata.partitions.remove(position);
+                match partition.quarantine(&self.dir_manager) {
+                    Ok(path) => log::warn!(
+                        "Replica {} is unknown to the cluster, its data was quarantined in {}",
+                        replica_id,
+                        path.display()
+                    ),
+                    Err(e) => log::error!("Failed to quarantine replica {}: {}", replica_id, e),
+                }
+            }
+        }
+
+        changed |= self.local_metadata.reconcile(&self.cluster_metadata);
+
+        if changed {
+            self.dir_manager.save(METADATA_FILE, &self.local_metadata)?;
+        }
+
+        Ok(())
+    }
+
     fn handle_create_partition(
         &mut self,
         id: &str,
@@ -190,6 +851,21 @@ impl Broker {
         replica_number: usize,
         partition_number: usize,
     ) -> Result<(), String> {
+        // Replica might have been created already while reconciling with the cluster metadata
+        if self
+            .local_metadata
+            .partitions
+            .iter()
+            .any(|p| p.details.replica_id == replica_id)
+        {
+            return Ok(());
+        }
+
+        let mut span = Span::start("partition.create", SpanKind::Internal);
+        span.set_attribute("topic", topic.name.as_str());
+        span.set_attribute("partition", partition_number);
+        span.set_attribute("replica", replica_id);
+
         let partition_details = PartitionDetails {
             id: id.to_string(),
             replica_id: replica_id.to_string(),
@@ -199,11 +875,176 @@ impl Broker {
             partition_number,
             replica_number,
         };
-        let partition = Partition::from(partition_details, self.custom_dir.as_ref())?;
+        let partition = Partition::from(partition_details, &self.dir_manager, &self.metrics)?;
         self.local_metadata.partitions.push(partition);
         self.dir_manager.save(METADATA_FILE, &self.local_metadata)
     }
 }
 
+// Records the duration and failure of a request in the metrics and the failure in its span
+fn observe_request<T>(
+    metrics: &Metrics,
+    request_name: &str,
+    started: Instant,
+    result: &Result<T, String>,
+    span: Option<&mut Span>,
+) {
+    let labels = [("request", request_name)];
+
+    metrics.observe(
+        &metrics::REQUEST_DURATION,
+        &labels,
+        started.elapsed().as_secs_f64(),
+    );
+
+    if let Err(e) = result {
+        metrics.increment(&metrics::REQUESTS_FAILED, &labels, 1.0);
+
+        if let Some(span) = span {
+            span.set_error(e);
+        }
+    }
+}
+
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use shared_structures::{metadata::PartitionDetails as ClusterPartitionDetails, Role};
+
+    use super::*;
+
+    fn mock_partition(replica_id: &str) -> Partition {
+        Partition {
+            details: PartitionDetails {
+                id: "mocked_partition_id".to_string(),
+                replica_id: replica_id.to_string(),
+                status: Status::Up,
+                topic: Topic::from("notifications".to_string()),
+                role: Role::Follower,
+                partition_number: 1,
+                replica_number: 1,
+            },
+            database: None,
+            appends: Arc::default(),
+            subscriptions: vec![],
+            metrics: Arc::default(),
+        }
+    }
+
+    fn mock_cluster_metadata() -> Metadata {
+        Metadata {
+            brokers: vec![BrokerDetails {
+                id: "mocked_broker_id".to_string(),
+                addr: "localhost:123123".to_string(),
+                rack: None,
+                status: Status::Up,
+                partitions: vec![
+                    ClusterPartitionDetails {
+                        id: "mocked_partition_id".to_string(),
+                        replica_id: "known_replica".to_string(),
+                        role: Role::Leader,
+                        topic: Topic::from("notifications".to_string()),
+                        partition_number: 1,
+                        replica_count: 1,
+                    },
+                    ClusterPartitionDetails {
+                        id: "mocked_partition_id".to_string(),
+                        replica_id: "missing_replica".to_string(),
+                        role: Role::Follower,
+                        topic: Topic::from("notifications".to_string()),
+                        partition_number: 1,
+                        replica_count: 2,
+                    },
+                ],
+            }],
+            topics: vec![Topic::from("notifications".to_string())],
+        }
+    }
+
+    #[test]
+    fn reconcile_updates_replicas_from_cluster_metadata() {
+        let mut local_metadata = LocalMetadata {
+            id: "mocked_broker_id".to_string(),
+            partitions: vec![
+                mock_partition("known_replica"),
+                mock_partition("stale_replica"),
+            ],
+        };
+
+        let cluster_metadata = mock_cluster_metadata();
+
+        assert!(local_metadata.reconcile(&cluster_metadata));
+
+        assert_eq!(local_metadata.partitions[0].details.role, Role::Leader);
+        assert_eq!(local_metadata.partitions[0].details.status, Status::Up);
+        // Orphaned replicas are left for the broker to quarantine
+        assert_eq!(local_metadata.partitions[1].details.role, Role::Follower);
+
+        // Nothing changes the second time around
+        assert!(!local_metadata.reconcile(&cluster_metadata));
+    }
+
+    #[test]
+    fn reconcile_updates_topic_config_from_cluster_metadata() {
+        let mut local_metadata = LocalMetadata {
+            id: "mocked_broker_id".to_string(),
+            partitions: vec![mock_partition("known_replica")],
+        };
+
+        let mut cluster_metadata = mock_cluster_metadata();
+        cluster_metadata.brokers[0].partitions[0]
+            .topic
+            .config
+            .max_message_size = 1024;
+
+        assert!(local_metadata.reconcile(&cluster_metadata));
+        assert_eq!(
+            local_metadata.partitions[0]
+                .details
+                .topic
+                .config
+                .max_message_size,
+            1024
+        );
+    }
+
+    #[test]
+    fn finds_missing_and_orphaned_replicas() {
+        let local_metadata = LocalMetadata {
+            id: "mocked_broker_id".to_string(),
+            partitions: vec![
+                mock_partition("known_replica"),
+                mock_partition("stale_replica"),
+            ],
+        };
+
+        let cluster_metadata = mock_cluster_metadata();
+
+        let missing_replicas = local_metadata.missing_replicas(&cluster_metadata);
+        assert_eq!(missing_replicas.len(), 1);
+        assert_eq!(missing_replicas[0].replica_id, "missing_replica");
+        assert_eq!(missing_replicas[0].replica_number, 2);
+        assert_eq!(missing_replicas[0].status, Status::Up);
+
+        assert_eq!(
+            local_metadata.orphaned_replicas(&cluster_metadata),
+            vec!["stale_replica".to_string()]
+        );
+    }
+
+    #[test]
+    fn nothing_to_reconcile_before_broker_is_registered() {
+        let local_metadata = LocalMetadata {
+            id: "unregistered_broker_id".to_string(),
+            partitions: vec![mock_partition("stale_replica")],
+        };
+
+        let cluster_metadata = mock_cluster_metadata();
+
+        assert!(local_metadata
+            .missing_replicas(&cluster_metadata)
+            .is_empty());
+        assert!(local_metadata
+            .orphaned_replicas(&cluster_metadata)
+            .is_empty());
+    }
+}
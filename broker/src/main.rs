@@ -2,95 +2,234 @@ use std::{
     error::Error,
     io::{BufRead, BufReader},
     net::{TcpListener, TcpStream},
-    sync::{Arc, Mutex},
-    time::Duration,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
 use broker::Broker;
 use clap::{arg, command};
-use shared_structures::println_c;
+use shared_structures::{logger, tracing, Config, Message, MessageDecoder, Reader};
+
+// Upper bound for the interval between attempts to connect to the Observers
+const MAX_RETRY_INTERVAL: u64 = 30000;
+// Interval in which records past the retention period of their topic are removed
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+// How long a shutting down broker waits for the Observer to move the leadership off its replicas
+const LEAVE_TIMEOUT: Duration = Duration::from_secs(10);
 
 fn main() -> Result<(), Box<dyn Error>> {
     let matches = command!()
-    .arg(clap::Arg::new("host")
+    .arg(clap::Arg::new("observers")
+        .help("List of Observers to connect to seperated by comma e.g. localhost:2828,localhost:2829, the broker will find the leader among them.")
         .required(true)
     )
     .arg(
         arg!(-n --name <NAME> "Assigns a name to the broker, names are useful if you want to run two brokers on the same machine. Useful for nyx maintainers testing multi-node features.")
         .required(false)
+    )
+    .arg(
+        arg!(-r --rack <RACK> "The rack or zone the broker is running in, the Observer will avoid placing replicas of the same partition in the same rack.")
+        .required(false)
+    )
+    .arg(
+        arg!(--"heartbeat-interval" <MS> "Interval in ms in which the broker sends heartbeats to the Observer, should be well below the session_timeout of the Observer.")
+        .required(false)
+        .value_parser(clap::value_parser!(u64))
+        .default_value("3000")
+    )
+    .arg(
+        arg!(-c --config <PATH> "Path to the broker's configuration file, see config/broker.properties.")
+        .required(false)
+    )
+    .arg(
+        arg!(--host <HOST> "Host the broker listens on for producers, overrides `host` of the configuration.")
+        .required(false)
+    )
+    .arg(
+        arg!(-p --port <PORT> "Port the broker listens on for producers, a random port is used when not set. Overrides `port` of the configuration.")
+        .required(false)
+        .value_parser(clap::value_parser!(u16))
+    )
+    .arg(
+        arg!(--"advertised-addr" <ADDR> "Address advertised to the Observer and producers, defaults to the address the broker listens on. Overrides `advertised_addr` of the configuration.")
+        .required(false)
+    )
+    .arg(
+        arg!(--"data-dir" <DIR> "Directory the broker stores its data in instead of ~/.config/nyx. Overrides `data_dir` of the configuration.")
+        .required(false)
+    )
+    .arg(
+        arg!(--"metrics-addr" <ADDR> "Address the Prometheus metrics are served on at /metrics, e.g. localhost:9101. Overrides `metrics_addr` of the configuration.")
+        .required(false)
+    )
+    .arg(
+        arg!(--"trace-exporter" <DEST> "OTLP/HTTP endpoint, e.g. http://localhost:4318, or file the trace spans of the produce and replication paths are exported to. Overrides `trace_exporter` of the configuration.")
+        .required(false)
+    )
+    .arg(
+        arg!(--"log-level" <LEVEL> "Most verbose level of the logs written to stderr.")
+        .required(false)
+        .value_parser(logger::LEVELS)
+        .default_value("info")
+    )
+    .arg(
+        arg!(--"log-format" <FORMAT> "Format of the logs, `json` writes one object per line for log collectors.")
+        .required(false)
+        .value_parser(logger::FORMATS)
+        .default_value("text")
     ).get_matches();
 
-    let addr = matches.get_one::<String>("host").unwrap();
+    if let Err(e) = logger::init(
+        matches.get_one::<String>("log-level").unwrap(),
+        matches.get_one::<String>("log-format").unwrap(),
+    ) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+
+    let addr = matches.get_one::<String>("observers").unwrap();
     let name = matches.get_one::<String>("name");
+    let rack = matches.get_one::<String>("rack");
+    let heartbeat_interval =
+        Duration::from_millis(*matches.get_one::<u64>("heartbeat-interval").unwrap());
 
-    let log_name = match name {
-        Some(n) => n,
-        None => "broker",
+    let config = match matches.get_one::<String>("config") {
+        Some(config_path) => Config::from(config_path.into()),
+        None => Config::from_env(),
     };
 
-    println_c(&format!("Initializing {}", log_name), 105);
+    let mut config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
-    let tcp_stream: Option<TcpStream>;
-    let mut sleep_interval = 1000;
+    if let Some(host) = matches.get_one::<String>("host") {
+        config.host = host.clone();
+    }
 
-    // Should  trying to connect to the observer in intervals until success
-    loop {
-        match TcpStream::connect(addr) {
-            Ok(stream) => {
-                tcp_stream = Some(stream);
-                println!("Connection with the Observer has been established");
-                break;
-            }
+    if let Some(port) = matches.get_one::<u16>("port") {
+        config.port = Some(*port);
+    }
 
-            Err(_) => {
-                println!(
-                    "Failed to connect to the Observer, next retry in {}s",
-                    Duration::from_millis(sleep_interval).as_secs_f32()
-                );
-                std::thread::sleep(Duration::from_millis(sleep_interval));
-                sleep_interval += 1500;
-            }
-        }
+    if let Some(advertised_addr) = matches.get_one::<String>("advertised-addr") {
+        config.advertised_addr = Some(advertised_addr.clone());
     }
 
-    let stream: TcpStream = tcp_stream.ok_or("Stream is wrong")?;
+    if let Some(data_dir) = matches.get_one::<String>("data-dir") {
+        config.data_dir = Some(PathBuf::from(data_dir));
+    }
+
+    if let Some(metrics_addr) = matches.get_one::<String>("metrics-addr") {
+        config.metrics_addr = Some(metrics_addr.clone());
+    }
+
+    if let Some(trace_exporter) = matches.get_one::<String>("trace-exporter") {
+        config.trace_exporter = Some(trace_exporter.clone());
+    }
+
+    if let Some(trace_exporter) = &config.trace_exporter {
+        tracing::init("broker", trace_exporter)?;
+        log::info!("Trace spans are exported to {}", trace_exporter);
+    }
+
+    let log_name = match name {
+        Some(n) => n,
+        None => "broker",
+    };
+
+    log::info!("Initializing {}", log_name);
+
+    let observers: Vec<String> = addr
+        .split_terminator(',')
+        .map(|o| o.trim().to_string())
+        .collect();
 
     // Port 0 means that we let the system find a free port in itself and use that
-    let listener = TcpListener::bind("localhost:0").map_err(|e| e.to_string())?;
+    let bind_addr = format!("{}:{}", config.host, config.port.unwrap_or(0));
+
+    let listener = TcpListener::bind(&bind_addr)
+        .map_err(|e| format!("Failed to listen on {}: {}", bind_addr, e))?;
 
-    let host = listener.local_addr().unwrap();
+    let advertised_addr = config
+        .advertised_addr
+        .clone()
+        .unwrap_or(listener.local_addr()?.to_string());
 
-    let broker = Broker::new(stream, host.to_string(), name)?;
+    let stream = connect_to_observer(&observers);
+
+    let broker = Broker::new(
+        stream,
+        advertised_addr,
+        name,
+        rack,
+        config.data_dir.as_ref(),
+    )?;
 
     let broker_lock = broker.lock().unwrap();
 
     let reader_stream = broker_lock.stream.try_clone().map_err(|e| e.to_string())?;
 
-    println_c(
-        &format!(
-            "Broker is ready to accept producers on port {}",
-            listener.local_addr().unwrap().port()
-        ),
-        50,
+    log::info!(
+        "Broker is ready to accept producers on port {}",
+        listener.local_addr().unwrap().port()
     );
 
     let connected_producers = broker_lock.connected_producers.clone();
 
+    if let Some(metrics_addr) = &config.metrics_addr {
+        let metrics_broker = broker.clone();
+        let metrics_addr = shared_structures::metrics::serve(
+            metrics_addr,
+            broker_lock.metrics.clone(),
+            move |_| metrics_broker.lock().unwrap().collect_metrics(),
+        )?;
+        log::info!("Metrics are served on http://{}/metrics", metrics_addr);
+    }
+
     let broker_for_producers = broker.clone();
 
-    // Producers listener
+    let signal_broker = broker.clone();
+    let shutting_down = AtomicBool::new(false);
+
+    // SIGINT / SIGTERM let the broker leave the cluster before it exits, a second signal exits right away
+    ctrlc::set_handler(move || {
+        if shutting_down.swap(true, Ordering::SeqCst) {
+            log::error!("Broker was forced to exit before shutting down.");
+            std::process::exit(1);
+        }
+
+        log::info!("Shutting down the broker...");
+
+        // The handler has to return to receive the second signal
+        let broker = signal_broker.clone();
+        std::thread::spawn(move || std::process::exit(shutdown(&broker)));
+    })?;
+
+    // Producers and consumers listener
     std::thread::spawn(move || loop {
         let connection = listener.incoming().next();
 
         if let Some(stream) = connection {
             match stream {
                 Ok(stream) => {
+                    if broker_for_producers.lock().unwrap().leaving {
+                        log::info!("Broker is shutting down, refusing the connection.");
+                        continue;
+                    }
+
                     if let Ok(read_stream) = stream.try_clone() {
                         connected_producers.lock().unwrap().push(read_stream);
                         match handshake_with_producer(stream, broker_for_producers.clone()) {
                             Ok(()) => {}
                             Err(e) => {
-                                println!(
+                                log::warn!(
                                     "Error while handshaking with connectiong producer: {}",
                                     e
                                 );
@@ -98,44 +237,175 @@ fn main() -> Result<(), Box<dyn Error>> {
                         };
                     }
                 }
-                Err(e) => println!("Error: {}", e),
+                Err(e) => log::warn!("Error: {}", e),
             }
         }
     });
 
-    println!("Initial data on the broker:");
+    let heartbeat_broker = broker.clone();
+
+    // Heartbeats sender, a failed heartbeat means the connection is lost which
+    // is handled by the reader loop below.
+    std::thread::spawn(move || loop {
+        std::thread::sleep(heartbeat_interval);
+
+        if let Err(e) = heartbeat_broker.lock().unwrap().send_heartbeat() {
+            log::warn!("Failed to send a heartbeat to the Observer: {}", e);
+        }
+    });
+
+    let cleanup_broker = broker.clone();
+
+    // Retention enforcer, the topic configurations come along with the cluster metadata
+    std::thread::spawn(move || loop {
+        std::thread::sleep(CLEANUP_INTERVAL);
+
+        cleanup_broker.lock().unwrap().clean_up_partitions();
+    });
 
     for partition in broker_lock.local_metadata.partitions.iter() {
-        println!(
-            "Partition {}: {:#?}",
-            partition.details.replica_id, partition.database
+        log::debug!(
+            "Replica {} of topic `{}` holds {} records",
+            partition.details.replica_id,
+            partition.details.topic.name,
+            partition.log_size()
+        );
+        log::trace!(
+            "Replica {}: {:?}",
+            partition.details.replica_id,
+            partition.database
         );
     }
 
     drop(broker_lock);
 
-    println_c("Initialization complete.", 35);
+    log::info!("Initialization complete.");
 
     let mut reader: BufReader<TcpStream> = BufReader::new(reader_stream);
 
     let mut buf = String::with_capacity(1024);
 
-    // Reader loop
+    // Reader loop, the broker keeps serving producers while it looks for the Observer leader
     loop {
-        let size = reader.read_line(&mut buf).map_err(|e| e.to_string())?;
+        let size = match reader.read_line(&mut buf) {
+            Ok(size) => size,
+            Err(e) => {
+                log::warn!("Observer read stream error: {}", e);
+                0
+            }
+        };
 
         if size == 0 {
-            println!("Connection with observer has been closed, exiting.");
-            break;
+            // The signal handler closes the connection and exits the process
+            if broker.lock().unwrap().leaving {
+                loop {
+                    std::thread::park();
+                }
+            }
+
+            log::warn!(
+                "Connection with observer has been closed, looking for the leader Observer."
+            );
+            reader = reconnect_to_observer(&broker, &observers)?;
+            buf.clear();
+            continue;
         }
+
+        // Observer we are connected to is not the leader, following it to the leader
+        if let Ok(Message::NotLeader { leader_addr }) = MessageDecoder::decode(&buf) {
+            let candidates = match leader_addr {
+                Some(leader_addr) => {
+                    log::info!("Redirected to the leader Observer at {}", leader_addr);
+                    // Falling back to the rest of the Observers in case the leader is gone by now
+                    std::iter::once(leader_addr)
+                        .chain(observers.iter().cloned())
+                        .collect()
+                }
+                None => {
+                    log::info!("Observers have not elected a leader yet, retrying.");
+                    std::thread::sleep(Duration::from_millis(1000));
+                    observers.clone()
+                }
+            };
+
+            reader = reconnect_to_observer(&broker, &candidates)?;
+            buf.clear();
+            continue;
+        }
+
         let mut broker_lock = broker.lock().unwrap();
 
-        broker_lock.handle_raw_message(&buf, None)?;
+        // Messages of the Observer carry no payload. A message that fails to be handled, e.g. metadata
+        // that can't be saved, doesn't stop the broker, only a lost connection makes it look for the leader.
+        if let Err(e) = broker_lock.handle_raw_message(&buf, &[], None) {
+            log::error!("Failed to handle a message of the Observer: {}", e);
+        }
 
         buf.clear();
     }
+}
+
+// Leaves the cluster and closes the broker, returns the exit code of the process
+fn shutdown(broker: &Arc<Mutex<Broker>>) -> i32 {
+    if let Err(e) = broker.lock().unwrap().request_leave() {
+        log::warn!(
+            "Failed to notify the Observer that the broker is leaving: {}",
+            e
+        );
+    }
 
-    Ok(())
+    let deadline = Instant::now() + LEAVE_TIMEOUT;
+
+    // The lock is released in between, the acknowledgement is handled by the reader loop
+    let acknowledged = loop {
+        if broker.lock().unwrap().left {
+            break true;
+        }
+
+        if Instant::now() >= deadline {
+            break false;
+        }
+
+        std::thread::sleep(Duration::from_millis(100));
+    };
+
+    let closed = broker.lock().unwrap().close();
+
+    // Spans of the last requests would be lost with the process otherwise
+    tracing::flush();
+
+    if let Err(e) = closed {
+        log::error!("Failed to close the broker: {}", e);
+        return 1;
+    }
+
+    if !acknowledged {
+        log::error!(
+            "Observer didn't acknowledge the leave within {}s, the leadership of the broker's replicas moves once its session expires.",
+            LEAVE_TIMEOUT.as_secs()
+        );
+        return 1;
+    }
+
+    log::info!("Broker has been shut down.");
+    0
+}
+
+// Connects to one of the `observers` and repeats the handshake on the new connection,
+// the broker lock is only held for the handshake so producers are served in the meantime.
+fn reconnect_to_observer(
+    broker: &Arc<Mutex<Broker>>,
+    observers: &[String],
+) -> Result<BufReader<TcpStream>, String> {
+    loop {
+        let stream = connect_to_observer(observers);
+        let reader_stream = stream.try_clone().map_err(|e| e.to_string())?;
+
+        match broker.lock().unwrap().reconnect(stream) {
+            Ok(()) => return Ok(BufReader::new(reader_stream)),
+            Err(e) => log::warn!("Handshake with the Observer has failed, retrying: {}", e),
+        }
+    }
 }
 
 fn handshake_with_producer(
@@ -149,7 +419,9 @@ fn handshake_with_producer(
         )
     })?;
 
-    println!("Spawning reader: {:#?}", reader_stream);
+    let peer_addr = stream.peer_addr().ok();
+
+    log::debug!("Client {:?} has connected", peer_addr);
 
     std::thread::spawn(move || {
         let mut buf = String::with_capacity(1024);
@@ -159,29 +431,74 @@ fn handshake_with_producer(
             let bytes_read = match reader.read_line(&mut buf) {
                 Ok(b) => b,
                 Err(e) => {
-                    println!("Producer Read Stream Error: {}", e);
+                    log::warn!("Producer Read Stream Error: {}", e);
                     break;
                 }
             };
 
             if bytes_read == 0 {
-                println!("Producer is disconnect");
+                log::info!("Client {:?} has disconnected", peer_addr);
                 break;
             }
 
-            let mut broker_lock = broker.lock().unwrap();
+            // Payload is read before taking the lock, a slow client shouldn't hold up the broker
+            let payload = match MessageDecoder::decode(&buf)
+                .and_then(|message| Reader::read_payload(&mut reader, &message))
+            {
+                Ok(payload) => payload,
+                Err(e) => {
+                    log::warn!("Failed to read message from {:?}: {}", peer_addr, e);
+                    break;
+                }
+            };
 
-            match broker_lock.handle_raw_message(&buf, Some(&mut stream)) {
+            match Broker::handle_client_message(&broker, &buf, &payload, &mut stream) {
                 Ok(_) => {}
                 Err(e) => {
-                    println!("Failed to handle raw message: {}", e);
+                    log::warn!("Failed to handle raw message: {}", e);
                     break;
                 }
             };
 
             buf.clear();
         }
+
+        let mut broker_lock = broker.lock().unwrap();
+
+        if let Some(peer_addr) = peer_addr {
+            broker_lock.remove_subscriptions(peer_addr);
+        }
+
+        broker_lock
+            .connected_producers
+            .lock()
+            .unwrap()
+            .retain(|p| p.peer_addr().ok() != peer_addr);
     });
 
     Ok(())
 }
+
+// Tries to connect to the provided Observers one after another in intervals until success
+fn connect_to_observer(observers: &[String]) -> TcpStream {
+    let mut sleep_interval = 1000;
+
+    loop {
+        for observer in observers {
+            if let Ok(stream) = TcpStream::connect(observer) {
+                log::info!(
+                    "Connection with the Observer {} has been established",
+                    observer
+                );
+                return stream;
+            }
+        }
+
+        log::warn!(
+            "Failed to connect to the Observer, next retry in {}s",
+            Duration::from_millis(sleep_interval).as_secs_f32()
+        );
+        std::thread::sleep(Duration::from_millis(sleep_interval));
+        sleep_interval = (sleep_interval + 1500).min(MAX_RETRY_INTERVAL);
+    }
+}
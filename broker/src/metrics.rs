@@ -0,0 +1,83 @@
+use shared_structures::{
+    metrics::{Metric, MetricKind},
+    Message,
+};
+
+pub const MESSAGES_IN: Metric = Metric {
+    name: "nyx_broker_messages_in_total",
+    help: "Messages stored in a partition replica",
+    kind: MetricKind::Counter,
+};
+
+pub const BYTES_IN: Metric = Metric {
+    name: "nyx_broker_bytes_in_total",
+    help: "Bytes of the messages stored in a partition replica",
+    kind: MetricKind::Counter,
+};
+
+pub const MESSAGES_OUT: Metric = Metric {
+    name: "nyx_broker_messages_out_total",
+    help: "Messages of a partition replica fetched by or pushed to consumers",
+    kind: MetricKind::Counter,
+};
+
+pub const BYTES_OUT: Metric = Metric {
+    name: "nyx_broker_bytes_out_total",
+    help: "Bytes of the messages of a partition replica fetched by or pushed to consumers",
+    kind: MetricKind::Counter,
+};
+
+pub const MESSAGES_REJECTED: Metric = Metric {
+    name: "nyx_broker_messages_rejected_total",
+    help: "Messages refused by a partition replica, e.g. for exceeding the max message size of the topic",
+    kind: MetricKind::Counter,
+};
+
+pub const PARTITION_LOG_SIZE: Metric = Metric {
+    name: "nyx_broker_partition_log_size_records",
+    help: "Records in the log of a partition replica",
+    kind: MetricKind::Gauge,
+};
+
+pub const PARTITION_STORAGE_SIZE: Metric = Metric {
+    name: "nyx_broker_partition_storage_bytes",
+    help: "Size of the storage file of a partition replica",
+    kind: MetricKind::Gauge,
+};
+
+pub const CONNECTED_PRODUCERS: Metric = Metric {
+    name: "nyx_broker_connected_producers",
+    help: "Producers connected to the broker",
+    kind: MetricKind::Gauge,
+};
+
+pub const REQUEST_DURATION: Metric = Metric {
+    name: "nyx_broker_request_duration_seconds",
+    help: "Time taken to handle a request from a producer or the Observer",
+    kind: MetricKind::Histogram,
+};
+
+pub const REQUESTS_FAILED: Metric = Metric {
+    name: "nyx_broker_requests_failed_total",
+    help: "Requests from producers or the Observer that failed",
+    kind: MetricKind::Counter,
+};
+
+/// Label of the request in the request metrics.
+pub fn request_name(message: &Message) -> &'static str {
+    match message {
+        Message::ProducerMessage { .. } => "produce",
+        Message::Fetch { .. } => "fetch",
+        Message::ListOffsets { .. } => "list_offsets",
+        Message::CommitOffset { .. } => "commit_offset",
+        Message::FetchCommittedOffset { .. } => "fetch_committed_offset",
+        Message::Subscribe { .. } => "subscribe",
+        Message::Credit { .. } => "credit",
+        Message::Unsubscribe { .. } => "unsubscribe",
+        Message::CreatePartition { .. } => "create_partition",
+        Message::ClusterMetadata { .. } => "cluster_metadata",
+        Message::RequestClusterMetadata => "request_cluster_metadata",
+        Message::LeaveAcknowledged => "leave_acknowledged",
+        _ => "other",
+    }
+}
@@ -1,44 +1,399 @@
-use std::{fmt::Debug, path::PathBuf};
+use std::{borrow::Cow, collections::HashMap, error::Error, fmt::Debug, fs, path::PathBuf};
 
 use heed::{
-    types::{OwnedType, SerdeJson},
-    Database, Env, EnvOpenOptions,
+    byteorder::BigEndian,
+    types::{OwnedType, Str, U64},
+    BytesDecode, BytesEncode, Database, Env, EnvOpenOptions,
 };
 
-use shared_structures::DirManager;
+use shared_structures::{CleanupPolicy, DirManager, Record};
+
+const STORAGE_DIR: &str = "storage";
+const QUARANTINE_DIR: &str = "quarantine";
+// Kept inside the storage of the replica, so it is moved along when the replica is quarantined
+const TIME_INDEX_DIR: &str = "time_index";
+const COMMITTED_OFFSETS_DIR: &str = "committed_offsets";
+
+// Big endian keys are sorted by their value, which lets timestamps be looked up by range
+type Timestamp = U64<BigEndian>;
+
+// Stores records in their binary encoding, payloads are kept as they were produced.
+// Logs written before records existed hold JSON strings, they are decoded as records
+// of the string without a key, headers or timestamps. Logs written before the binary
+// encoding hold records serialized as JSON objects, which are decoded as they are.
+pub struct RecordCodec;
+
+impl<'a> BytesEncode<'a> for RecordCodec {
+    type EItem = Record;
+
+    fn bytes_encode(record: &'a Record) -> Result<Cow<'a, [u8]>, Box<dyn Error>> {
+        let mut buf = Vec::new();
+        record.encode(&mut buf);
+        Ok(Cow::Owned(buf))
+    }
+}
+
+impl<'a> BytesDecode<'a> for RecordCodec {
+    type DItem = Record;
+
+    fn bytes_decode(bytes: &'a [u8]) -> Result<Record, Box<dyn Error>> {
+        match bytes.first() {
+            Some(b'"') => {
+                let value: String = serde_json::from_slice(bytes)?;
+                Ok(Record {
+                    timestamp: 0,
+                    ..Record::new(value)
+                })
+            }
+            Some(b'{') => Ok(serde_json::from_slice(bytes)?),
+            _ => Ok(Record::decode(bytes)?.0),
+        }
+    }
+}
 
 pub struct DB {
+    // Offset the next record is appended at
     pub length: u64,
+    // Offset of the first record the cleanup hasn't removed
+    pub first_offset: u64,
+    // Amount of records stored, fewer than `length` once records have been cleaned up
+    pub records: u64,
+    // Offset the last cleanup stopped at and the offsets of the keys compaction has kept before it,
+    // the first cleanup after the database is opened goes through the records past retention once.
+    cleaned_up_to: u64,
+    compacted_keys: HashMap<Vec<u8>, u64>,
     pub env: Env,
-    pub db: Database<OwnedType<u128>, SerdeJson<String>>,
+    pub db: Database<OwnedType<u128>, RecordCodec>,
+    // Log append timestamps mapped to the offset of the first record appended at them
+    pub time_index_env: Env,
+    pub time_index: Database<OwnedType<Timestamp>, OwnedType<u64>>,
+    // Log append timestamp of the last record, appends never go back in time even when the clock does
+    pub last_append_timestamp: u64,
+    // Offsets consumer groups resume the replica from, by the name of the group
+    pub committed_offsets_env: Env,
+    pub committed_offsets: Database<Str, OwnedType<u64>>,
 }
 
 impl DB {
-    pub fn with_dir(replica_id: &str, custom_dir: Option<&PathBuf>) -> Result<Self, String> {
-        let storage_dir_path = if let Some(custom_dir) = custom_dir {
-            let mut dir = custom_dir.clone();
-            dir.push("storage");
-            dir
-        } else {
-            "storage".into()
-        };
-        let storage_dir = DirManager::with_dir(Some(&storage_dir_path));
+    pub fn with_dir(replica_id: &str, dir_manager: &DirManager) -> Result<Self, String> {
+        let storage_dir = dir_manager.join(STORAGE_DIR);
         let db_file_name = format!("{}.mdb", replica_id);
         let db_file_path = storage_dir
             .create(&db_file_name)
             .map_err(|e| format!("PartitionDB: {}", e))?;
         let env = EnvOpenOptions::new()
-            .open(db_file_path)
+            .open(&db_file_path)
             .map_err(|e| format!("PartitionDB: {}", e))?;
-        let db: Database<OwnedType<u128>, SerdeJson<String>> = env
+        let db: Database<OwnedType<u128>, RecordCodec> = env
             .create_database(None)
             .map_err(|e| format!("PartitionDB: {}", e))?;
 
+        // Keys aren't sorted by offset as they're stored in native endianness, the bounds of a log
+        // the cleanup has removed records of are found by going through all of its keys.
         let txn = env.read_txn().map_err(|e| e.to_string())?;
-        let length = db.len(&txn).map_err(|e| e.to_string())?;
+        let mut bounds: Option<(u64, u64)> = None;
+        let mut records = 0;
+        for entry in db
+            .iter(&txn)
+            .map_err(|e| e.to_string())?
+            .lazily_decode_data()
+        {
+            let offset = entry.map_err(|e| e.to_string())?.0 as u64;
+            bounds = Some(match bounds {
+                Some((first, last)) => (first.min(offset), last.max(offset)),
+                None => (offset, offset),
+            });
+            records += 1;
+        }
         txn.commit().map_err(|e| e.to_string())?;
+        let (first_offset, length) = bounds
+            .map(|(first, last)| (first, last + 1))
+            .unwrap_or((0, 0));
+
+        let time_index_path = db_file_path.join(TIME_INDEX_DIR);
+        fs::create_dir_all(&time_index_path).map_err(|e| format!("PartitionDB: {}", e))?;
+        let time_index_env = EnvOpenOptions::new()
+            .open(time_index_path)
+            .map_err(|e| format!("PartitionDB: {}", e))?;
+        let time_index = time_index_env
+            .create_database(None)
+            .map_err(|e| format!("PartitionDB: {}", e))?;
+
+        let committed_offsets_path = db_file_path.join(COMMITTED_OFFSETS_DIR);
+        fs::create_dir_all(&committed_offsets_path).map_err(|e| format!("PartitionDB: {}", e))?;
+        let committed_offsets_env = EnvOpenOptions::new()
+            .open(committed_offsets_path)
+            .map_err(|e| format!("PartitionDB: {}", e))?;
+        let committed_offsets = committed_offsets_env
+            .create_database(None)
+            .map_err(|e| format!("PartitionDB: {}", e))?;
+
+        let mut database = Self {
+            db,
+            env,
+            length,
+            first_offset,
+            records,
+            cleaned_up_to: first_offset,
+            compacted_keys: HashMap::new(),
+            time_index_env,
+            time_index,
+            last_append_timestamp: 0,
+            committed_offsets_env,
+            committed_offsets,
+        };
+
+        database.catch_up_time_index()?;
+
+        Ok(database)
+    }
+
+    /// Adds the record appended at `offset` to the time index, unless a record was appended at the same time before.
+    pub fn index(&self, offset: u64, timestamp: u64) -> Result<(), String> {
+        let key = Timestamp::new(timestamp);
+        let mut wtxn = self.time_index_env.write_txn().map_err(|e| e.to_string())?;
+
+        if self
+            .time_index
+            .get(&wtxn, &key)
+            .map_err(|e| e.to_string())?
+            .is_none()
+        {
+            self.time_index
+                .put(&mut wtxn, &key, &offset)
+                .map_err(|e| e.to_string())?;
+        }
+
+        wtxn.commit().map_err(|e| e.to_string())
+    }
+
+    /// Offset of the first record appended at or after `timestamp`, the end of the log if there is none.
+    pub fn offset_for_timestamp(&self, timestamp: u64) -> Result<u64, String> {
+        let rtxn = self.time_index_env.read_txn().map_err(|e| e.to_string())?;
+        let entry = self
+            .time_index
+            .get_greater_than_or_equal_to(&rtxn, &Timestamp::new(timestamp))
+            .map_err(|e| e.to_string())?;
+
+        Ok(entry
+            .map(|(_, offset)| offset.max(self.first_offset))
+            .unwrap_or(self.length))
+    }
+
+    /// Stores `offset` as the offset consumer group `group` resumes the replica from.
+    pub fn commit_offset(&self, group: &str, offset: u64) -> Result<(), String> {
+        let mut wtxn = self
+            .committed_offsets_env
+            .write_txn()
+            .map_err(|e| e.to_string())?;
+        self.committed_offsets
+            .put(&mut wtxn, group, &offset)
+            .map_err(|e| e.to_string())?;
+        wtxn.commit().map_err(|e| e.to_string())
+    }
+
+    /// Offset consumer group `group` has committed last, `None` when it hasn't committed any.
+    pub fn committed_offset(&self, group: &str) -> Result<Option<u64>, String> {
+        let rtxn = self
+            .committed_offsets_env
+            .read_txn()
+            .map_err(|e| e.to_string())?;
+        self.committed_offsets
+            .get(&rtxn, group)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Removes the records appended before `cutoff` as `policy` says, returns the amount of removed records.
+    /// `Delete` removes all of them, `Compact` those which a later record with the same key supersedes and
+    /// the ones without a key. The last record of the log is always kept, the offsets of new records carry on from it.
+    pub fn clean_up(&mut self, policy: CleanupPolicy, cutoff: u64) -> Result<u64, String> {
+        if self.length == 0 {
+            return Ok(0);
+        }
+
+        let mut wtxn = self.env.write_txn().map_err(|e| e.to_string())?;
+        let mut removed = 0;
+
+        // Records from where the last cleanup stopped on, up to the first record within the retention period
+        let start = match policy {
+            CleanupPolicy::Delete => self.first_offset,
+            CleanupPolicy::Compact => self.cleaned_up_to.max(self.first_offset),
+        };
+        let mut end = start;
+
+        for offset in start..self.length - 1 {
+            let Some(record) = self
+                .db
+                .get(&wtxn, &(offset as u128))
+                .map_err(|e| e.to_string())?
+            else {
+                end = offset + 1;
+                continue;
+            };
+
+            // Log append timestamps never go back in time, the records after it are within the retention period
+            if record.log_append_timestamp.unwrap_or(record.timestamp) >= cutoff {
+                break;
+            }
+
+            // Compaction only needs to know the offsets of the keys it has kept, instead of going through the whole log
+            let superseded = match (&policy, record.key) {
+                (CleanupPolicy::Delete, _) | (CleanupPolicy::Compact, None) => Some(offset),
+                (CleanupPolicy::Compact, Some(key)) => self.compacted_keys.insert(key, offset),
+            };
+
+            if let Some(superseded) = superseded {
+                self.db
+                    .delete(&mut wtxn, &(superseded as u128))
+                    .map_err(|e| e.to_string())?;
+                removed += 1;
+            }
+
+            end = offset + 1;
+        }
+
+        let mut first_offset = self.first_offset;
+        while first_offset < end
+            && self
+                .db
+                .get(&wtxn, &(first_offset as u128))
+                .map_err(|e| e.to_string())?
+                .is_none()
+        {
+            first_offset += 1;
+        }
+
+        wtxn.commit().map_err(|e| e.to_string())?;
+
+        self.first_offset = first_offset;
+        self.records -= removed;
+        self.cleaned_up_to = end;
+
+        if policy == CleanupPolicy::Delete {
+            self.compacted_keys.clear();
+        }
+
+        // Entries are pruned once the following entry doesn't point past the first record either,
+        // the timestamps of removed records are then looked up as the first record that is kept.
+        let mut wtxn = self.time_index_env.write_txn().map_err(|e| e.to_string())?;
+        let mut pruned = vec![];
+        let mut entries = self
+            .time_index
+            .iter(&wtxn)
+            .map_err(|e| e.to_string())?
+            .peekable();
+        while let Some(entry) = entries.next() {
+            let (timestamp, _) = entry.map_err(|e| e.to_string())?;
+            match entries.peek() {
+                Some(Ok((_, next_offset))) if *next_offset <= first_offset => {
+                    pruned.push(timestamp)
+                }
+                _ => break,
+            }
+        }
+        drop(entries);
+        for timestamp in pruned {
+            self.time_index
+                .delete(&mut wtxn, &timestamp)
+                .map_err(|e| e.to_string())?;
+        }
+        wtxn.commit().map_err(|e| e.to_string())?;
+
+        Ok(removed)
+    }
+
+    // The index is committed after the record, records of a crash in between or stored before
+    // the index existed are indexed when the database is opened.
+    fn catch_up_time_index(&mut self) -> Result<(), String> {
+        let rtxn = self.time_index_env.read_txn().map_err(|e| e.to_string())?;
+        let last_indexed = self
+            .time_index
+            .last(&rtxn)
+            .map_err(|e| e.to_string())?
+            .map(|(timestamp, offset)| (timestamp.get(), offset));
+        rtxn.commit().map_err(|e| e.to_string())?;
+
+        let (mut last_timestamp, first_unindexed) = match last_indexed {
+            Some((timestamp, offset)) => (Some(timestamp), offset + 1),
+            None => (None, 0),
+        };
+
+        let rtxn = self.env.read_txn().map_err(|e| e.to_string())?;
+
+        for offset in first_unindexed..self.length {
+            let timestamp = self
+                .db
+                .get(&rtxn, &(offset as u128))
+                .map_err(|e| e.to_string())?
+                .and_then(|record| record.log_append_timestamp);
+
+            if let Some(timestamp) = timestamp {
+                if last_timestamp.is_none_or(|last| timestamp > last) {
+                    self.index(offset, timestamp)?;
+                    last_timestamp = Some(timestamp);
+                }
+            }
+        }
+
+        rtxn.commit().map_err(|e| e.to_string())?;
+
+        self.last_append_timestamp = last_timestamp.unwrap_or(0);
+
+        Ok(())
+    }
+
+    /// Size of the database, time index and committed offsets files on disk in bytes.
+    pub fn size(&self) -> Result<u64, String> {
+        [
+            self.env.path(),
+            self.time_index_env.path(),
+            self.committed_offsets_env.path(),
+        ]
+        .iter()
+        .map(|path| {
+            fs::metadata(path.join("data.mdb"))
+                .map(|m| m.len())
+                .map_err(|e| format!("PartitionDB: {}", e))
+        })
+        .sum()
+    }
+
+    /// Flushes the database to disk and closes it, waits until no transaction uses it anymore.
+    pub fn close(self) -> Result<(), String> {
+        for env in [&self.env, &self.time_index_env, &self.committed_offsets_env] {
+            env.force_sync()
+                .map_err(|e| format!("PartitionDB: {}", e))?;
+        }
+        self.committed_offsets_env.prepare_for_closing().wait();
+        self.time_index_env.prepare_for_closing().wait();
+        self.env.prepare_for_closing().wait();
+
+        Ok(())
+    }
+
+    /// Closes the database and moves its files from the storage into the quarantine directory,
+    /// the data of a replica the cluster no longer knows of is kept around for manual recovery.
+    pub fn quarantine(self, replica_id: &str, dir_manager: &DirManager) -> Result<PathBuf, String> {
+        // Waits for the environments to be closed before their files are moved
+        self.committed_offsets_env.prepare_for_closing().wait();
+        self.time_index_env.prepare_for_closing().wait();
+        self.env.prepare_for_closing().wait();
+
+        let db_file_name = format!("{}.mdb", replica_id);
+        let db_file_path = dir_manager
+            .join(STORAGE_DIR)
+            .create(&db_file_name)
+            .map_err(|e| format!("PartitionDB: {}", e))?;
+        let quarantine_file_path = dir_manager
+            .join(QUARANTINE_DIR)
+            .create("")
+            .map_err(|e| format!("PartitionDB: {}", e))?
+            .join(&db_file_name);
+
+        fs::rename(&db_file_path, &quarantine_file_path)
+            .map_err(|e| format!("PartitionDB: {}", e))?;
 
-        Ok(Self { db, env, length })
+        Ok(quarantine_file_path)
     }
 }
 
@@ -47,9 +402,10 @@ impl Debug for DB {
         let txn = self.env.read_txn().unwrap();
         let mut data = String::new();
         for key in 0..self.length {
-            let k = self.db.get(&txn, &(key as u128)).unwrap();
-            if let Some(d) = k {
-                data.push_str(&format!("key: {} | data: {:?}\n", key, d));
+            match self.db.get(&txn, &(key as u128)) {
+                Ok(Some(d)) => data.push_str(&format!("key: {} | data: {:?}\n", key, d)),
+                Ok(None) => {}
+                Err(e) => data.push_str(&format!("key: {} | undecodable: {}\n", key, e)),
             }
         }
         txn.commit().unwrap();
@@ -1,10 +1,31 @@
-use std::path::PathBuf;
+use std::{
+    net::{SocketAddr, TcpStream},
+    path::PathBuf,
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
 
-use shared_structures::{Role, Status, Topic};
+use shared_structures::{
+    record::{self, now_millis},
+    tracing::{Span, SpanKind},
+    DirManager, Message, Metrics, OffsetSpec, Record, Role, Status, Topic,
+};
 
-use crate::partition::db::DB;
+use crate::{metrics, partition::db::DB};
+
+pub use subscription::Subscription;
 
 mod db;
+mod subscription;
+
+// Most bytes of records pushed to a subscriber at once, as counted by `Record::size`
+const MAX_PUSH_BYTES: usize = 1024 * 1024;
+// A subscriber which doesn't read its pushed records in time is dropped
+const PUSH_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+// Most bytes of pushed records queued for a subscriber, a subscriber falling further behind is dropped
+const MAX_QUEUED_PUSH_BYTES: usize = 4 * MAX_PUSH_BYTES;
+// Most records a subscription can have credit for
+pub const MAX_SUBSCRIPTION_CREDIT: usize = 100_000;
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct PartitionDetails {
@@ -17,63 +38,857 @@ pub struct PartitionDetails {
     pub replica_number: usize,
 }
 
+/// Wakes up the fetches waiting for records to be appended to the log of a replica.
+#[derive(Debug, Default)]
+pub struct AppendSignal {
+    appends: Mutex<u64>,
+    condvar: Condvar,
+}
+
+impl AppendSignal {
+    /// Amount of appends signaled so far.
+    pub fn count(&self) -> u64 {
+        *self.appends.lock().unwrap()
+    }
+
+    /// Waits until more than `seen` appends have been signaled or `deadline` has passed.
+    pub fn wait(&self, seen: u64, deadline: Instant) {
+        let mut appends = self.appends.lock().unwrap();
+
+        while *appends <= seen {
+            let now = Instant::now();
+
+            if now >= deadline {
+                break;
+            }
+
+            appends = self
+                .condvar
+                .wait_timeout(appends, deadline - now)
+                .unwrap()
+                .0;
+        }
+    }
+
+    fn notify(&self) {
+        *self.appends.lock().unwrap() += 1;
+        self.condvar.notify_all();
+    }
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct Partition {
     pub details: PartitionDetails,
     #[serde(skip_serializing, skip_deserializing)]
     pub database: Option<DB>,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub appends: Arc<AppendSignal>,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub subscriptions: Vec<Subscription>,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub metrics: Arc<Metrics>,
 }
 
 impl Partition {
-    pub fn from(details: PartitionDetails, custom_dir: Option<&PathBuf>) -> Result<Self, String> {
-        let database = DB::with_dir(&details.replica_id, custom_dir)?;
+    pub fn from(
+        details: PartitionDetails,
+        dir_manager: &DirManager,
+        metrics: &Arc<Metrics>,
+    ) -> Result<Self, String> {
+        let database = DB::with_dir(&details.replica_id, dir_manager)?;
 
-        println!("Database for partition initialized");
+        log::debug!("Database of replica {} initialized", details.replica_id);
 
         Ok(Self {
             details,
             database: Some(database),
+            appends: Arc::default(),
+            subscriptions: vec![],
+            metrics: metrics.clone(),
         })
     }
 
+    /// Removes the replica from the broker's storage, its data is moved into quarantine instead of being deleted.
+    pub fn quarantine(self, dir_manager: &DirManager) -> Result<PathBuf, String> {
+        // Fetches waiting for appends find the replica gone instead of waiting until their deadline
+        self.appends.notify();
+
+        let database = match self.database {
+            Some(database) => database,
+            None => DB::with_dir(&self.details.replica_id, dir_manager)?,
+        };
+
+        database.quarantine(&self.details.replica_id, dir_manager)
+    }
+
+    /// Amount of records in the log of the replica.
+    pub fn log_size(&self) -> u64 {
+        self.database.as_ref().map(|db| db.records).unwrap_or(0)
+    }
+
+    /// Size of the replica's storage on disk in bytes.
+    pub fn storage_size(&self) -> Result<u64, String> {
+        match &self.database {
+            Some(database) => database.size(),
+            None => Ok(0),
+        }
+    }
+
+    /// Closes the database of the replica, used when the broker shuts down.
+    pub fn close(&mut self) -> Result<(), String> {
+        // Fetches waiting for appends find the replica closed instead of waiting until their deadline
+        self.appends.notify();
+
+        match self.database.take() {
+            Some(database) => database.close(),
+            None => Ok(()),
+        }
+    }
+
     // pub fn send_candidacy_for_leadership(&self, observer: &TcpStream) -> Result<()> {}
 
-    pub fn put(&mut self, value: &serde_json::Value) -> Result<(), String> {
-        if let Some(db) = self.database.as_mut() {
-            let mut wtxn = db.env.write_txn().map_err(|s| s.to_string())?;
-            let record = value.to_string();
-            db.db
-                .put(&mut wtxn, &(db.length as u128), &record)
-                .map_err(|s| s.to_string())?;
-            wtxn.commit().map_err(|s| s.to_string())?;
-            db.length += 1;
+    /// Appends the record to the log of the replica and stamps it with the time it was appended at,
+    /// which never precedes the one of the previous record. Returns the offset of the record.
+    pub fn put(&mut self, mut record: Record) -> Result<u64, String> {
+        let db = self.database.as_mut().ok_or(format!(
+            "Replica {} has been closed.",
+            self.details.replica_id
+        ))?;
+
+        let offset = db.length;
+
+        let mut span = Span::start("partition.put", SpanKind::Internal);
+        span.set_attribute("topic", self.details.topic.name.as_str());
+        span.set_attribute("partition", self.details.partition_number);
+        span.set_attribute("replica", self.details.replica_id.as_str());
+        span.set_attribute("offset", offset);
+        span.set_attribute("bytes", record.size());
+
+        let log_append_timestamp = now_millis().max(db.last_append_timestamp);
+        record.log_append_timestamp = Some(log_append_timestamp);
+
+        let mut wtxn = db.env.write_txn().map_err(|s| s.to_string())?;
+        db.db
+            .put(&mut wtxn, &(offset as u128), &record)
+            .map_err(|s| s.to_string())?;
+        wtxn.commit().map_err(|s| s.to_string())?;
+        db.length += 1;
+        db.records += 1;
+        db.last_append_timestamp = log_append_timestamp;
+
+        db.index(offset, log_append_timestamp)?;
+
+        self.appends.notify();
+        self.push_to_subscribers();
+
+        Ok(offset)
+    }
+
+    /// Removes the records past the retention period of the topic as its cleanup policy says,
+    /// `now` is the time in milliseconds the retention period is counted back from.
+    pub fn clean_up(&mut self, now: u64) -> Result<u64, String> {
+        let db = self.database.as_mut().ok_or(format!(
+            "Replica {} has been closed.",
+            self.details.replica_id
+        ))?;
+
+        let config = &self.details.topic.config;
+        let cutoff = now.saturating_sub(config.retention_period.as_millis() as u64);
+
+        db.clean_up(config.cleanup_policy, cutoff)
+    }
+
+    /// Stores `offset` as the offset consumer group `group` resumes the replica from.
+    pub fn commit_offset(&self, group: &str, offset: u64) -> Result<(), String> {
+        let db = self.database.as_ref().ok_or(format!(
+            "Replica {} has been closed.",
+            self.details.replica_id
+        ))?;
+
+        db.commit_offset(group, offset)
+    }
+
+    /// Offset consumer group `group` has committed last, `None` when it hasn't committed any.
+    pub fn committed_offset(&self, group: &str) -> Result<Option<u64>, String> {
+        let db = self.database.as_ref().ok_or(format!(
+            "Replica {} has been closed.",
+            self.details.replica_id
+        ))?;
+
+        db.committed_offset(group)
+    }
+
+    /// Subscribes the connection of `stream` to the replica, replacing its previous subscription.
+    /// Records from `offset` on are pushed right away as far as `credit` allows.
+    pub fn subscribe(
+        &mut self,
+        stream: &TcpStream,
+        write_lock: Arc<Mutex<()>>,
+        offset: u64,
+        credit: usize,
+    ) -> Result<(), String> {
+        check_credit(credit)?;
+
+        let peer_addr = stream.peer_addr().map_err(|e| e.to_string())?;
+        let stream = stream.try_clone().map_err(|e| e.to_string())?;
+        stream
+            .set_write_timeout(Some(PUSH_WRITE_TIMEOUT))
+            .map_err(|e| e.to_string())?;
+
+        self.unsubscribe(peer_addr);
+        self.subscriptions.push(Subscription::new(
+            peer_addr,
+            stream,
+            write_lock,
+            offset,
+            credit,
+            MAX_QUEUED_PUSH_BYTES,
+        ));
+        self.push_to_subscribers();
+
+        Ok(())
+    }
+
+    /// Grants the subscription of the connection at `peer_addr` more credit and pushes the records it was waiting for.
+    pub fn add_credit(&mut self, peer_addr: SocketAddr, credit: usize) -> Result<(), String> {
+        let subscription = self
+            .subscriptions
+            .iter_mut()
+            .find(|s| s.peer_addr == peer_addr)
+            .ok_or(format!(
+                "{} is not subscribed to replica {}.",
+                peer_addr, self.details.replica_id
+            ))?;
+
+        check_credit(subscription.credit.saturating_add(credit))?;
+
+        subscription.credit += credit;
+        self.push_to_subscribers();
+
+        Ok(())
+    }
+
+    /// Removes the subscription of the connection at `peer_addr`, returns whether there was one.
+    pub fn unsubscribe(&mut self, peer_addr: SocketAddr) -> bool {
+        let subscriptions = self.subscriptions.len();
+        self.subscriptions.retain(|s| s.peer_addr != peer_addr);
+        self.subscriptions.len() != subscriptions
+    }
+
+    // Queues the records following their offset for the subscribers with credit left, subscribers
+    // which can't be written to anymore or fall too far behind reading their records are dropped.
+    fn push_to_subscribers(&mut self) {
+        let mut subscriptions = std::mem::take(&mut self.subscriptions);
+
+        subscriptions.retain_mut(|subscription| match self.push(subscription) {
+            Ok(()) => true,
+            Err(e) => {
+                log::warn!(
+                    "Dropping the subscription of {} to replica {}: {}",
+                    subscription.peer_addr,
+                    self.details.replica_id,
+                    e
+                );
+                false
+            }
+        });
+
+        self.subscriptions = subscriptions;
+    }
+
+    fn push(&self, subscription: &mut Subscription) -> Result<(), String> {
+        while subscription.credit > 0 {
+            let records = self.read(subscription.offset, subscription.credit, MAX_PUSH_BYTES)?;
+
+            let Some((last_offset, _)) = records.last() else {
+                break;
+            };
+
+            let batch = record::encode_batch(&records);
+
+            subscription.send(
+                Message::PushedRecords {
+                    replica_id: self.details.replica_id.clone(),
+                    batch_size: batch.len(),
+                },
+                batch,
+            )?;
+
+            subscription.offset = last_offset + 1;
+            subscription.credit -= records.len();
+
+            self.count_out(&records);
         }
+
         Ok(())
     }
+
+    /// Counts `records` as handed out to a consumer in the metrics of the replica.
+    pub fn count_out(&self, records: &[(u64, Record)]) {
+        let partition_number = self.details.partition_number.to_string();
+        let labels = [
+            ("topic", self.details.topic.name.as_str()),
+            ("partition", partition_number.as_str()),
+        ];
+        let bytes: u64 = records.iter().map(|(_, r)| r.size()).sum();
+
+        self.metrics
+            .increment(&metrics::MESSAGES_OUT, &labels, records.len() as f64);
+        self.metrics
+            .increment(&metrics::BYTES_OUT, &labels, bytes as f64);
+    }
+
+    /// Looks up the offset `spec` refers to in the log of the replica,
+    /// timestamps are looked up in the time index of the replica.
+    pub fn list_offset(&self, spec: OffsetSpec) -> Result<u64, String> {
+        let db = self.database.as_ref().ok_or(format!(
+            "Replica {} has been closed.",
+            self.details.replica_id
+        ))?;
+
+        match spec {
+            OffsetSpec::Earliest => Ok(db.first_offset),
+            OffsetSpec::Latest => Ok(db.length),
+            OffsetSpec::Timestamp(timestamp) => db.offset_for_timestamp(timestamp),
+        }
+    }
+
+    /// Reads up to `max_records` records of the log starting at `offset`, along with their offsets.
+    /// Records stop at `max_bytes` as counted by `Record::size`, though the first record is always
+    /// read so a record larger than `max_bytes` doesn't hold up its consumers.
+    pub fn read(
+        &self,
+        offset: u64,
+        max_records: usize,
+        max_bytes: usize,
+    ) -> Result<Vec<(u64, Record)>, String> {
+        let db = self.database.as_ref().ok_or(format!(
+            "Replica {} has been closed.",
+            self.details.replica_id
+        ))?;
+
+        let rtxn = db.env.read_txn().map_err(|s| s.to_string())?;
+        let mut records = vec![];
+        let mut bytes = 0;
+
+        // Offsets of records the cleanup has removed are skipped
+        for offset in offset.max(db.first_offset)..db.length {
+            if records.len() == max_records {
+                break;
+            }
+
+            if let Some(record) = db
+                .db
+                .get(&rtxn, &(offset as u128))
+                .map_err(|s| s.to_string())?
+            {
+                bytes += record.size() as usize;
+
+                if bytes > max_bytes && !records.is_empty() {
+                    break;
+                }
+
+                records.push((offset, record));
+            }
+        }
+
+        Ok(records)
+    }
+}
+
+fn check_credit(credit: usize) -> Result<(), String> {
+    if credit > MAX_SUBSCRIPTION_CREDIT {
+        return Err(format!(
+            "A subscription can't have credit for more than {} records.",
+            MAX_SUBSCRIPTION_CREDIT
+        ));
+    }
+
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
+    use shared_structures::CleanupPolicy;
+
     use super::*;
 
-    #[test]
-    #[cfg_attr(miri, ignore)]
-    fn creates_partition_on_broker() {
-        let topic = Topic::from("notifications".to_string());
-        let custom_dir = PathBuf::from("just_for_test_dir");
+    fn mock_dir_manager(custom_dir: &PathBuf) -> DirManager {
+        DirManager::for_tests(Some(custom_dir))
+    }
 
-        let partition_info = PartitionDetails {
+    fn mock_partition_details(replica_id: &str) -> PartitionDetails {
+        PartitionDetails {
             id: "mocked_partition_id".to_string(),
-            replica_id: "mocked_partition_replica_id".to_string(),
+            replica_id: replica_id.to_string(),
             status: Status::Up,
-            topic,
-            role: Role::Follower,
+            topic: Topic::from("notifications".to_string()),
+            role: Role::Leader,
             partition_number: 1,
             replica_number: 1,
+        }
+    }
+
+    // Every replica gets its own fresh test directory named after it
+    fn mock_partition(replica_id: &str) -> (Partition, DirManager) {
+        let dir_manager = mock_dir_manager(&PathBuf::from(replica_id));
+        let _ = std::fs::remove_dir_all(dir_manager.base_dir().unwrap());
+
+        let partition = Partition::from(
+            mock_partition_details(replica_id),
+            &dir_manager,
+            &Arc::default(),
+        )
+        .unwrap();
+
+        (partition, dir_manager)
+    }
+
+    fn cleanup_partition(mut partition: Partition, dir_manager: &DirManager) {
+        partition.close().unwrap();
+        std::fs::remove_dir_all(dir_manager.base_dir().unwrap()).unwrap();
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn creates_partition_on_broker() {
+        let (partition, dir_manager) = mock_partition("mocked_partition_replica_id");
+
+        assert_eq!(partition.details.id, "mocked_partition_id".to_string());
+
+        cleanup_partition(partition, &dir_manager);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn quarantines_partition_storage() {
+        let (mut partition, dir_manager) = mock_partition("mocked_orphaned_replica_id");
+        partition.put(Record::new("hello world")).unwrap();
+
+        let quarantine_path = partition.quarantine(&dir_manager).unwrap();
+
+        let base_dir = dir_manager.base_dir().unwrap();
+        assert!(quarantine_path.join("data.mdb").exists());
+        assert!(!base_dir
+            .join("storage/mocked_orphaned_replica_id.mdb")
+            .exists());
+
+        std::fs::remove_dir_all(base_dir).unwrap();
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn stores_records_with_their_metadata() {
+        let (mut partition, dir_manager) = mock_partition("mocked_records_replica_id");
+
+        let record = Record::new(vec![0, 1, 2, 255])
+            .with_key("user-42")
+            .with_header("source", "billing");
+
+        assert_eq!(partition.put(record.clone()).unwrap(), 0);
+        assert_eq!(partition.put(Record::new("second")).unwrap(), 1);
+
+        let records = partition.read(0, 10, usize::MAX).unwrap();
+        assert_eq!(records.len(), 2);
+
+        let (offset, stored) = &records[0];
+        assert_eq!(*offset, 0);
+        assert_eq!(stored.key, record.key);
+        assert_eq!(stored.value, record.value);
+        assert_eq!(stored.headers, record.headers);
+        assert_eq!(stored.timestamp, record.timestamp);
+        assert!(stored.log_append_timestamp.unwrap() >= record.timestamp);
+
+        assert_eq!(
+            partition.read(1, 1, usize::MAX).unwrap()[0].1.value,
+            b"second"
+        );
+        assert!(partition.read(2, 10, usize::MAX).unwrap().is_empty());
+
+        // The first record is read even when it exceeds `max_bytes`
+        assert_eq!(partition.read(0, 10, 1).unwrap().len(), 1);
+        assert_eq!(
+            partition
+                .read(0, 10, record.size() as usize + 6)
+                .unwrap()
+                .len(),
+            2
+        );
+
+        cleanup_partition(partition, &dir_manager);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn reads_records_of_logs_in_earlier_formats() {
+        let (mut partition, dir_manager) = mock_partition("mocked_legacy_string_replica_id");
+
+        // Before records existed the log held the produced values as JSON strings,
+        // then records were stored as JSON objects before the binary encoding.
+        {
+            let db = partition.database.as_ref().unwrap();
+            let raw = db.db.remap_data_type::<heed::types::ByteSlice>();
+            let mut wtxn = db.env.write_txn().unwrap();
+            raw.put(&mut wtxn, &0, br#""hello world""#).unwrap();
+            raw.put(
+                &mut wtxn,
+                &1,
+                br#"{"key":"dXNlci00Mg==","value":"aGk=","timestamp":5,"log_append_timestamp":7}"#,
+            )
+            .unwrap();
+            wtxn.commit().unwrap();
+        }
+        partition.close().unwrap();
+
+        let mut partition = Partition::from(
+            mock_partition_details("mocked_legacy_string_replica_id"),
+            &dir_manager,
+            &Arc::default(),
+        )
+        .unwrap();
+        partition.put(Record::new("appended")).unwrap();
+
+        let records = partition.read(0, 10, usize::MAX).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].1.value, b"hello world");
+        assert_eq!(records[0].1.timestamp, 0);
+        assert_eq!(records[0].1.log_append_timestamp, None);
+        assert_eq!(records[1].1.key.as_deref(), Some(&b"user-42"[..]));
+        assert_eq!(records[1].1.value, b"hi");
+        assert_eq!(records[1].1.log_append_timestamp, Some(7));
+        assert_eq!(records[2].1.value, b"appended");
+
+        // JSON records are indexed by the time they were appended at
+        assert_eq!(partition.list_offset(OffsetSpec::Timestamp(0)).unwrap(), 1);
+
+        cleanup_partition(partition, &dir_manager);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn looks_up_offsets_by_timestamp() {
+        let (mut partition, dir_manager) = mock_partition("mocked_time_index_replica_id");
+
+        for value in ["first", "second", "third"] {
+            partition.put(Record::new(value)).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+
+        let appended_at: Vec<_> = partition
+            .read(0, 3, usize::MAX)
+            .unwrap()
+            .into_iter()
+            .map(|(_, r)| r.log_append_timestamp.unwrap())
+            .collect();
+
+        let assert_offsets = |partition: &Partition| {
+            assert_eq!(partition.list_offset(OffsetSpec::Earliest).unwrap(), 0);
+            assert_eq!(partition.list_offset(OffsetSpec::Latest).unwrap(), 3);
+            assert_eq!(partition.list_offset(OffsetSpec::Timestamp(0)).unwrap(), 0);
+            assert_eq!(
+                partition
+                    .list_offset(OffsetSpec::Timestamp(appended_at[1]))
+                    .unwrap(),
+                1
+            );
+            assert_eq!(
+                partition
+                    .list_offset(OffsetSpec::Timestamp(appended_at[1] + 1))
+                    .unwrap(),
+                2
+            );
+            assert_eq!(
+                partition
+                    .list_offset(OffsetSpec::Timestamp(appended_at[2] + 1))
+                    .unwrap(),
+                3
+            );
         };
 
-        let partition = Partition::from(partition_info, Some(&custom_dir)).unwrap();
+        assert_offsets(&partition);
+
+        // A lost time index is rebuilt from the records
+        partition.close().unwrap();
+        let storage_dir = dir_manager
+            .base_dir()
+            .unwrap()
+            .join("storage/mocked_time_index_replica_id.mdb");
+        std::fs::remove_dir_all(storage_dir.join("time_index")).unwrap();
+
+        let partition = Partition::from(
+            mock_partition_details("mocked_time_index_replica_id"),
+            &dir_manager,
+            &Arc::default(),
+        )
+        .unwrap();
+        assert_offsets(&partition);
+
+        cleanup_partition(partition, &dir_manager);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn deletes_records_past_the_retention_period() {
+        let (mut partition, dir_manager) = mock_partition("mocked_retention_replica_id");
+
+        for value in ["first", "second", "third"] {
+            partition.put(Record::new(value)).unwrap();
+        }
+
+        // Nothing is past the retention period of 7 days yet
+        assert_eq!(partition.clean_up(now_millis()).unwrap(), 0);
+
+        // The last record is kept so the offsets carry on from it
+        assert_eq!(partition.clean_up(u64::MAX).unwrap(), 2);
+        let records = partition.read(0, 10, usize::MAX).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].0, 2);
+        assert_eq!(partition.log_size(), 1);
+        assert_eq!(partition.list_offset(OffsetSpec::Earliest).unwrap(), 2);
+        assert_eq!(partition.list_offset(OffsetSpec::Timestamp(0)).unwrap(), 2);
+        assert_eq!(partition.put(Record::new("fourth")).unwrap(), 3);
+
+        partition.close().unwrap();
+        let mut partition = Partition::from(
+            mock_partition_details("mocked_retention_replica_id"),
+            &dir_manager,
+            &Arc::default(),
+        )
+        .unwrap();
+
+        assert_eq!(partition.log_size(), 2);
+        assert_eq!(partition.list_offset(OffsetSpec::Earliest).unwrap(), 2);
+        assert_eq!(partition.put(Record::new("fifth")).unwrap(), 4);
+
+        cleanup_partition(partition, &dir_manager);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn compacts_records_past_the_retention_period_to_the_latest_of_each_key() {
+        let (mut partition, dir_manager) = mock_partition("mocked_compacted_replica_id");
+        partition.details.topic.config.cleanup_policy = CleanupPolicy::Compact;
+
+        for (key, value) in [
+            (Some("user-1"), "v1"),
+            (Some("user-2"), "v1"),
+            (None, "keyless"),
+            (Some("user-1"), "v2"),
+            (Some("user-3"), "v1"),
+            (Some("user-3"), "v2"),
+        ] {
+            let record = Record::new(value);
+            partition
+                .put(match key {
+                    Some(key) => record.with_key(key),
+                    None => record,
+                })
+                .unwrap();
+        }
+
+        assert_eq!(partition.clean_up(now_millis()).unwrap(), 0);
+
+        // The last record is kept, so the first record of `user-3` isn't superseded yet
+        assert_eq!(partition.clean_up(u64::MAX).unwrap(), 2);
+
+        let offsets = |partition: &Partition| -> Vec<u64> {
+            partition
+                .read(0, 10, usize::MAX)
+                .unwrap()
+                .into_iter()
+                .map(|(offset, _)| offset)
+                .collect()
+        };
+        assert_eq!(offsets(&partition), vec![1, 3, 4, 5]);
+        assert_eq!(partition.list_offset(OffsetSpec::Earliest).unwrap(), 1);
+
+        // Reads skip the removed offsets instead of coming back short
+        let records = partition.read(0, 2, usize::MAX).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].1.value, b"v2");
+
+        // Only the records that went past the retention period since are compacted
+        partition.put(Record::new("v2").with_key("user-2")).unwrap();
+        assert_eq!(partition.clean_up(u64::MAX).unwrap(), 1);
+        assert_eq!(offsets(&partition), vec![1, 3, 5, 6]);
+
+        // Compaction picks up where it left off after the database is opened again
+        partition.close().unwrap();
+        let mut partition = Partition::from(
+            mock_partition_details("mocked_compacted_replica_id"),
+            &dir_manager,
+            &Arc::default(),
+        )
+        .unwrap();
+        partition.details.topic.config.cleanup_policy = CleanupPolicy::Compact;
+
+        partition.put(Record::new("v3").with_key("user-3")).unwrap();
+        assert_eq!(partition.clean_up(u64::MAX).unwrap(), 1);
+        assert_eq!(offsets(&partition), vec![3, 5, 6, 7]);
+        assert_eq!(partition.list_offset(OffsetSpec::Earliest).unwrap(), 3);
+
+        cleanup_partition(partition, &dir_manager);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn stores_the_offsets_committed_by_consumer_groups() {
+        let (mut partition, dir_manager) = mock_partition("mocked_committed_offsets_replica_id");
+
+        assert_eq!(partition.committed_offset("billing").unwrap(), None);
+
+        partition.commit_offset("billing", 3).unwrap();
+        partition.commit_offset("billing", 5).unwrap();
+        partition.commit_offset("audit", 1).unwrap();
+
+        partition.close().unwrap();
+        let partition = Partition::from(
+            mock_partition_details("mocked_committed_offsets_replica_id"),
+            &dir_manager,
+            &Arc::default(),
+        )
+        .unwrap();
+
+        assert_eq!(partition.committed_offset("billing").unwrap(), Some(5));
+        assert_eq!(partition.committed_offset("audit").unwrap(), Some(1));
+
+        cleanup_partition(partition, &dir_manager);
+    }
+
+    #[test]
+    fn append_signal_wakes_up_waiting_fetches() {
+        let signal = Arc::new(AppendSignal::default());
+        let seen = signal.count();
+
+        let notifier = signal.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            notifier.notify();
+        });
+
+        let started = Instant::now();
+        signal.wait(seen, started + std::time::Duration::from_secs(5));
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+        assert_eq!(signal.count(), seen + 1);
+
+        // Without appends the wait ends at the deadline
+        let deadline = Instant::now() + std::time::Duration::from_millis(20);
+        signal.wait(signal.count(), deadline);
+        assert!(Instant::now() >= deadline);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn closing_a_replica_wakes_up_waiting_fetches() {
+        let (mut partition, dir_manager) = mock_partition("mocked_closed_replica_id");
+
+        let appends = partition.appends.clone();
+        let seen = appends.count();
+        let waiting = std::thread::spawn(move || {
+            let started = Instant::now();
+            appends.wait(seen, started + std::time::Duration::from_secs(5));
+            started.elapsed()
+        });
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        partition.close().unwrap();
+
+        assert!(waiting.join().unwrap() < std::time::Duration::from_secs(5));
+        assert!(partition.read(0, 1, usize::MAX).is_err());
+
+        cleanup_partition(partition, &dir_manager);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn pushes_records_to_subscribers_within_their_credit() {
+        let (mut partition, dir_manager) = mock_partition("mocked_subscribed_replica_id");
+
+        let listener = std::net::TcpListener::bind("localhost:0").unwrap();
+        let mut subscriber = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (broker_stream, subscriber_addr) = listener.accept().unwrap();
+
+        let mut receive = || {
+            let message = shared_structures::Reader::read_one_message(&mut subscriber).unwrap();
+            let payload =
+                shared_structures::Reader::read_payload(&mut subscriber, &message).unwrap();
+            assert!(matches!(message, Message::PushedRecords { .. }));
+            record::decode_batch(&payload)
+                .unwrap()
+                .into_iter()
+                .map(|(offset, _)| offset)
+                .collect::<Vec<_>>()
+        };
+
+        partition.put(Record::new("first")).unwrap();
+        partition.put(Record::new("second")).unwrap();
+
+        // The backlog is pushed as far as the credit allows
+        partition
+            .subscribe(&broker_stream, Arc::default(), 0, 1)
+            .unwrap();
+        assert_eq!(receive(), vec![0]);
+
+        partition.add_credit(subscriber_addr, 2).unwrap();
+        assert_eq!(receive(), vec![1]);
+
+        // Appended records are pushed as they are committed
+        partition.put(Record::new("third")).unwrap();
+        assert_eq!(receive(), vec![2]);
+
+        // Without credit left nothing is pushed until more is granted
+        partition.put(Record::new("fourth")).unwrap();
+        assert_eq!(partition.subscriptions[0].offset, 3);
+
+        // Credit is limited, however much the subscriber grants
+        assert!(partition.add_credit(subscriber_addr, usize::MAX).is_err());
+
+        assert!(partition.unsubscribe(subscriber_addr));
+        assert!(partition.add_credit(subscriber_addr, 1).is_err());
+
+        cleanup_partition(partition, &dir_manager);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn drops_subscribers_which_fall_behind_without_holding_up_appends() {
+        let (mut partition, dir_manager) = mock_partition("mocked_slow_subscriber_replica_id");
+
+        let listener = std::net::TcpListener::bind("localhost:0").unwrap();
+        let _subscriber = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (broker_stream, subscriber_addr) = listener.accept().unwrap();
+
+        // Nothing can be written to the subscriber while the connection is locked
+        let write_lock = Arc::new(Mutex::new(()));
+        let _writing = write_lock.lock().unwrap();
+
+        let value = vec![0; 1024];
+        let max_queued_bytes = 10 * value.len();
+
+        partition.subscriptions.push(Subscription::new(
+            subscriber_addr,
+            broker_stream,
+            write_lock.clone(),
+            0,
+            MAX_SUBSCRIPTION_CREDIT,
+            max_queued_bytes,
+        ));
+
+        let started = Instant::now();
+
+        for _ in 0..5 {
+            partition.put(Record::new(value.clone())).unwrap();
+        }
+        assert_eq!(partition.subscriptions.len(), 1);
+
+        for _ in 0..5 {
+            partition.put(Record::new(value.clone())).unwrap();
+        }
+
+        // Appends are only queued for the subscriber, they never wait for it to read them
+        assert!(started.elapsed() < PUSH_WRITE_TIMEOUT);
+        assert!(partition.subscriptions.is_empty());
 
-        assert_eq!(partition.details.id, "mocked_partition_id".to_string())
+        cleanup_partition(partition, &dir_manager);
     }
 }
@@ -0,0 +1,123 @@
+use std::{
+    net::{SocketAddr, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+};
+
+use shared_structures::{Broadcast, Message};
+
+/// A connection subscribed to a partition replica, records are pushed to it while it has credit left.
+///
+/// Pushed records are queued and written to the connection by a writer thread of the subscription,
+/// so a slow subscriber never holds up the appends to the replica.
+#[derive(Debug)]
+pub struct Subscription {
+    pub peer_addr: SocketAddr,
+    // Offset of the next record to push
+    pub offset: u64,
+    // Records the subscriber is ready to receive
+    pub credit: usize,
+    queue: Sender<(Message, Vec<u8>)>,
+    queued_bytes: Arc<AtomicUsize>,
+    max_queued_bytes: usize,
+    // Set once the subscription is dropped or its connection can't be written to anymore
+    closed: Arc<AtomicBool>,
+}
+
+impl Subscription {
+    /// Subscribes `stream`, every message written to the connection is written while holding `write_lock`
+    /// so the pushed records never interleave with the responses to the subscriber's requests.
+    /// The subscriber is dropped once more than `max_queued_bytes` of records wait to be written to it.
+    pub fn new(
+        peer_addr: SocketAddr,
+        stream: TcpStream,
+        write_lock: Arc<Mutex<()>>,
+        offset: u64,
+        credit: usize,
+        max_queued_bytes: usize,
+    ) -> Self {
+        let (queue, queued) = mpsc::channel();
+        let queued_bytes = Arc::new(AtomicUsize::new(0));
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let writer_queued_bytes = queued_bytes.clone();
+        let writer_closed = closed.clone();
+        std::thread::spawn(move || {
+            write_pushed_records(
+                stream,
+                write_lock,
+                queued,
+                writer_queued_bytes,
+                writer_closed,
+            )
+        });
+
+        Self {
+            peer_addr,
+            offset,
+            credit,
+            queue,
+            queued_bytes,
+            max_queued_bytes,
+            closed,
+        }
+    }
+
+    /// Queues `message` and its `payload` to be written to the subscriber, fails when the subscriber
+    /// can't be written to anymore or has more than `max_queued_bytes` waiting already.
+    pub fn send(&self, message: Message, payload: Vec<u8>) -> Result<(), String> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err("The connection can't be written to anymore.".to_string());
+        }
+
+        let queued_bytes = self.queued_bytes.load(Ordering::SeqCst);
+
+        if queued_bytes + payload.len() > self.max_queued_bytes {
+            return Err(format!(
+                "More than {} bytes of pushed records are waiting to be written.",
+                self.max_queued_bytes
+            ));
+        }
+
+        self.queued_bytes.fetch_add(payload.len(), Ordering::SeqCst);
+        self.queue
+            .send((message, payload))
+            .map_err(|_| "The connection can't be written to anymore.".to_string())
+    }
+}
+
+impl Drop for Subscription {
+    // Messages still queued are discarded, nothing is pushed once the subscription is gone
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::SeqCst);
+    }
+}
+
+fn write_pushed_records(
+    mut stream: TcpStream,
+    write_lock: Arc<Mutex<()>>,
+    queued: Receiver<(Message, Vec<u8>)>,
+    queued_bytes: Arc<AtomicUsize>,
+    closed: Arc<AtomicBool>,
+) {
+    // Ends once the subscription is dropped and its queue with it
+    for (message, payload) in queued {
+        let _writing = write_lock.lock().unwrap();
+
+        // Checked while holding the lock, an unsubscribed connection gets nothing after its `Unsubscribed`
+        if closed.load(Ordering::SeqCst) {
+            break;
+        }
+
+        if let Err(e) = Broadcast::with_payload(&mut stream, &message, &payload) {
+            log::warn!("Failed to push records to {:?}: {}", stream.peer_addr(), e);
+            closed.store(true, Ordering::SeqCst);
+            break;
+        }
+
+        queued_bytes.fetch_sub(payload.len(), Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,271 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use shared_structures::{
+    metadata::{BrokerDetails, Metadata, PartitionDetails},
+    record, Broadcast, DirManager, Message, MessageDecoder, OffsetSpec, Reader, Record, Role,
+    Status, Topic,
+};
+
+// Kills the broker even when an assertion fails
+struct BrokerProcess(Child);
+
+impl Drop for BrokerProcess {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn free_addr() -> String {
+    let listener = TcpListener::bind("localhost:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+fn produce(client: &mut TcpStream, value: &str) -> Result<u64, String> {
+    let mut payload = vec![];
+    Record::new(value).encode(&mut payload);
+
+    Broadcast::with_payload(
+        client,
+        &Message::ProducerMessage {
+            replica_id: "metrics_replica_id".to_string(),
+            record_size: payload.len(),
+            traceparent: None,
+        },
+        &payload,
+    )
+    .unwrap();
+
+    match Reader::read_one_message(client).unwrap() {
+        Message::ProduceResponse { response } => response,
+        message => panic!("Unexpected response to produce: {:?}", message),
+    }
+}
+
+// Metadata of the cluster with the replica of the test on the broker, along with a replica
+// of the same partition on another broker when it is up
+fn cluster_metadata(broker: (&str, &str), topic: &Topic, other_broker_up: bool) -> Metadata {
+    let replica = |replica_id: &str| PartitionDetails {
+        id: "metrics_partition_id".to_string(),
+        replica_id: replica_id.to_string(),
+        role: Role::Leader,
+        topic: topic.clone(),
+        partition_number: 1,
+        replica_count: 1,
+    };
+
+    let mut brokers = vec![BrokerDetails {
+        id: broker.0.to_string(),
+        addr: broker.1.to_string(),
+        rack: None,
+        status: Status::Up,
+        partitions: vec![replica("metrics_replica_id")],
+    }];
+
+    if other_broker_up {
+        brokers.push(BrokerDetails {
+            id: "other_broker_id".to_string(),
+            addr: "localhost:1".to_string(),
+            rack: None,
+            status: Status::Up,
+            partitions: vec![replica("other_replica_id")],
+        });
+    }
+
+    Metadata {
+        brokers,
+        topics: vec![topic.clone()],
+    }
+}
+
+// Produces until the broker answers as `accepted` says, the broker applies the cluster metadata on its own time
+fn produce_until(client: &mut TcpStream, accepted: impl Fn(&Result<u64, String>) -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+
+    while !accepted(&produce(client, "waiting")) {
+        assert!(
+            Instant::now() < deadline,
+            "The broker never applied the metadata"
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn scrape(addr: &str) -> String {
+    let deadline = Instant::now() + Duration::from_secs(10);
+
+    loop {
+        if let Ok(mut stream) = TcpStream::connect(addr) {
+            write!(stream, "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            return response;
+        }
+
+        assert!(
+            Instant::now() < deadline,
+            "Metrics are not served on {}",
+            addr
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn scrapes_the_messages_in_and_out_of_a_partition() {
+    let data_dir = DirManager::test_root().join(format!("broker_metrics_{}", uuid::Uuid::new_v4()));
+    let metrics_addr = free_addr();
+
+    // Stands in for the Observer
+    let observer = TcpListener::bind("localhost:0").unwrap();
+
+    let broker = BrokerProcess(
+        Command::new(env!("CARGO_BIN_EXE_broker"))
+            .arg(observer.local_addr().unwrap().to_string())
+            .arg("--data-dir")
+            .arg(&data_dir)
+            .arg("--metrics-addr")
+            .arg(&metrics_addr)
+            .arg("--log-level")
+            .arg("off")
+            .stdin(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+
+    let (mut observer_stream, _) = observer.accept().unwrap();
+    let mut observer_reader = BufReader::new(observer_stream.try_clone().unwrap());
+    let mut line = String::new();
+
+    let (broker_id, broker_addr) = loop {
+        line.clear();
+        observer_reader.read_line(&mut line).unwrap();
+
+        if let Ok(Message::BrokerConnectionDetails { id, addr, .. }) = MessageDecoder::decode(&line)
+        {
+            break (id, addr);
+        }
+    };
+
+    let mut topic = Topic::from("notifications".to_string());
+    topic.config.max_message_size = 64;
+
+    Broadcast::to(
+        &mut observer_stream,
+        &Message::CreatePartition {
+            id: "metrics_partition_id".to_string(),
+            replica_id: "metrics_replica_id".to_string(),
+            topic: topic.clone(),
+            partition_number: 1,
+            replica_count: 1,
+            traceparent: None,
+        },
+    )
+    .unwrap();
+
+    let mut client = TcpStream::connect(&broker_addr).unwrap();
+
+    // The replica may not have been created yet
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        Broadcast::to(
+            &mut client,
+            &Message::ListOffsets {
+                replica_id: "metrics_replica_id".to_string(),
+                spec: OffsetSpec::Latest,
+            },
+        )
+        .unwrap();
+
+        if let Message::ListOffsetsResponse { response: Ok(_) } =
+            Reader::read_one_message(&mut client).unwrap()
+        {
+            break;
+        }
+
+        assert!(Instant::now() < deadline, "The replica was never created");
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    assert_eq!(produce(&mut client, "first"), Ok(0));
+    assert_eq!(produce(&mut client, "second"), Ok(1));
+
+    // A rejected record is answered, the connection stays open
+    assert!(produce(&mut client, &"x".repeat(100))
+        .unwrap_err()
+        .contains("exceeds the max message size"));
+
+    Broadcast::to(
+        &mut client,
+        &Message::Fetch {
+            replica_id: "metrics_replica_id".to_string(),
+            offset: 0,
+            max_records: 10,
+            min_bytes: 11,
+            max_bytes: 1024,
+            max_wait_ms: 5000,
+            traceparent: None,
+        },
+    )
+    .unwrap();
+
+    let response = Reader::read_one_message(&mut client).unwrap();
+    let batch = Reader::read_payload(&mut client, &response).unwrap();
+    assert_eq!(record::decode_batch(&batch).unwrap().len(), 2);
+
+    let response = scrape(&metrics_addr);
+    let labels = r#"{partition="1",topic="notifications"}"#;
+
+    for expected in [
+        format!("nyx_broker_messages_in_total{} 2\n", labels),
+        format!("nyx_broker_bytes_in_total{} 11\n", labels),
+        format!("nyx_broker_messages_out_total{} 2\n", labels),
+        format!("nyx_broker_bytes_out_total{} 11\n", labels),
+        format!("nyx_broker_partition_log_size_records{} 2\n", labels),
+        format!("nyx_broker_messages_rejected_total{} 1\n", labels),
+        "nyx_broker_request_duration_seconds_count{request=\"produce\"} 3\n".to_string(),
+    ] {
+        assert!(
+            response.contains(&expected),
+            "{} is missing in {}",
+            expected,
+            response
+        );
+    }
+
+    // Records are refused while fewer replicas than `min_insync_replicas` are up
+    topic.config.replica_factor = 2;
+    topic.config.min_insync_replicas = 2;
+
+    Broadcast::to(
+        &mut observer_stream,
+        &Message::ClusterMetadata {
+            metadata: cluster_metadata((&broker_id, &broker_addr), &topic, false),
+        },
+    )
+    .unwrap();
+    produce_until(&mut client, |response| {
+        response
+            .as_ref()
+            .is_err_and(|e| e.contains("in-sync replicas"))
+    });
+
+    Broadcast::to(
+        &mut observer_stream,
+        &Message::ClusterMetadata {
+            metadata: cluster_metadata((&broker_id, &broker_addr), &topic, true),
+        },
+    )
+    .unwrap();
+    produce_until(&mut client, |response| response.is_ok());
+
+    drop(broker);
+    let _ = std::fs::remove_dir_all(data_dir);
+}
@@ -0,0 +1,472 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    net::TcpStream,
+    time::Duration,
+};
+
+use producer::Producer;
+use shared_structures::{
+    metadata::BrokerDetails,
+    record,
+    tracing::{Span, SpanKind},
+    Broadcast, Message, OffsetSpec, Reader, Record, Role, TopicConfig,
+};
+
+pub const DEFAULT_FETCH_MIN_BYTES: usize = 1;
+pub const DEFAULT_FETCH_MAX_BYTES: usize = 1024 * 1024;
+pub const DEFAULT_FETCH_MAX_WAIT: Duration = Duration::from_millis(500);
+
+// Headers added to the records routed to the dead-letter topic
+pub const DEAD_LETTER_TOPIC_HEADER: &str = "dlq.original.topic";
+pub const DEAD_LETTER_PARTITION_HEADER: &str = "dlq.original.partition";
+pub const DEAD_LETTER_OFFSET_HEADER: &str = "dlq.original.offset";
+pub const DEAD_LETTER_REASON_HEADER: &str = "dlq.reason";
+pub const DEAD_LETTER_REDELIVERIES_HEADER: &str = "dlq.redeliveries";
+
+/// A record fetched from the partition along with its offset in the partition's log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsumerRecord {
+    pub offset: u64,
+    pub record: Record,
+}
+
+pub struct Consumer {
+    pub broker_details: BrokerDetails,
+    pub stream: TcpStream,
+    pub topic: String,
+    pub partition_number: usize,
+    pub source_replica_id: String,
+    // Offset of the next record to fetch
+    pub offset: u64,
+    // A poll waits up to `fetch_max_wait` for `fetch_min_bytes` of records to be available,
+    // the records of a poll add up to at most `fetch_max_bytes` as counted by `Record::size`.
+    pub fetch_min_bytes: usize,
+    pub fetch_max_bytes: usize,
+    pub fetch_max_wait: Duration,
+    // Configuration of the topic when the consumer connected, holds its redelivery and dead-letter settings
+    pub topic_config: TopicConfig,
+    // Consumer group the consumer commits its offset for, set by `join_group`
+    pub group: Option<String>,
+    brokers: String,
+    // Negatively acknowledged records waiting to be redelivered, handed out before any new record
+    redeliveries: VecDeque<ConsumerRecord>,
+    // Times the records at the offsets have been negatively acknowledged, until they're acknowledged
+    // or dead-lettered. Commits don't move past these records so they're redelivered after a restart.
+    nacks: HashMap<u64, usize>,
+    dead_letter_producer: Option<Producer>,
+}
+
+impl Consumer {
+    /// Connects to the broker holding the leader replica of the partition, or its first replica
+    /// while it has no leader as producers do, consuming from the start of the partition.
+    pub fn from(brokers: &str, topic: &str, partition_number: usize) -> Result<Self, String> {
+        let broker_addrs: Vec<_> = brokers
+            .split_terminator(',')
+            .map(|b| b.to_string())
+            .collect();
+
+        if broker_addrs.is_empty() {
+            return Err("No brokers were provided".to_string());
+        }
+
+        // Any broker knows the cluster metadata, it tells which one holds the leader replica
+        let mut stream = TcpStream::connect(&broker_addrs[0]).map_err(|e| e.to_string())?;
+
+        Broadcast::to(&mut stream, &Message::RequestClusterMetadata)?;
+
+        let message = Reader::read_one_message(&mut stream)?;
+
+        match message {
+            Message::ClusterMetadata {
+                metadata: cluster_metadata,
+            } => {
+                let replicas: Vec<_> = cluster_metadata
+                    .brokers
+                    .iter()
+                    .flat_map(|b| b.partitions.iter().map(move |p| (b, p)))
+                    .filter(|(_, p)| {
+                        p.topic.name == topic && p.partition_number == partition_number
+                    })
+                    .collect();
+
+                let &(broker_details, partition_details) = replicas
+                    .iter()
+                    .find(|(_, p)| p.role == Role::Leader)
+                    .or(replicas.first())
+                    .ok_or(format!(
+                        "Partition {} of topic `{}` has not been found.",
+                        partition_number, topic
+                    ))?;
+
+                let peer_addr = stream.peer_addr().map_err(|e| format!("Consumer: {}", e))?;
+
+                let stream = if peer_addr.to_string() == broker_details.addr {
+                    stream
+                } else {
+                    TcpStream::connect(&broker_details.addr).map_err(|e| e.to_string())?
+                };
+
+                log::debug!(
+                    "Connected to broker {} at {}",
+                    broker_details.id,
+                    broker_details.addr
+                );
+
+                Ok(Self {
+                    broker_details: broker_details.clone(),
+                    stream,
+                    topic: topic.to_string(),
+                    partition_number,
+                    source_replica_id: partition_details.replica_id.clone(),
+                    offset: 0,
+                    fetch_min_bytes: DEFAULT_FETCH_MIN_BYTES,
+                    fetch_max_bytes: DEFAULT_FETCH_MAX_BYTES,
+                    fetch_max_wait: DEFAULT_FETCH_MAX_WAIT,
+                    topic_config: partition_details.topic.config.clone(),
+                    group: None,
+                    brokers: brokers.to_string(),
+                    redeliveries: VecDeque::new(),
+                    nacks: HashMap::new(),
+                    dead_letter_producer: None,
+                })
+            }
+            _ => Err("Wrong message received on handshake".to_string()),
+        }
+    }
+
+    /// Fetches up to `max_records` records following the last polled one, the broker holds the poll
+    /// until `fetch_min_bytes` are available. An empty result means no records arrived within `fetch_max_wait`.
+    /// Records waiting to be redelivered are returned first, without fetching.
+    pub fn poll(&mut self, max_records: usize) -> Result<Vec<ConsumerRecord>, String> {
+        if !self.redeliveries.is_empty() {
+            let count = max_records.min(self.redeliveries.len());
+            return Ok(self.redeliveries.drain(..count).collect());
+        }
+
+        let mut span = Span::start("consumer poll", SpanKind::Client);
+        span.set_attribute("topic", self.topic.as_str());
+        span.set_attribute("replica", self.source_replica_id.as_str());
+        span.set_attribute("offset", self.offset);
+
+        let result = self.fetch(max_records, span.context());
+
+        match &result {
+            Ok(records) => span.set_attribute("records", records.len()),
+            Err(e) => span.set_error(e),
+        }
+
+        result
+    }
+
+    /// Moves the consumer to `offset`, the next poll starts from the record at it.
+    pub fn seek(&mut self, offset: u64) {
+        self.offset = offset;
+    }
+
+    /// Moves the consumer to the first record of the partition.
+    pub fn seek_to_beginning(&mut self) -> Result<(), String> {
+        self.offset = self.list_offset(OffsetSpec::Earliest)?;
+        Ok(())
+    }
+
+    /// Moves the consumer past the last record of the partition, only records appended from now on are polled.
+    pub fn seek_to_end(&mut self) -> Result<(), String> {
+        self.offset = self.list_offset(OffsetSpec::Latest)?;
+        Ok(())
+    }
+
+    /// Moves the consumer to the first record appended to the partition at or after `timestamp`,
+    /// in milliseconds since the unix epoch, or to the end of the partition if there is none.
+    pub fn seek_to_timestamp(&mut self, timestamp: u64) -> Result<(), String> {
+        self.offset = self.list_offset(OffsetSpec::Timestamp(timestamp))?;
+        Ok(())
+    }
+
+    /// Joins consumer group `group` and moves the consumer to the offset the group has committed.
+    /// Returns the committed offset, `None` while the group hasn't committed any and the consumer stays where it is.
+    pub fn join_group(&mut self, group: &str) -> Result<Option<u64>, String> {
+        Broadcast::to(
+            &mut self.stream,
+            &Message::FetchCommittedOffset {
+                replica_id: self.source_replica_id.clone(),
+                group: group.to_string(),
+            },
+        )?;
+
+        let committed_offset = match Reader::read_one_message(&mut self.stream)? {
+            Message::FetchCommittedOffsetResponse { response } => response?,
+            message => {
+                return Err(format!(
+                    "Unexpected response to fetch committed offset: {:?}",
+                    message
+                ))
+            }
+        };
+
+        self.group = Some(group.to_string());
+
+        if let Some(offset) = committed_offset {
+            self.offset = offset;
+        }
+
+        Ok(committed_offset)
+    }
+
+    /// Commits the offset the group of the consumer resumes from: the offset of the next record to fetch,
+    /// or the first negatively acknowledged record that hasn't been acknowledged or dead-lettered since.
+    /// Every other record handed out before the commit counts as processed. Returns the committed offset.
+    pub fn commit(&mut self) -> Result<u64, String> {
+        let group = self
+            .group
+            .clone()
+            .ok_or("The consumer has to join a group before committing its offset.")?;
+
+        let offset = self.nacks.keys().copied().fold(self.offset, u64::min);
+
+        Broadcast::to(
+            &mut self.stream,
+            &Message::CommitOffset {
+                replica_id: self.source_replica_id.clone(),
+                group,
+                offset,
+            },
+        )?;
+
+        match Reader::read_one_message(&mut self.stream)? {
+            Message::CommitOffsetResponse { response } => response.map(|()| offset),
+            message => Err(format!(
+                "Unexpected response to commit offset: {:?}",
+                message
+            )),
+        }
+    }
+
+    /// Subscribes to the partition from the offset of the consumer, the broker pushes records as soon as
+    /// they are appended as long as `credit` records are left. Pushed records are read with [`Consumer::receive`],
+    /// the consumer shouldn't poll while subscribed.
+    pub fn subscribe(&mut self, credit: usize) -> Result<(), String> {
+        Broadcast::to(
+            &mut self.stream,
+            &Message::Subscribe {
+                replica_id: self.source_replica_id.clone(),
+                offset: self.offset,
+                credit,
+            },
+        )?;
+
+        match Reader::read_one_message(&mut self.stream)? {
+            Message::SubscribeResponse { response } => response,
+            message => Err(format!("Unexpected response to subscribe: {:?}", message)),
+        }
+    }
+
+    /// Waits for the next records pushed to the subscription, each of them uses up one record of credit.
+    /// Records waiting to be redelivered are returned first, they don't use up any credit.
+    pub fn receive(&mut self) -> Result<Vec<ConsumerRecord>, String> {
+        if !self.redeliveries.is_empty() {
+            return Ok(self.redeliveries.drain(..).collect());
+        }
+
+        let message = Reader::read_one_message(&mut self.stream)?;
+        let payload = Reader::read_payload(&mut self.stream, &message)?;
+
+        match message {
+            Message::PushedRecords { .. } => self.decode_records(&payload),
+            _ => Err(format!(
+                "Unexpected message while subscribed: {:?}",
+                message
+            )),
+        }
+    }
+
+    /// Lets the broker push `credit` more records to the subscription.
+    pub fn grant_credit(&mut self, credit: usize) -> Result<(), String> {
+        Broadcast::to(
+            &mut self.stream,
+            &Message::Credit {
+                replica_id: self.source_replica_id.clone(),
+                credit,
+            },
+        )
+    }
+
+    /// Ends the subscription, records pushed before the broker acknowledged it are discarded
+    /// and will be polled or pushed again from the offset of the consumer.
+    pub fn unsubscribe(&mut self) -> Result<(), String> {
+        Broadcast::to(
+            &mut self.stream,
+            &Message::Unsubscribe {
+                replica_id: self.source_replica_id.clone(),
+            },
+        )?;
+
+        loop {
+            let message = Reader::read_one_message(&mut self.stream)?;
+            Reader::read_payload(&mut self.stream, &message)?;
+
+            match message {
+                Message::Unsubscribed { .. } => return Ok(()),
+                Message::PushedRecords { .. } => {}
+                _ => return Err(format!("Unexpected response to unsubscribe: {:?}", message)),
+            }
+        }
+    }
+
+    /// Acknowledges the processing of a record, forgetting the times it has been negatively acknowledged.
+    pub fn ack(&mut self, consumer_record: &ConsumerRecord) {
+        self.nacks.remove(&consumer_record.offset);
+    }
+
+    /// Negatively acknowledges a record which failed to be processed, it is redelivered by the next poll
+    /// up to `max_redeliveries` times of the topic. After that it is routed to the `dead_letter_topic`
+    /// of the topic with headers describing where it came from and why, or dropped without one.
+    /// A record that fails to be routed is redelivered once more, the next nack routes it again.
+    pub fn nack(&mut self, consumer_record: &ConsumerRecord, reason: &str) -> Result<(), String> {
+        let nacks = self.nacks.entry(consumer_record.offset).or_insert(0);
+        *nacks += 1;
+
+        if *nacks <= self.topic_config.max_redeliveries {
+            self.redeliveries.push_back(consumer_record.clone());
+            return Ok(());
+        }
+
+        if let Err(e) = self.dead_letter(consumer_record, reason) {
+            self.redeliveries.push_back(consumer_record.clone());
+            return Err(format!(
+                "Record {} of topic `{}` couldn't be dead-lettered and will be redelivered: {}",
+                consumer_record.offset, self.topic, e
+            ));
+        }
+
+        self.nacks.remove(&consumer_record.offset);
+
+        Ok(())
+    }
+
+    // Routes a record out of redeliveries to the dead-letter topic once the broker has appended it, or drops it without one
+    fn dead_letter(
+        &mut self,
+        consumer_record: &ConsumerRecord,
+        reason: &str,
+    ) -> Result<(), String> {
+        let Some(dead_letter_topic) = self.topic_config.dead_letter_topic.clone() else {
+            log::warn!(
+                "Record {} of topic `{}` has run out of redeliveries and is dropped: {}",
+                consumer_record.offset,
+                self.topic,
+                reason
+            );
+            return Ok(());
+        };
+
+        let dead_letter = self.dead_letter_record(consumer_record, reason);
+
+        let producer = match &mut self.dead_letter_producer {
+            Some(producer) => producer,
+            None => self
+                .dead_letter_producer
+                .insert(Producer::from(&self.brokers, &dead_letter_topic)?),
+        };
+
+        // Returns once the broker has appended the record, a refused record, e.g. one that exceeds the
+        // `max_message_size` of the dead-letter topic with its headers, fails like a lost connection
+        if let Err(e) = producer.produce(dead_letter) {
+            // Connecting anew on the next attempt, the broker may have gone away
+            self.dead_letter_producer = None;
+            return Err(e);
+        }
+
+        log::debug!(
+            "Record {} of topic `{}` has been routed to the dead-letter topic `{}`",
+            consumer_record.offset,
+            self.topic,
+            dead_letter_topic
+        );
+
+        Ok(())
+    }
+
+    fn dead_letter_record(&self, consumer_record: &ConsumerRecord, reason: &str) -> Record {
+        let mut record = consumer_record
+            .record
+            .clone()
+            .with_header(DEAD_LETTER_TOPIC_HEADER, self.topic.as_str())
+            .with_header(
+                DEAD_LETTER_PARTITION_HEADER,
+                self.partition_number.to_string(),
+            )
+            .with_header(
+                DEAD_LETTER_OFFSET_HEADER,
+                consumer_record.offset.to_string(),
+            )
+            .with_header(DEAD_LETTER_REASON_HEADER, reason)
+            .with_header(
+                DEAD_LETTER_REDELIVERIES_HEADER,
+                self.topic_config.max_redeliveries.to_string(),
+            );
+
+        // Stamped again once appended to the dead-letter topic
+        record.log_append_timestamp = None;
+        record
+    }
+
+    fn list_offset(&mut self, spec: OffsetSpec) -> Result<u64, String> {
+        Broadcast::to(
+            &mut self.stream,
+            &Message::ListOffsets {
+                replica_id: self.source_replica_id.clone(),
+                spec,
+            },
+        )?;
+
+        match Reader::read_one_message(&mut self.stream)? {
+            Message::ListOffsetsResponse { response } => response,
+            message => Err(format!(
+                "Unexpected response to list offsets: {:?}",
+                message
+            )),
+        }
+    }
+
+    fn fetch(
+        &mut self,
+        max_records: usize,
+        traceparent: Option<shared_structures::TraceContext>,
+    ) -> Result<Vec<ConsumerRecord>, String> {
+        Broadcast::to(
+            &mut self.stream,
+            &Message::Fetch {
+                replica_id: self.source_replica_id.clone(),
+                offset: self.offset,
+                max_records,
+                min_bytes: self.fetch_min_bytes,
+                max_bytes: self.fetch_max_bytes,
+                max_wait_ms: self.fetch_max_wait.as_millis() as u64,
+                traceparent,
+            },
+        )?;
+
+        let message = Reader::read_one_message(&mut self.stream)?;
+        let payload = Reader::read_payload(&mut self.stream, &message)?;
+
+        match message {
+            Message::FetchResponse { response: Ok(_) } => self.decode_records(&payload),
+            Message::FetchResponse { response: Err(e) } => Err(e),
+            _ => Err(format!("Unexpected response to fetch: {:?}", message)),
+        }
+    }
+
+    // Decodes a batch of fetched or pushed records and moves the consumer past them
+    fn decode_records(&mut self, batch: &[u8]) -> Result<Vec<ConsumerRecord>, String> {
+        let records: Vec<_> = record::decode_batch(batch)?
+            .into_iter()
+            .map(|(offset, record)| ConsumerRecord { offset, record })
+            .collect();
+
+        if let Some(last) = records.last() {
+            self.offset = last.offset + 1;
+        }
+
+        Ok(records)
+    }
+}
@@ -1,3 +1,113 @@
-fn main() {
-    println!("Hello, world!");
+use std::time::Duration;
+
+use clap::{arg, command, value_parser};
+use consumer::Consumer;
+use shared_structures::{logger, tracing};
+
+fn main() -> Result<(), String> {
+    let matches = command!()
+        .arg(arg!(-b --brokers <BROKERS> "List of brokers to connect to seperated by comma e.g. localhost:3000,localhost:4000,...").required(true))
+        .arg(arg!(-t --topic <TOPIC> "The name of the topic from which the consumer is going to read messages").required(true))
+        .arg(arg!(-p --partition <PARTITION> "Number of the partition to consume, partitions are numbered from 1").required(false).value_parser(value_parser!(usize)).default_value("1"))
+        .arg(arg!(-o --offset <OFFSET> "Offset of the first record to consume").required(false).value_parser(value_parser!(u64)).conflicts_with("start"))
+        .arg(arg!(-s --start <START> "Where to start consuming: `beginning`, `end`, or a timestamp in milliseconds since the unix epoch, defaults to `beginning`").required(false))
+        .arg(arg!(-g --group <GROUP> "Consumer group to commit the consumed offset for, the consumer resumes from the offset the group has committed. `--offset` and `--start` apply while the group hasn't committed any").required(false))
+        .arg(arg!(--"fetch-min-bytes" <BYTES> "Bytes of records the broker waits for before answering a poll, defaults to 1").required(false).value_parser(value_parser!(usize)))
+        .arg(arg!(--"fetch-max-bytes" <BYTES> "Most bytes of records returned by a poll, a larger record is still returned on its own, defaults to 1MiB").required(false).value_parser(value_parser!(usize)))
+        .arg(arg!(--"fetch-max-wait-ms" <MS> "Longest time the broker waits for `--fetch-min-bytes` before answering a poll, defaults to 500").required(false).value_parser(value_parser!(u64)))
+        .arg(arg!(--push "Subscribes to the partition, the broker pushes records as they are appended instead of being polled").required(false))
+        .arg(arg!(--credit <RECORDS> "Records the broker may push ahead of the consumer with `--push`, defaults to 100").required(false).value_parser(value_parser!(usize)))
+        .arg(arg!(--"trace-exporter" <DEST> "OTLP/HTTP endpoint, e.g. http://localhost:4318, or file the trace spans of the polls are exported to.").required(false))
+        .arg(arg!(--"log-level" <LEVEL> "Most verbose level of the logs written to stderr.").required(false).value_parser(logger::LEVELS).default_value("info"))
+        .arg(arg!(--"log-format" <FORMAT> "Format of the logs, `json` writes one object per line for log collectors.").required(false).value_parser(logger::FORMATS).default_value("text"))
+        .get_matches();
+
+    logger::init(
+        matches.get_one::<String>("log-level").unwrap(),
+        matches.get_one::<String>("log-format").unwrap(),
+    )?;
+
+    if let Some(trace_exporter) = matches.get_one::<String>("trace-exporter") {
+        tracing::init("consumer", trace_exporter)?;
+    }
+
+    let brokers = matches.get_one::<String>("brokers").unwrap();
+    let topic = matches.get_one::<String>("topic").unwrap();
+    let partition = *matches.get_one::<usize>("partition").unwrap();
+
+    let mut consumer = Consumer::from(brokers, topic, partition)?;
+
+    if let Some(min_bytes) = matches.get_one::<usize>("fetch-min-bytes") {
+        consumer.fetch_min_bytes = *min_bytes;
+    }
+
+    if let Some(max_bytes) = matches.get_one::<usize>("fetch-max-bytes") {
+        consumer.fetch_max_bytes = *max_bytes;
+    }
+
+    if let Some(max_wait_ms) = matches.get_one::<u64>("fetch-max-wait-ms") {
+        consumer.fetch_max_wait = Duration::from_millis(*max_wait_ms);
+    }
+
+    match (
+        matches.get_one::<u64>("offset"),
+        matches.get_one::<String>("start").map(|s| s.as_str()),
+    ) {
+        (Some(offset), _) => consumer.seek(*offset),
+        (None, None | Some("beginning")) => consumer.seek_to_beginning()?,
+        (None, Some("end")) => consumer.seek_to_end()?,
+        (None, Some(timestamp)) => {
+            consumer.seek_to_timestamp(timestamp.parse().map_err(|_| {
+                format!(
+                    "Start `{}` is neither `beginning`, `end` nor a timestamp",
+                    timestamp
+                )
+            })?)?
+        }
+    }
+
+    let group = matches.get_one::<String>("group");
+
+    if let Some(group) = group {
+        if let Some(offset) = consumer.join_group(group)? {
+            log::info!("Resuming group `{}` from offset {}", group, offset);
+        }
+    }
+
+    log::debug!("Consuming from offset {}", consumer.offset);
+
+    log::debug!("Broker details: {:?}", consumer.broker_details);
+
+    let credit = matches.get_one::<usize>("credit").copied().unwrap_or(100);
+    let push = matches.get_flag("push");
+
+    if push {
+        consumer.subscribe(credit)?;
+    }
+
+    loop {
+        // Polls wait on the broker for new records, no need to pause in between
+        let records = if push {
+            let records = consumer.receive()?;
+            // Keeping the same amount of records in flight
+            consumer.grant_credit(records.len())?;
+            records
+        } else {
+            consumer.poll(100)?
+        };
+
+        // Values which aren't UTF-8, e.g. protobuf, are printed lossily
+        for consumer_record in records.iter() {
+            println!(
+                "{}: {}",
+                consumer_record.offset,
+                String::from_utf8_lossy(&consumer_record.record.value)
+            );
+        }
+
+        // The printed records are processed, a restarted consumer of the group continues after them
+        if group.is_some() && !records.is_empty() {
+            consumer.commit()?;
+        }
+    }
 }
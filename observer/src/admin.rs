@@ -0,0 +1,324 @@
+use std::{
+    io::{BufRead, BufReader},
+    net::TcpStream,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use shared_structures::{
+    tracing::{Span, SpanKind},
+    AdminRequest, AdminResponse, Broadcast, Message, MessageDecoder,
+};
+
+use crate::{
+    command_processor::{self, Command, HISTORY_FILE},
+    distribution_manager::DistributionManager,
+    metrics,
+    raft::Raft,
+};
+
+// Why an admin request has not been executed
+enum AdminError {
+    // Requests changing the cluster metadata are only executed by the leader
+    NotLeader {
+        leader_addr: Option<String>,
+        error: String,
+    },
+    Failed(String),
+}
+
+impl From<String> for AdminError {
+    fn from(error: String) -> Self {
+        Self::Failed(error)
+    }
+}
+
+impl From<AdminError> for String {
+    fn from(error: AdminError) -> Self {
+        match error {
+            AdminError::NotLeader { error, .. } | AdminError::Failed(error) => error,
+        }
+    }
+}
+
+/// Executes an admin request against the cluster, requests changing the cluster
+/// metadata fail unless this Observer is the leader.
+pub fn execute(
+    raft: &Arc<Mutex<Raft>>,
+    distribution_manager: &Arc<Mutex<DistributionManager>>,
+    request: AdminRequest,
+) -> Result<AdminResponse, String> {
+    execute_traced(raft, distribution_manager, request).map_err(String::from)
+}
+
+fn execute_traced(
+    raft: &Arc<Mutex<Raft>>,
+    distribution_manager: &Arc<Mutex<DistributionManager>>,
+    request: AdminRequest,
+) -> Result<AdminResponse, AdminError> {
+    let started = Instant::now();
+    let request_name = metrics::request_name(&request);
+    let labels = [("request", request_name)];
+    let registry = distribution_manager.lock().unwrap().metrics.clone();
+
+    // Replicas created by the request are traced as its children
+    let mut span = Span::start(format!("observer {}", request_name), SpanKind::Server);
+
+    let response = execute_request(raft, distribution_manager, request);
+
+    if let Err(AdminError::NotLeader { error, .. } | AdminError::Failed(error)) = &response {
+        span.set_error(error);
+    }
+
+    registry.observe(
+        &metrics::ADMIN_REQUEST_DURATION,
+        &labels,
+        started.elapsed().as_secs_f64(),
+    );
+
+    if response.is_err() {
+        registry.increment(&metrics::ADMIN_REQUESTS_FAILED, &labels, 1.0);
+    }
+
+    response
+}
+
+fn execute_request(
+    raft: &Arc<Mutex<Raft>>,
+    distribution_manager: &Arc<Mutex<DistributionManager>>,
+    request: AdminRequest,
+) -> Result<AdminResponse, AdminError> {
+    if request.is_mutation() {
+        let raft_lock = raft.lock().unwrap();
+
+        if !raft_lock.is_leader() {
+            return Err(AdminError::NotLeader {
+                leader_addr: raft_lock.leader_addr.clone(),
+                error: raft_lock.not_leader_error(),
+            });
+        }
+    }
+
+    let mut distribution_manager_lock = distribution_manager.lock().unwrap();
+
+    let command = request
+        .is_mutation()
+        .then(|| Command::from_admin_request(&request));
+
+    let response = apply_request(&mut distribution_manager_lock, request)?;
+
+    // Persisted while holding the lock, so the history is in the order the requests were applied in
+    if let Some(command) = command {
+        let history_file = distribution_manager_lock
+            .cluster_dir
+            .base_dir()?
+            .join(HISTORY_FILE);
+
+        if let Err(e) = command_processor::persist(&history_file, &command) {
+            log::warn!("Failed to persist the command history: {}", e);
+        }
+    }
+
+    Ok(response)
+}
+
+fn apply_request(
+    distribution_manager_lock: &mut DistributionManager,
+    request: AdminRequest,
+) -> Result<AdminResponse, String> {
+    match request {
+        AdminRequest::CreateTopic {
+            name,
+            partitions,
+            config,
+        } => {
+            let mut topic_config = distribution_manager_lock.default_topic_config();
+            topic_config.apply(&config)?;
+            let name = distribution_manager_lock.create_topic(&name, partitions, topic_config)?;
+            Ok(AdminResponse::TopicCreated { name })
+        }
+        AdminRequest::CreatePartition { topic } => {
+            let id = distribution_manager_lock.create_partition(&topic)?;
+            Ok(AdminResponse::PartitionCreated { id })
+        }
+        AdminRequest::AlterTopic { name, changes } => {
+            distribution_manager_lock.alter_topic(&name, &changes)?;
+            let topic = distribution_manager_lock.describe_topic(&name)?.topic;
+            Ok(AdminResponse::TopicAltered { topic })
+        }
+        AdminRequest::DeleteTopic { name } => {
+            distribution_manager_lock.delete_topic(&name)?;
+            Ok(AdminResponse::TopicDeleted { name })
+        }
+        AdminRequest::ListTopics => Ok(AdminResponse::Topics {
+            topics: distribution_manager_lock.list_topics(),
+        }),
+        AdminRequest::ListBrokers => Ok(AdminResponse::Brokers {
+            brokers: distribution_manager_lock.describe_brokers(),
+        }),
+        AdminRequest::DescribeTopic { name } => Ok(AdminResponse::Topic {
+            topic: distribution_manager_lock.describe_topic(&name)?,
+        }),
+        AdminRequest::DescribeBroker { id } => Ok(AdminResponse::Broker {
+            broker: distribution_manager_lock.describe_broker(&id)?,
+        }),
+        AdminRequest::DescribeCluster => Ok(AdminResponse::Cluster {
+            cluster: distribution_manager_lock.describe_cluster()?,
+        }),
+    }
+}
+
+/// Answers the requests of an admin client connected to this Observer, mutations sent
+/// to an Observer which is not the leader are answered with `NotLeader`.
+pub fn serve_admin(
+    raft: Arc<Mutex<Raft>>,
+    distribution_manager: Arc<Mutex<DistributionManager>>,
+    stream: TcpStream,
+) -> Result<(), String> {
+    let reader_stream = stream.try_clone().map_err(|e| e.to_string())?;
+
+    std::thread::spawn(move || {
+        let mut stream = stream;
+        let mut reader = BufReader::new(reader_stream);
+        let mut buf = String::with_capacity(1024);
+
+        loop {
+            match reader.read_line(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+
+            if let Err(e) = handle_admin_message(&raft, &distribution_manager, &mut stream, &buf) {
+                log::warn!("Admin client error: {}", e);
+                break;
+            }
+
+            buf.clear();
+        }
+    });
+
+    Ok(())
+}
+
+fn handle_admin_message(
+    raft: &Arc<Mutex<Raft>>,
+    distribution_manager: &Arc<Mutex<DistributionManager>>,
+    stream: &mut TcpStream,
+    raw_message: &str,
+) -> Result<(), String> {
+    let request = match MessageDecoder::decode(raw_message)? {
+        Message::AdminRequest { request } => request,
+        message => {
+            return Err(format!(
+                "Message {:?} is not handled for admin clients.",
+                message
+            ))
+        }
+    };
+
+    match execute_traced(raft, distribution_manager, request) {
+        Err(AdminError::NotLeader { leader_addr, .. }) => {
+            Broadcast::to(stream, &Message::NotLeader { leader_addr })
+        }
+        response => Broadcast::to(
+            stream,
+            &Message::AdminResponse {
+                response: response.map_err(String::from),
+            },
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use shared_structures::{Config, DirManager};
+
+    use super::*;
+    use crate::command_processor::read_script;
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn mutations_are_persisted_to_the_history() {
+        let data_dir = DirManager::test_root().join(format!("admin_{}", uuid::Uuid::new_v4()));
+        let config = Config {
+            data_dir: Some(data_dir.clone()),
+            replica_factor: 1,
+            ..Config::default()
+        };
+
+        let distribution_manager = DistributionManager::from(config, None).unwrap();
+        // A single Observer is the leader right away
+        let raft = Arc::new(Mutex::new(
+            Raft::new(
+                "localhost:1".to_string(),
+                vec![],
+                DirManager::with_root(Some(&data_dir), None),
+            )
+            .unwrap(),
+        ));
+        distribution_manager.lock().unwrap().raft = Some(raft.clone());
+
+        // Stands in for a broker, the partitions are placed on it
+        let listener = TcpListener::bind("localhost:0").unwrap();
+        let mut broker = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        Broadcast::to(
+            &mut broker,
+            &Message::BrokerConnectionDetails {
+                id: "mocked_broker_id".to_string(),
+                addr: "localhost:0".to_string(),
+                rack: None,
+            },
+        )
+        .unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        distribution_manager
+            .lock()
+            .unwrap()
+            .connect_broker(stream)
+            .unwrap();
+
+        let create_topic = AdminRequest::CreateTopic {
+            name: "notifications".to_string(),
+            partitions: 1,
+            config: vec![("retention_period".to_string(), "1d".to_string())],
+        };
+
+        execute(&raft, &distribution_manager, create_topic.clone()).unwrap();
+        // Neither failed requests nor reads are persisted
+        assert!(execute(&raft, &distribution_manager, create_topic).is_err());
+        execute(&raft, &distribution_manager, AdminRequest::ListTopics).unwrap();
+        execute(
+            &raft,
+            &distribution_manager,
+            AdminRequest::CreatePartition {
+                topic: "notifications".to_string(),
+            },
+        )
+        .unwrap();
+
+        let history_file = distribution_manager
+            .lock()
+            .unwrap()
+            .cluster_dir
+            .base_dir()
+            .unwrap()
+            .join(HISTORY_FILE);
+        let history = read_script(&history_file)
+            .unwrap()
+            .into_iter()
+            .map(|(_, raw_command)| raw_command)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            history,
+            [
+                "CREATE TOPIC notifications PARTITIONS 1 WITH retention_period=1d",
+                "CREATE PARTITION notifications",
+            ]
+        );
+
+        let _ = std::fs::remove_dir_all(data_dir);
+    }
+}
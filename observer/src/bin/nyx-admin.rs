@@ -0,0 +1,177 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpStream,
+};
+
+use clap::{arg, command};
+use observer::command_processor::Command;
+use shared_structures::{logger, AdminRequest, AdminResponse, EntityType, Message, MessageDecoder};
+
+fn main() {
+    let matches = command!()
+    .about("Administers a nyx cluster through its Observers")
+    .arg(
+        arg!(-o --observers <OBSERVERS> "List of the admin addresses of the Observers seperated by comma e.g. localhost:3828,localhost:3829, mutations are sent on to the leader among them.")
+        .required(false)
+        .default_value("localhost:3828")
+    )
+    .arg(
+        arg!(--json "Prints the response as JSON instead of a human readable form.")
+    )
+    .arg(
+        arg!(--"log-level" <LEVEL> "Most verbose level of the logs written to stderr, `debug` shows the Observers the request went through.")
+        .required(false)
+        .value_parser(logger::LEVELS)
+        .default_value("warn")
+    )
+    .arg(
+        arg!(--"log-format" <FORMAT> "Format of the logs, `json` writes one object per line for log collectors.")
+        .required(false)
+        .value_parser(logger::FORMATS)
+        .default_value("text")
+    )
+    .arg(
+        clap::Arg::new("command")
+        .help("Command to execute, the same commands the Observer accepts e.g. LIST ALL, CREATE TOPIC notifications PARTITIONS 3, DELETE TOPIC notifications.")
+        .required(true)
+        .num_args(1..)
+        .trailing_var_arg(true)
+    ).get_matches();
+
+    if let Err(e) = logger::init(
+        matches.get_one::<String>("log-level").unwrap(),
+        matches.get_one::<String>("log-format").unwrap(),
+    ) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+
+    let observers: Vec<String> = matches
+        .get_one::<String>("observers")
+        .unwrap()
+        .split_terminator(',')
+        .map(|o| o.trim().to_string())
+        .collect();
+    let json = matches.get_flag("json");
+    let raw_command = matches
+        .get_many::<String>("command")
+        .unwrap()
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let response = Command::from(&raw_command)
+        .and_then(|command| command.to_admin_request())
+        .and_then(|request| send_request(&observers, request));
+
+    if json {
+        match serde_json::to_string_pretty(&response) {
+            Ok(json) => println!("{}", json),
+            Err(e) => eprintln!("{}", e),
+        }
+    } else {
+        match &response {
+            Ok(response) => print!("{}", response),
+            Err(e) => eprintln!("\x1b[38;5;1mERROR:\x1b[0m {}", e),
+        }
+    }
+
+    if response.is_err() {
+        std::process::exit(1);
+    }
+}
+
+// Sends the request to the first reachable Observer, mutations refused by an Observer which is not
+// the leader are sent to the next one
+fn send_request(observers: &[String], request: AdminRequest) -> Result<AdminResponse, String> {
+    let mut refused = false;
+    let mut known_leader_addr = None;
+
+    for observer in observers {
+        let mut stream = match connect_to_observer(observer) {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::debug!("{}", e);
+                continue;
+            }
+        };
+
+        match request_once(&mut stream, &request)? {
+            Message::AdminResponse { response } => return response,
+            Message::NotLeader { leader_addr } => {
+                log::debug!(
+                    "Observer at {} is not the leader, the leader is {:?}",
+                    observer,
+                    leader_addr
+                );
+                refused = true;
+                known_leader_addr = leader_addr.or(known_leader_addr);
+            }
+            message => {
+                return Err(format!(
+                    "Unexpected response from the Observer: {:?}",
+                    message
+                ))
+            }
+        }
+    }
+
+    match (refused, known_leader_addr) {
+        (false, _) => Err(format!(
+            "Failed to connect to any of the Observers {:?}",
+            observers
+        )),
+        // Observers know the leader by its address among them, not by its admin address
+        (true, Some(leader_addr)) => Err(format!(
+            "None of the Observers {:?} is the leader, please add the admin address of the leader, which the Observers know as {}.",
+            observers, leader_addr
+        )),
+        (true, None) => {
+            Err("Observers have not elected a leader yet, please try again later.".to_string())
+        }
+    }
+}
+
+fn request_once(stream: &mut TcpStream, request: &AdminRequest) -> Result<Message, String> {
+    send(
+        stream,
+        &Message::AdminRequest {
+            request: request.clone(),
+        },
+    )?;
+
+    let mut reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+    let mut buf = String::with_capacity(1024);
+
+    let size = reader.read_line(&mut buf).map_err(|e| e.to_string())?;
+
+    if size == 0 {
+        return Err("Connection with the Observer has been closed.".to_string());
+    }
+
+    MessageDecoder::decode(&buf)
+}
+
+fn connect_to_observer(observer: &str) -> Result<TcpStream, String> {
+    let mut stream = TcpStream::connect(observer)
+        .map_err(|e| format!("Failed to connect to the Observer at {}: {}", observer, e))?;
+
+    log::debug!("Connected to the Observer at {}", observer);
+    send(
+        &mut stream,
+        &Message::EntityWantsToConnect {
+            entity_type: EntityType::Admin,
+        },
+    )?;
+
+    Ok(stream)
+}
+
+// Unlike `Broadcast::to` this doesn't log, stdout is kept for the response only
+fn send(stream: &mut TcpStream, message: &Message) -> Result<(), String> {
+    let mut payload = serde_json::to_string(message).map_err(|e| e.to_string())?;
+    payload.push('\n');
+    stream
+        .write_all(payload.as_bytes())
+        .map_err(|e| e.to_string())
+}
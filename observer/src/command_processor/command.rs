@@ -1,20 +1,55 @@
+use std::fmt::Display;
+
+use shared_structures::AdminRequest;
+
+#[derive(Debug, PartialEq)]
 pub enum CommandName {
     Create,
+    Alter,
+    Delete,
+    Describe,
     List,
 }
 
+impl CommandName {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Create => "CREATE",
+            Self::Alter => "ALTER",
+            Self::Delete => "DELETE",
+            Self::Describe => "DESCRIBE",
+            Self::List => "LIST",
+        }
+    }
+}
+
 pub struct Command {
     pub name: CommandName,
     pub arguments: Vec<String>,
 }
 
+impl Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name.as_str())?;
+
+        for argument in self.arguments.iter() {
+            write!(f, " {}", argument)?;
+        }
+
+        Ok(())
+    }
+}
+
 impl Command {
     pub fn from(raw_command: &str) -> Result<Self, String> {
         let mut tokens = raw_command.split_ascii_whitespace();
-        let command = tokens.next().unwrap();
+        let command = tokens.next().ok_or("Empty command".to_string())?;
 
         let name = match command {
             "CREATE" => CommandName::Create,
+            "ALTER" => CommandName::Alter,
+            "DELETE" => CommandName::Delete,
+            "DESCRIBE" => CommandName::Describe,
             "LIST" => CommandName::List,
             _ => return Err("unrecognized command has been passed.".to_string()),
         };
@@ -24,4 +59,281 @@ impl Command {
             arguments: tokens.map(|s| s.to_string()).collect(),
         })
     }
+
+    /// Command executing `request`, the inverse of `to_admin_request`.
+    pub fn from_admin_request(request: &AdminRequest) -> Self {
+        let (name, arguments) = match request {
+            AdminRequest::CreateTopic {
+                name,
+                partitions,
+                config,
+            } => {
+                let mut arguments = vec![
+                    "TOPIC".to_string(),
+                    name.clone(),
+                    "PARTITIONS".to_string(),
+                    partitions.to_string(),
+                ];
+
+                if !config.is_empty() {
+                    arguments.push("WITH".to_string());
+                    arguments.push(join_topic_config_changes(config));
+                }
+
+                (CommandName::Create, arguments)
+            }
+            AdminRequest::CreatePartition { topic } => (
+                CommandName::Create,
+                vec!["PARTITION".to_string(), topic.clone()],
+            ),
+            AdminRequest::AlterTopic { name, changes } => (
+                CommandName::Alter,
+                vec![
+                    "TOPIC".to_string(),
+                    name.clone(),
+                    "SET".to_string(),
+                    join_topic_config_changes(changes),
+                ],
+            ),
+            AdminRequest::DeleteTopic { name } => {
+                (CommandName::Delete, vec!["TOPIC".to_string(), name.clone()])
+            }
+            AdminRequest::DescribeTopic { name } => (
+                CommandName::Describe,
+                vec!["TOPIC".to_string(), name.clone()],
+            ),
+            AdminRequest::DescribeBroker { id } => (
+                CommandName::Describe,
+                vec!["BROKER".to_string(), id.clone()],
+            ),
+            AdminRequest::DescribeCluster => (CommandName::List, vec!["ALL".to_string()]),
+            AdminRequest::ListTopics => (CommandName::List, vec!["TOPICS".to_string()]),
+            AdminRequest::ListBrokers => (CommandName::List, vec!["BROKERS".to_string()]),
+        };
+
+        Self { name, arguments }
+    }
+
+    /// Translates the command into the request executed by the Observer, the same
+    /// commands are accepted on the Observer's stdin and by `nyx-admin`.
+    pub fn to_admin_request(&self) -> Result<AdminRequest, String> {
+        let mut arguments_iter = self.arguments.iter();
+
+        let entity = arguments_iter
+            .next()
+            .ok_or("Entity type was not provided.".to_string())?;
+
+        match (&self.name, entity.as_str()) {
+            (CommandName::Create, "TOPIC") => parse_create_topic(&mut arguments_iter),
+            (CommandName::Create, "PARTITION") => Ok(AdminRequest::CreatePartition {
+                topic: parse_name(
+                    &mut arguments_iter,
+                    "Please provide a valid topic name for which you want to create a partition.",
+                )?,
+            }),
+            (CommandName::Alter, "TOPIC") => parse_alter_topic(&mut arguments_iter),
+            (CommandName::Delete, "TOPIC") => Ok(AdminRequest::DeleteTopic {
+                name: parse_name(
+                    &mut arguments_iter,
+                    "Please provide the name of the topic you want to delete.",
+                )?,
+            }),
+            (CommandName::Describe, "TOPIC") => Ok(AdminRequest::DescribeTopic {
+                name: parse_name(
+                    &mut arguments_iter,
+                    "Please provide the name of the topic you want to describe.",
+                )?,
+            }),
+            (CommandName::Describe, "BROKER") => Ok(AdminRequest::DescribeBroker {
+                id: parse_name(
+                    &mut arguments_iter,
+                    "Please provide the id of the broker you want to describe.",
+                )?,
+            }),
+            (CommandName::List, "ALL") => Ok(AdminRequest::DescribeCluster),
+            (CommandName::List, "TOPICS") => Ok(AdminRequest::ListTopics),
+            (CommandName::List, "BROKERS") => Ok(AdminRequest::ListBrokers),
+            (CommandName::List, level) => Err(format!(
+                "Requested listing depth `{}` is not supported",
+                level
+            )),
+            _ => Err("Unrecognized entity has been provided.".to_string()),
+        }
+    }
+}
+
+fn parse_name(
+    arguments_iter: &mut std::slice::Iter<'_, String>,
+    error: &str,
+) -> Result<String, String> {
+    arguments_iter.next().cloned().ok_or(error.to_string())
+}
+
+// CREATE TOPIC [TOPIC_NAME] PARTITIONS [N] REPLICAS [R] WITH [KEY=VALUE]...
+fn parse_create_topic(
+    arguments_iter: &mut std::slice::Iter<'_, String>,
+) -> Result<AdminRequest, String> {
+    let name = parse_name(
+        arguments_iter,
+        "Please provide topic name for which you want to create the topic.",
+    )?;
+    let mut partitions = 0;
+    let mut config = vec![];
+
+    while let Some(keyword) = arguments_iter.next() {
+        match keyword.as_str() {
+            "PARTITIONS" => {
+                partitions = parse_count(arguments_iter.next(), "PARTITIONS")?;
+            }
+            "REPLICAS" => {
+                let replica_factor = parse_count(arguments_iter.next(), "REPLICAS")?;
+                config.push(("replica_factor".to_string(), replica_factor.to_string()));
+            }
+            "WITH" => config.extend(parse_topic_config_changes(arguments_iter)?),
+            _ => {
+                return Err(format!(
+                    "Expected `PARTITIONS`, `REPLICAS` or `WITH`, found `{}`.",
+                    keyword
+                ))
+            }
+        }
+    }
+
+    Ok(AdminRequest::CreateTopic {
+        name,
+        partitions,
+        config,
+    })
+}
+
+// ALTER TOPIC [TOPIC_NAME] SET [KEY=VALUE]...
+fn parse_alter_topic(
+    arguments_iter: &mut std::slice::Iter<'_, String>,
+) -> Result<AdminRequest, String> {
+    let name = parse_name(
+        arguments_iter,
+        "Please provide the name of the topic you want to alter.",
+    )?;
+    let changes = match arguments_iter.next() {
+        Some(keyword) if keyword == "SET" => parse_topic_config_changes(arguments_iter)?,
+        _ => return Err("Expected `SET key=value` after the topic name.".to_string()),
+    };
+
+    Ok(AdminRequest::AlterTopic { name, changes })
+}
+
+fn parse_count(value: Option<&String>, keyword: &str) -> Result<usize, String> {
+    value
+        .and_then(|v| v.parse::<usize>().ok())
+        .ok_or(format!("`{}` should be followed by a number.", keyword))
+}
+
+// Parses `key=value` pairs separated by whitespace or commas, e.g. `retention_period=1d,cleanup_policy=compact`
+fn parse_topic_config_changes(
+    arguments_iter: &mut std::slice::Iter<'_, String>,
+) -> Result<Vec<(String, String)>, String> {
+    let changes = arguments_iter
+        .flat_map(|a| a.split_terminator(','))
+        .filter(|a| !a.is_empty())
+        .map(|a| {
+            a.split_once('=')
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .ok_or(format!("`{}` should be in the form of key=value.", a))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    if changes.is_empty() {
+        return Err("Please provide at least one key=value pair.".to_string());
+    }
+
+    Ok(changes)
+}
+
+fn join_topic_config_changes(changes: &[(String, String)]) -> String {
+    changes
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw_command: &str) -> Result<AdminRequest, String> {
+        Command::from(raw_command)?.to_admin_request()
+    }
+
+    #[test]
+    fn parses_commands_into_admin_requests() {
+        assert_eq!(
+            parse("CREATE TOPIC notifications PARTITIONS 3 REPLICAS 2 WITH retention_period=1d,cleanup_policy=compact").unwrap(),
+            AdminRequest::CreateTopic {
+                name: "notifications".to_string(),
+                partitions: 3,
+                config: vec![
+                    ("replica_factor".to_string(), "2".to_string()),
+                    ("retention_period".to_string(), "1d".to_string()),
+                    ("cleanup_policy".to_string(), "compact".to_string()),
+                ],
+            }
+        );
+        assert_eq!(
+            parse("ALTER TOPIC notifications SET max_message_size=2MiB").unwrap(),
+            AdminRequest::AlterTopic {
+                name: "notifications".to_string(),
+                changes: vec![("max_message_size".to_string(), "2MiB".to_string())],
+            }
+        );
+        assert_eq!(
+            parse("DELETE TOPIC notifications").unwrap(),
+            AdminRequest::DeleteTopic {
+                name: "notifications".to_string()
+            }
+        );
+        assert_eq!(
+            parse("DESCRIBE TOPIC notifications").unwrap(),
+            AdminRequest::DescribeTopic {
+                name: "notifications".to_string()
+            }
+        );
+        assert_eq!(
+            parse("DESCRIBE BROKER 6b15118e").unwrap(),
+            AdminRequest::DescribeBroker {
+                id: "6b15118e".to_string()
+            }
+        );
+        assert_eq!(parse("LIST ALL").unwrap(), AdminRequest::DescribeCluster);
+        assert_eq!(parse("LIST BROKERS").unwrap(), AdminRequest::ListBrokers);
+    }
+
+    #[test]
+    fn admin_requests_translate_back_into_commands() {
+        for raw_command in [
+            "CREATE TOPIC notifications PARTITIONS 3",
+            "CREATE TOPIC notifications PARTITIONS 3 WITH replica_factor=2,retention_period=1d",
+            "CREATE PARTITION notifications",
+            "ALTER TOPIC notifications SET max_message_size=2MiB,cleanup_policy=compact",
+            "DELETE TOPIC notifications",
+            "DESCRIBE BROKER 6b15118e",
+            "LIST ALL",
+        ] {
+            let request = parse(raw_command).unwrap();
+            let command = Command::from_admin_request(&request);
+
+            assert_eq!(command.to_string(), raw_command);
+            assert_eq!(command.to_admin_request().unwrap(), request);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_commands() {
+        assert!(parse("").is_err());
+        assert!(parse("DROP TOPIC notifications").is_err());
+        assert!(parse("DELETE PARTITION notifications").is_err());
+        assert!(parse("CREATE TOPIC notifications PARTITIONS many").is_err());
+        assert!(parse("ALTER TOPIC notifications retention_period=1d").is_err());
+        assert!(parse("LIST EVERYTHING").is_err());
+    }
 }
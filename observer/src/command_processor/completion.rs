@@ -0,0 +1,191 @@
+use std::sync::{Arc, Mutex};
+
+use rustyline::{
+    completion::{Completer, Pair},
+    highlight::Highlighter,
+    hint::Hinter,
+    validate::Validator,
+    Context, Helper,
+};
+use shared_structures::TopicConfig;
+
+use crate::distribution_manager::DistributionManager;
+
+use super::help;
+
+/// Completes the commands typed into the Observer's prompt, topic names and broker
+/// ids are taken from the distribution manager.
+pub struct CommandHelper {
+    distribution_manager: Option<Arc<Mutex<DistributionManager>>>,
+}
+
+impl CommandHelper {
+    pub fn new(distribution_manager: Option<Arc<Mutex<DistributionManager>>>) -> Self {
+        Self {
+            distribution_manager,
+        }
+    }
+
+    fn topics_and_brokers(&self) -> (Vec<String>, Vec<String>) {
+        // Completing without them beats blocking the prompt while a metadata change is replicated
+        let Some(distribution_manager_lock) = self
+            .distribution_manager
+            .as_ref()
+            .and_then(|d| d.try_lock().ok())
+        else {
+            return (vec![], vec![]);
+        };
+
+        let topics = distribution_manager_lock
+            .list_topics()
+            .into_iter()
+            .map(|t| t.name)
+            .collect();
+        let brokers = distribution_manager_lock
+            .describe_brokers()
+            .into_iter()
+            .map(|b| b.id)
+            .collect();
+
+        (topics, brokers)
+    }
+}
+
+impl Completer for CommandHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let (topics, brokers) = self.topics_and_brokers();
+        let (start, candidates) = complete(&line[..pos], &topics, &brokers);
+
+        Ok((
+            start,
+            candidates
+                .into_iter()
+                .map(|c| Pair {
+                    display: c.trim_end().to_string(),
+                    replacement: c,
+                })
+                .collect(),
+        ))
+    }
+}
+
+impl Hinter for CommandHelper {
+    type Hint = String;
+}
+
+impl Highlighter for CommandHelper {}
+
+impl Validator for CommandHelper {}
+
+impl Helper for CommandHelper {}
+
+/// Returns the position the completed word starts at alongside the candidates to replace
+/// it with, `line` is the part of the line before the cursor.
+pub fn complete(line: &str, topics: &[String], brokers: &[String]) -> (usize, Vec<String>) {
+    let mut start = line
+        .rfind(|c: char| c.is_ascii_whitespace())
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    let words: Vec<&str> = line[..start].split_ascii_whitespace().collect();
+
+    let keywords = |k: &[&str]| k.iter().map(|k| format!("{} ", k)).collect::<Vec<_>>();
+    let names = |n: &[String]| n.iter().map(|n| format!("{} ", n)).collect::<Vec<_>>();
+    let config_keys = || {
+        TopicConfig::KEYS
+            .iter()
+            .map(|k| format!("{}=", k))
+            .collect::<Vec<_>>()
+    };
+
+    let candidates = match words.as_slice() {
+        [] | ["HELP"] => keywords(&help::command_names().collect::<Vec<_>>()),
+        ["CREATE"] => keywords(&["TOPIC", "PARTITION"]),
+        ["ALTER"] | ["DELETE"] => keywords(&["TOPIC"]),
+        ["DESCRIBE"] => keywords(&["TOPIC", "BROKER"]),
+        ["LIST"] => keywords(&["ALL", "TOPICS", "BROKERS"]),
+        ["CREATE", "PARTITION"]
+        | ["ALTER", "TOPIC"]
+        | ["DELETE", "TOPIC"]
+        | ["DESCRIBE", "TOPIC"] => names(topics),
+        ["DESCRIBE", "BROKER"] => names(brokers),
+        ["ALTER", "TOPIC", _] => keywords(&["SET"]),
+        ["ALTER", "TOPIC", _, "SET", ..] => config_keys(),
+        ["CREATE", "TOPIC", _, rest @ ..] => {
+            if rest.contains(&"WITH") {
+                config_keys()
+            } else if matches!(rest.last(), Some(&"PARTITIONS") | Some(&"REPLICAS")) {
+                vec![]
+            } else {
+                keywords(&["PARTITIONS", "REPLICAS", "WITH"])
+            }
+        }
+        _ => vec![],
+    };
+
+    // Configuration pairs can be separated by commas as well
+    if let Some(i) = line[start..].rfind(',') {
+        start += i + 1;
+    }
+
+    let word = &line[start..];
+
+    (
+        start,
+        candidates
+            .into_iter()
+            .filter(|c| c.starts_with(word))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates(line: &str) -> Vec<String> {
+        let topics = vec!["notifications".to_string(), "payments".to_string()];
+        let brokers = vec!["6b15118e".to_string()];
+        complete(line, &topics, &brokers).1
+    }
+
+    #[test]
+    fn completes_commands_and_keywords() {
+        assert_eq!(candidates("CR"), vec!["CREATE "]);
+        assert_eq!(candidates("DE").len(), 2);
+        assert_eq!(candidates("CREATE "), vec!["TOPIC ", "PARTITION "]);
+        assert_eq!(candidates("LIST B"), vec!["BROKERS "]);
+        assert_eq!(candidates("HELP AL"), vec!["ALTER "]);
+        assert_eq!(
+            candidates("CREATE TOPIC logs "),
+            vec!["PARTITIONS ", "REPLICAS ", "WITH "]
+        );
+        assert!(candidates("CREATE TOPIC logs PARTITIONS ").is_empty());
+        assert_eq!(candidates("ALTER TOPIC payments "), vec!["SET "]);
+    }
+
+    #[test]
+    fn completes_topics_brokers_and_config_keys() {
+        assert_eq!(candidates("DELETE TOPIC pay"), vec!["payments "]);
+        assert_eq!(candidates("DESCRIBE TOPIC ").len(), 2);
+        assert_eq!(candidates("DESCRIBE BROKER 6b"), vec!["6b15118e "]);
+        // New topics have no name to complete
+        assert!(candidates("CREATE TOPIC no").is_empty());
+
+        assert_eq!(
+            candidates("ALTER TOPIC payments SET ret"),
+            vec!["retention_period="]
+        );
+
+        let (start, candidates) =
+            complete("CREATE TOPIC logs WITH retention_period=1d,cle", &[], &[]);
+        assert_eq!(start, 43);
+        assert_eq!(candidates, vec!["cleanup_policy="]);
+    }
+}
@@ -0,0 +1,87 @@
+use shared_structures::TopicConfig;
+
+struct CommandHelp {
+    name: &'static str,
+    summary: &'static str,
+    usage: &'static [&'static str],
+}
+
+const COMMANDS: [CommandHelp; 7] = [
+    CommandHelp {
+        name: "CREATE",
+        summary: "Creates a topic or a partition of a topic",
+        usage: &[
+            "CREATE TOPIC [TOPIC_NAME] PARTITIONS [N] REPLICAS [R] WITH [KEY=VALUE]...",
+            "CREATE PARTITION [TOPIC_NAME]",
+        ],
+    },
+    CommandHelp {
+        name: "ALTER",
+        summary: "Changes the configuration of a topic",
+        usage: &["ALTER TOPIC [TOPIC_NAME] SET [KEY=VALUE]..."],
+    },
+    CommandHelp {
+        name: "DELETE",
+        summary: "Deletes a topic with all of its partitions",
+        usage: &["DELETE TOPIC [TOPIC_NAME]"],
+    },
+    CommandHelp {
+        name: "DESCRIBE",
+        summary: "Describes a topic or a broker",
+        usage: &["DESCRIBE TOPIC [TOPIC_NAME]", "DESCRIBE BROKER [BROKER_ID]"],
+    },
+    CommandHelp {
+        name: "LIST",
+        summary: "Lists the brokers and topics of the cluster",
+        usage: &["LIST ALL", "LIST TOPICS", "LIST BROKERS"],
+    },
+    CommandHelp {
+        name: "HELP",
+        summary: "Shows the available commands or the usage of a command",
+        usage: &["HELP", "HELP [COMMAND]"],
+    },
+    CommandHelp {
+        name: "EXIT",
+        summary: "Saves the cluster state and shuts the Observer down",
+        usage: &["EXIT"],
+    },
+];
+
+/// Names of every command accepted by the Observer's prompt.
+pub fn command_names() -> impl Iterator<Item = &'static str> {
+    COMMANDS.iter().map(|c| c.name)
+}
+
+/// Summary of every command, or the usage of `command` when provided.
+pub fn help(command: Option<&str>) -> Result<String, String> {
+    let Some(command) = command else {
+        let mut text = String::from("Available commands:\n");
+
+        for command in COMMANDS.iter() {
+            text.push_str(&format!("  {:<10}{}\n", command.name, command.summary));
+        }
+
+        text.push_str("Type HELP [COMMAND] for the usage of a command.\n");
+        return Ok(text);
+    };
+
+    let command = COMMANDS
+        .iter()
+        .find(|c| c.name.eq_ignore_ascii_case(command))
+        .ok_or(format!("Command `{}` doesn't exist.", command))?;
+
+    let mut text = format!("{}\n", command.summary);
+
+    for usage in command.usage {
+        text.push_str(&format!("  {}\n", usage));
+    }
+
+    if command.name == "CREATE" || command.name == "ALTER" {
+        text.push_str(&format!(
+            "Topic configuration keys: {}\n",
+            TopicConfig::KEYS.join(", ")
+        ));
+    }
+
+    Ok(text)
+}
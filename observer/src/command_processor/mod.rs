@@ -1,39 +1,284 @@
 pub mod command;
+pub mod completion;
+pub mod help;
+
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use rustyline::{error::ReadlineError, history::DefaultHistory, Editor};
 
 pub use self::command::Command;
 pub use self::command::CommandName;
+use self::completion::CommandHelper;
+use crate::distribution_manager::DistributionManager;
+
+pub const HISTORY_FILE: &str = "history.nyx";
+
+const PROMPT: &str = "> ";
+
+/// Input typed into the Observer's prompt.
+#[derive(Debug, PartialEq)]
+pub enum Input {
+    Command(String),
+    Help(Option<String>),
+    Exit,
+    // Stdin has been closed
+    Closed,
+}
+
+/// What happens when a command of a script fails.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OnError {
+    Stop,
+    Continue,
+}
 
 #[derive(Debug, Default)]
 pub struct CommandProcessor {
-    buf: String,
+    // Created once the first command is read, so that scripts and tests never touch the terminal
+    editor: Option<Editor<CommandHelper, DefaultHistory>>,
     history: Vec<String>,
+    // Source of the topic names and broker ids for completion
+    distribution_manager: Option<Arc<Mutex<DistributionManager>>>,
 }
 
 impl CommandProcessor {
     pub fn new() -> Self {
         Self {
-            buf: String::new(),
+            editor: None,
             history: Vec::with_capacity(64),
+            distribution_manager: None,
+        }
+    }
+
+    /// Command processor recalling the commands of previous runs, which are persisted to `history_file`.
+    pub fn with_history_file(history_file: PathBuf) -> Result<Self, String> {
+        let history = match std::fs::read_to_string(&history_file) {
+            Ok(content) => content.lines().map(|l| l.to_string()).collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::with_capacity(64),
+            Err(e) => {
+                return Err(format!(
+                    "Failed to read the command history {}: {}",
+                    history_file.display(),
+                    e
+                ))
+            }
+        };
+
+        Ok(Self {
+            editor: None,
+            history,
+            distribution_manager: None,
+        })
+    }
+
+    /// Completes topic names and broker ids of the cluster managed by `distribution_manager`.
+    pub fn complete_from(&mut self, distribution_manager: Arc<Mutex<DistributionManager>>) {
+        self.distribution_manager = Some(distribution_manager);
+    }
+
+    /// Reads the next input from the prompt, with line editing, history recall and tab completion.
+    pub fn read_input(&mut self) -> Result<Input, String> {
+        loop {
+            let line = match self.editor()?.readline(PROMPT) {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => {
+                    println!("Type EXIT to shut down the Observer.");
+                    continue;
+                }
+                Err(ReadlineError::Eof) => return Ok(Input::Closed),
+                Err(e) => return Err(e.to_string()),
+            };
+
+            let raw_command = line.trim();
+
+            if raw_command.is_empty() {
+                continue;
+            }
+
+            self.add_history(raw_command.to_string());
+
+            return Ok(parse_input(raw_command));
         }
     }
 
-    pub fn process_raw_command(&mut self) -> Result<Command, String> {
-        std::io::stdin()
-            .read_line(&mut self.buf)
-            .map_err(|e| e.to_string())?;
+    fn editor(&mut self) -> Result<&mut Editor<CommandHelper, DefaultHistory>, String> {
+        if self.editor.is_none() {
+            let mut editor = Editor::new().map_err(|e| e.to_string())?;
+            editor.set_helper(Some(CommandHelper::new(self.distribution_manager.clone())));
+
+            // Commands of the previous runs can be recalled as well
+            for raw_command in self.history.iter() {
+                editor
+                    .add_history_entry(raw_command.as_str())
+                    .map_err(|e| e.to_string())?;
+            }
 
-        if self.buf.trim().is_empty() {
-            self.buf.clear();
-            return Err("Empty command".to_string());
+            self.editor = Some(editor);
         }
 
-        let command = Command::from(&self.buf)?;
-        self.add_history(self.buf.to_string());
-        self.buf.clear();
-        Ok(command)
+        Ok(self.editor.as_mut().unwrap())
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.history
     }
 
     fn add_history(&mut self, raw_command: String) {
+        if let Some(editor) = self.editor.as_mut() {
+            if let Err(e) = editor.add_history_entry(raw_command.as_str()) {
+                log::warn!("Failed to add the command to the history: {}", e);
+            }
+        }
+
         self.history.push(raw_command);
     }
 }
+
+/// Appends a command that changed the cluster to the history file, the file is a script itself
+/// so a cluster layout can be reproduced by passing it to `--init-script`.
+pub fn persist(history_file: &Path, command: &Command) -> Result<(), String> {
+    if let Some(dir) = history_file.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(history_file)
+        .map_err(|e| e.to_string())?;
+
+    writeln!(file, "{}", command).map_err(|e| {
+        format!(
+            "Failed to save the command to {}: {}",
+            history_file.display(),
+            e
+        )
+    })
+}
+
+fn parse_input(raw_command: &str) -> Input {
+    let mut tokens = raw_command.split_ascii_whitespace();
+
+    match tokens.next() {
+        Some("HELP") => Input::Help(tokens.next().map(|t| t.to_string())),
+        Some("EXIT") => Input::Exit,
+        _ => Input::Command(raw_command.to_string()),
+    }
+}
+
+/// Reads the commands of a script alongside their line numbers, empty lines and lines
+/// starting with `#` are skipped.
+pub fn read_script(path: &Path) -> Result<Vec<(usize, String)>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read the script {}: {}", path.display(), e))?;
+
+    Ok(content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| (i, line.to_string()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            shared_structures::DirManager::test_root().join(format!("command_processor_{}", name));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn persisted_history_is_loaded_back() {
+        let dir = test_dir("history");
+        let history_file = dir.join(HISTORY_FILE);
+
+        let command_processor = CommandProcessor::with_history_file(history_file.clone()).unwrap();
+        assert!(command_processor.history().is_empty());
+
+        for raw_command in [
+            "CREATE   TOPIC notifications PARTITIONS 3",
+            "ALTER TOPIC notifications SET retention_period=1d",
+        ] {
+            let command = Command::from(raw_command).unwrap();
+            persist(&history_file, &command).unwrap();
+        }
+
+        let command_processor = CommandProcessor::with_history_file(history_file.clone()).unwrap();
+        assert_eq!(
+            command_processor.history(),
+            [
+                "CREATE TOPIC notifications PARTITIONS 3",
+                "ALTER TOPIC notifications SET retention_period=1d"
+            ]
+        );
+
+        // The history is a script in itself
+        let script = read_script(&history_file).unwrap();
+        assert_eq!(script.len(), 2);
+        assert_eq!(script[1].0, 2);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn parses_prompt_input() {
+        assert_eq!(parse_input("EXIT"), Input::Exit);
+        assert_eq!(parse_input("HELP"), Input::Help(None));
+        assert_eq!(
+            parse_input("HELP CREATE"),
+            Input::Help(Some("CREATE".to_string()))
+        );
+        assert_eq!(
+            parse_input("LIST ALL"),
+            Input::Command("LIST ALL".to_string())
+        );
+    }
+
+    #[test]
+    fn help_shows_the_usage_of_commands() {
+        let summary = help::help(None).unwrap();
+        assert!(help::command_names().all(|c| summary.contains(c)));
+
+        let usage = help::help(Some("alter")).unwrap();
+        assert!(usage.contains("ALTER TOPIC [TOPIC_NAME] SET [KEY=VALUE]..."));
+        assert!(usage.contains("retention_period"));
+
+        assert!(help::help(Some("DROP")).is_err());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn script_skips_comments_and_empty_lines() {
+        let dir = test_dir("script");
+        std::fs::create_dir_all(&dir).unwrap();
+        let script_path = dir.join("topics.nyx");
+
+        std::fs::write(
+            &script_path,
+            "# Topics of the notifications service\n\nCREATE TOPIC notifications\n  LIST ALL  \n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            read_script(&script_path).unwrap(),
+            vec![
+                (3, "CREATE TOPIC notifications".to_string()),
+                (4, "LIST ALL".to_string())
+            ]
+        );
+        assert!(read_script(&dir.join("missing.nyx")).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
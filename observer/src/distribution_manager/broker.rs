@@ -1,4 +1,8 @@
-use std::{io::BufReader, net::TcpStream};
+use std::{
+    io::BufReader,
+    net::{Shutdown, SocketAddr, TcpStream},
+    time::Instant,
+};
 
 use shared_structures::Status;
 
@@ -12,10 +16,17 @@ pub struct Broker {
     pub reader: Option<BufReader<TcpStream>>,
     pub status: Status,
     pub addr: String,
+    pub rack: Option<String>,
+    pub last_heartbeat: Option<Instant>,
 }
 
 impl Broker {
-    pub fn from(id: String, stream: Option<TcpStream>, addr: String) -> Result<Self, String> {
+    pub fn from(
+        id: String,
+        stream: Option<TcpStream>,
+        addr: String,
+        rack: Option<String>,
+    ) -> Result<Self, String> {
         if let Some(stream) = stream {
             let read_stream = stream.try_clone().map_err(|e| e.to_string())?;
             let reader = BufReader::new(read_stream);
@@ -27,6 +38,8 @@ impl Broker {
                 reader: Some(reader),
                 status: Status::Up,
                 addr,
+                rack,
+                last_heartbeat: Some(Instant::now()),
             })
         } else {
             Ok(Self {
@@ -36,11 +49,18 @@ impl Broker {
                 reader: None,
                 status: Status::Up,
                 addr,
+                rack,
+                last_heartbeat: None,
             })
         }
     }
 
-    pub fn restore(&mut self, stream: TcpStream, addr: String) -> Result<(), String> {
+    pub fn restore(
+        &mut self,
+        stream: TcpStream,
+        addr: String,
+        rack: Option<String>,
+    ) -> Result<(), String> {
         let read_stream = stream.try_clone().map_err(|e| e.to_string())?;
         let reader = BufReader::new(read_stream);
 
@@ -48,6 +68,8 @@ impl Broker {
         self.stream = Some(stream);
         self.reader = Some(reader);
         self.addr = addr;
+        self.rack = rack;
+        self.last_heartbeat = Some(Instant::now());
 
         for partition in self.partitions.iter_mut() {
             partition.status = Status::Up
@@ -63,7 +85,30 @@ impl Broker {
             .for_each(|p| p.status = Status::Down);
     }
 
+    /// Closes the connection with a broker that stopped sending heartbeats,
+    /// the broker will connect again once it notices the closed connection.
+    pub fn expire_session(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+
+        self.reader = None;
+        self.disconnect();
+    }
+
+    /// Whether the broker is currently connected through the connection coming from `peer_addr`,
+    /// readers of connections that have been replaced by a newer one should leave the broker alone.
+    pub fn is_connected_through(&self, peer_addr: Option<SocketAddr>) -> bool {
+        peer_addr.is_some() && self.stream.as_ref().and_then(|s| s.peer_addr().ok()) == peer_addr
+    }
+
     pub fn get_offline_partitions(&self) -> &[Partition] {
         &self.partitions
     }
+
+    /// The failure domain of the broker, brokers without a rack are
+    /// treated as being the only member of their own rack.
+    pub fn failure_domain(&self) -> &str {
+        self.rack.as_deref().unwrap_or(&self.id)
+    }
 }
@@ -2,8 +2,8 @@ use std::{
     io::{BufRead, BufReader},
     net::TcpStream,
     path::PathBuf,
-    sync::{Arc, Mutex, MutexGuard},
-    time::Duration,
+    sync::{Arc, Mutex, MutexGuard, Weak},
+    time::{Duration, Instant},
 };
 
 mod broker;
@@ -12,20 +12,37 @@ mod partition;
 pub use broker::Broker;
 pub use partition::Partition;
 use shared_structures::{
+    admin::{
+        BrokerDescription, ClusterDescription, PartitionDescription, ReplicaDescription,
+        TopicDescription, UnderReplicatedPartition,
+    },
     metadata::{BrokerDetails, PartitionDetails},
-    Broadcast, DirManager, Message, Metadata, Reader, Status, Topic,
+    tracing::{Span, SpanKind},
+    Broadcast, Config, DirManager, Message, MessageDecoder, Metadata, Metrics, Reader, Role,
+    Status, Topic, TopicConfig,
 };
 
-use crate::{config::Config, CLUSTER_FILE};
+use crate::{raft::Raft, CLUSTER_FILE};
 
 #[derive(Debug)]
 pub struct DistributionManager {
     pub brokers: Arc<Mutex<Vec<Broker>>>,
     pub topics: Vec<Arc<Mutex<Topic>>>,
     pub cluster_dir: DirManager,
-    pub followers: Vec<TcpStream>,
+    // Metadata changes are replicated among the Observers through raft, when set
+    pub raft: Option<Arc<Mutex<Raft>>>,
+    pub metrics: Arc<Metrics>,
     config: Config,
     pending_replication_partitions: Vec<(usize, Partition)>,
+    // Lets the broker readers reach the distribution manager when a broker asks to leave
+    this: Weak<Mutex<Self>>,
+}
+
+// Topics and replicas of the cluster before a change, restored when the change can't be committed
+struct ClusterSnapshot {
+    topics: Vec<(Arc<Mutex<Topic>>, Topic)>,
+    replicas: Vec<(String, Vec<Partition>)>,
+    pending_replication_partitions: Vec<(usize, Partition)>,
 }
 
 impl DistributionManager {
@@ -37,14 +54,13 @@ impl DistributionManager {
             Some(PathBuf::from("/observer"))
         };
 
-        println!("Custom dir: {:?}", custom_dir);
+        log::debug!("Cluster directory: {:?}", custom_dir);
 
-        let cluster_dir = DirManager::with_dir(custom_dir.as_ref());
+        let cluster_dir = DirManager::with_root(config.data_dir.as_ref(), custom_dir.as_ref());
 
-        let cluster_metadata = match cluster_dir.open::<Metadata>(CLUSTER_FILE) {
-            Ok(m) => m,
-            Err(_) => Metadata::default(),
-        };
+        let cluster_metadata = cluster_dir
+            .open_optional::<Metadata>(CLUSTER_FILE)?
+            .unwrap_or_default();
 
         let mut distribution_manager = Self {
             brokers: Arc::new(Mutex::new(vec![])),
@@ -52,12 +68,19 @@ impl DistributionManager {
             config,
             pending_replication_partitions: vec![],
             cluster_dir,
-            followers: vec![],
+            raft: None,
+            metrics: Arc::new(Metrics::new()),
+            this: Weak::new(),
         };
 
         distribution_manager.load_cluster_state(&cluster_metadata)?;
 
-        Ok(Arc::new(Mutex::new(distribution_manager)))
+        distribution_manager.spawn_session_monitor();
+
+        Ok(Arc::new_cyclic(|this| {
+            distribution_manager.this = this.clone();
+            Mutex::new(distribution_manager)
+        }))
     }
 
     pub fn load_cluster_state(&mut self, cluster_metadata: &Metadata) -> Result<(), String> {
@@ -69,6 +92,8 @@ impl DistributionManager {
 
         let mut brokers_lock = self.brokers.lock().unwrap();
 
+        brokers_lock.clear();
+
         for b in cluster_metadata.brokers.iter() {
             let partitions: Vec<_> = b
                 .partitions
@@ -106,6 +131,8 @@ impl DistributionManager {
                 reader: None,
                 status: Status::Down,
                 addr: b.addr.clone(),
+                rack: b.rack.clone(),
+                last_heartbeat: None,
             };
 
             brokers_lock.push(offline_broker);
@@ -124,34 +151,34 @@ impl DistributionManager {
     // meaning that this broker has disconnected in one of many possible ways, including user interference, unexpected system crush
     // or any other reason. Observer should try and sync with the brokers via the brokers provided id.
     pub fn connect_broker(&mut self, stream: TcpStream) -> Result<String, String> {
-        println!("NEW BROKER: {:?}", stream);
+        log::debug!("Broker connecting from {:?}", stream.peer_addr().ok());
         // Handshake process between the Broker and Observer happening in get_broker_metadata
-        let (id, addr, stream) = self.get_broker_metadata(stream)?;
-        println!("BROKER METADATA: {} {} {:?}", id, addr, stream);
+        let (id, addr, rack, stream) = self.get_broker_metadata(stream)?;
+        log::debug!("Broker {} advertises {} in rack {:?}", id, addr, rack);
+        let snapshot = self.snapshot();
         let mut brokers_lock = self.brokers.lock().unwrap();
-        println!("AQUIRED BROKER LOCK");
         let broker_id =
             if let Some(disconnected_broker) = brokers_lock.iter_mut().find(|b| b.id == id) {
-                disconnected_broker.restore(stream, addr)?;
+                disconnected_broker.restore(stream, addr, rack)?;
                 self.spawn_broker_reader(disconnected_broker)?;
                 disconnected_broker.id.clone()
             } else {
-                let mut broker = Broker::from(id, Some(stream), addr)?;
+                let mut broker = Broker::from(id, Some(stream), addr, rack)?;
                 self.spawn_broker_reader(&broker)?;
                 // Need to replicate the pending partitions if there is any
                 replicate_pending_partitions_once(
                     &mut self.pending_replication_partitions,
                     &mut broker,
-                )?;
+                );
                 let broker_id = broker.id.clone();
                 brokers_lock.push(broker);
                 broker_id
             };
 
-        // Releaseing lock for broadcast_cluster_metadata
+        // Releaseing lock for commit
         drop(brokers_lock);
 
-        self.broadcast_cluster_metadata()?;
+        self.commit(snapshot)?;
 
         Ok(broker_id)
     }
@@ -164,6 +191,7 @@ impl DistributionManager {
             .map(|b| BrokerDetails {
                 id: b.id.clone(),
                 addr: b.addr.clone(),
+                rack: b.rack.clone(),
                 status: b.status,
                 partitions: b
                     .partitions
@@ -195,33 +223,110 @@ impl DistributionManager {
         })
     }
 
-    fn broadcast_cluster_metadata(&mut self) -> Result<(), String> {
+    fn snapshot(&self) -> ClusterSnapshot {
+        let brokers_lock = self.brokers.lock().unwrap();
+
+        ClusterSnapshot {
+            topics: self
+                .topics
+                .iter()
+                .map(|t| (t.clone(), t.lock().unwrap().clone()))
+                .collect(),
+            replicas: brokers_lock
+                .iter()
+                .map(|b| (b.id.clone(), b.partitions.clone()))
+                .collect(),
+            pending_replication_partitions: self.pending_replication_partitions.clone(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: ClusterSnapshot) {
+        self.topics = snapshot
+            .topics
+            .into_iter()
+            .map(|(topic, topic_before)| {
+                *topic.lock().unwrap() = topic_before;
+                topic
+            })
+            .collect();
+
+        let mut brokers_lock = self.brokers.lock().unwrap();
+
+        for broker in brokers_lock.iter_mut() {
+            broker.partitions = snapshot
+                .replicas
+                .iter()
+                .find(|(id, _)| *id == broker.id)
+                .map(|(_, partitions)| partitions.clone())
+                .unwrap_or_default();
+
+            // The broker may have gone down in the meantime
+            if broker.status == Status::Down {
+                broker.disconnect();
+            }
+        }
+
+        self.pending_replication_partitions = snapshot.pending_replication_partitions;
+    }
+
+    // Commits the changes made since `snapshot` was taken. The changes are acknowledged only once a quorum
+    // of Observers has them, only then they are saved, the new replicas are created and the brokers are told
+    // about them. Changes that can't be committed are rolled back.
+    fn commit(&mut self, snapshot: ClusterSnapshot) -> Result<(), String> {
         let metadata = self.get_cluster_metadata()?;
 
+        if let Some(raft) = &self.raft {
+            if let Err(e) = crate::raft::replicate(raft, metadata.clone()) {
+                let raft = raft.clone();
+                self.restore(snapshot);
+
+                // The proposed change may still be committed later on, the restored metadata supersedes it
+                if let Ok(restored_metadata) = self.get_cluster_metadata() {
+                    let _ = raft.lock().unwrap().propose(restored_metadata);
+                }
+
+                return Err(format!("{} The change has been rolled back.", e));
+            }
+        }
+
         self.save_cluster_state()?;
 
         let mut brokers = self.brokers.lock().unwrap();
 
+        create_replicas(&mut brokers);
+
         let mut broker_streams: Vec<_> = brokers
             .iter_mut()
             .filter_map(|b| b.stream.as_mut())
             .collect();
 
-        let mut followers_streams: Vec<_> = self.followers.iter_mut().collect();
-
-        println!("Followers: {:?}", followers_streams);
-
         let message = shared_structures::Message::ClusterMetadata { metadata };
 
-        println!("BROADCASTING!!!");
+        log::trace!(
+            "Broadcasting the cluster metadata to {} brokers",
+            broker_streams.len()
+        );
 
-        Broadcast::all(&mut followers_streams[..], &message)?;
         Broadcast::all(&mut broker_streams[..], &message)
     }
 
-    // Will return the name of created topic on success
-    pub fn create_topic(&mut self, topic_name: &str) -> Result<String, String> {
-        let brokers_lock = self.brokers.lock().unwrap();
+    /// Configuration a topic is created with unless specified otherwise, taken from the Observer's configuration.
+    pub fn default_topic_config(&self) -> TopicConfig {
+        TopicConfig::from_config(&self.config)
+    }
+
+    // Will return the name of created topic on success. All the `partition_count` partitions are created
+    // with the topic, if any of their replicas can't be placed the topic is not created at all.
+    pub fn create_topic(
+        &mut self,
+        topic_name: &str,
+        partition_count: usize,
+        topic_config: TopicConfig,
+    ) -> Result<String, String> {
+        topic_config.validate()?;
+
+        let snapshot = self.snapshot();
+        let mut brokers_lock = self.brokers.lock().unwrap();
 
         let available_brokers = brokers_lock
             .iter()
@@ -244,17 +349,301 @@ impl DistributionManager {
             return Err(format!("Topic `{}` already exist.", topic_name));
         }
 
-        let topic = Topic::new_shared(topic_name.to_string());
+        self.validate_dead_letter_topic(topic_name, &topic_config)?;
+
+        if partition_count > 0 && available_brokers < topic_config.replica_factor {
+            return Err(format!(
+                "Can't place {} replicas of every partition, only {} brokers are available.",
+                topic_config.replica_factor, available_brokers
+            ));
+        }
+
+        let mut topic = Topic::from(topic_name.to_string());
+        topic.config = topic_config;
+        let topic = Arc::new(Mutex::new(topic));
+
+        // Every replica of every partition is placed before any of them is created on the brokers,
+        // which happens only once the whole topic is committed
+        for _ in 0..partition_count {
+            place_partition(&topic, &mut brokers_lock);
+        }
+
         self.topics.push(topic);
 
+        // Releaseing lock for commit
+        drop(brokers_lock);
+
+        self.commit(snapshot)?;
+
         Ok(topic_name.to_string())
     }
 
+    /// Changes the configuration of an existing topic and propagates it to the brokers, returns the new configuration.
+    pub fn alter_topic(
+        &mut self,
+        topic_name: &str,
+        changes: &[(String, String)],
+    ) -> Result<TopicConfig, String> {
+        // Replicas are placed by the replica factor when the partitions are created
+        if changes.iter().any(|(key, _)| key == "replica_factor") {
+            return Err(
+                "`replica_factor` of an existing topic can't be changed, create a new topic instead."
+                    .to_string(),
+            );
+        }
+
+        let topic = self
+            .topics
+            .iter()
+            .find(|t| t.lock().unwrap().name == *topic_name)
+            .ok_or(format!("Topic `{}` doesn't exist.", topic_name))?;
+
+        let mut topic_config = topic.lock().unwrap().config.clone();
+        topic_config.apply(changes)?;
+        self.validate_dead_letter_topic(topic_name, &topic_config)?;
+
+        let topic = topic.clone();
+        let snapshot = self.snapshot();
+        topic.lock().unwrap().config = topic_config.clone();
+
+        self.commit(snapshot)?;
+
+        Ok(topic_config)
+    }
+
+    // Consumers route records to the dead-letter topic themselves, it has to exist beforehand
+    fn validate_dead_letter_topic(
+        &self,
+        topic_name: &str,
+        topic_config: &TopicConfig,
+    ) -> Result<(), String> {
+        let Some(dead_letter_topic) = &topic_config.dead_letter_topic else {
+            return Ok(());
+        };
+
+        if dead_letter_topic == topic_name {
+            return Err(format!(
+                "Topic `{}` can't be its own dead-letter topic.",
+                topic_name
+            ));
+        }
+
+        if !self
+            .topics
+            .iter()
+            .any(|t| t.lock().unwrap().name == *dead_letter_topic)
+        {
+            return Err(format!(
+                "Dead-letter topic `{}` doesn't exist.",
+                dead_letter_topic
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Deletes a topic with all of its partitions, brokers quarantine the replicas of the
+    /// topic once they receive the cluster metadata without them.
+    pub fn delete_topic(&mut self, topic_name: &str) -> Result<(), String> {
+        let topic_index = self
+            .topics
+            .iter()
+            .position(|t| t.lock().unwrap().name == *topic_name)
+            .ok_or(format!("Topic `{}` doesn't exist.", topic_name))?;
+
+        // Consumers of the other topic would have nowhere to route their records to
+        if let Some(dependent_topic) = self
+            .topics
+            .iter()
+            .map(|t| t.lock().unwrap())
+            .find(|t| t.config.dead_letter_topic.as_deref() == Some(topic_name))
+        {
+            return Err(format!(
+                "Topic `{}` is the dead-letter topic of `{}`, alter `{}` to remove it first.",
+                topic_name, dependent_topic.name, dependent_topic.name
+            ));
+        }
+
+        let snapshot = self.snapshot();
+        let topic = self.topics.remove(topic_index);
+
+        let mut brokers_lock = self.brokers.lock().unwrap();
+
+        for broker in brokers_lock.iter_mut() {
+            broker.partitions.retain(|p| !Arc::ptr_eq(&p.topic, &topic));
+        }
+
+        self.pending_replication_partitions
+            .retain(|(_, p)| !Arc::ptr_eq(&p.topic, &topic));
+
+        // Releaseing lock for commit
+        drop(brokers_lock);
+
+        self.commit(snapshot)
+    }
+
+    /// Moves the leadership off the replicas of a broker that is shutting down and marks it as down,
+    /// the broker is acknowledged once the rest of the cluster knows about the new leaders.
+    pub fn handle_broker_leaving(&mut self, broker_id: &str) -> Result<(), String> {
+        let snapshot = self.snapshot();
+        let mut brokers_lock = self.brokers.lock().unwrap();
+
+        let broker_index = brokers_lock
+            .iter()
+            .position(|b| b.id == broker_id)
+            .ok_or(format!("Broker {} doesn't exist.", broker_id))?;
+
+        for (partition_id, new_leader) in hand_off_leadership(&mut brokers_lock, broker_index) {
+            match new_leader {
+                Some(new_leader) => log::info!(
+                    "Leadership of partition {} has moved to broker {}",
+                    partition_id, new_leader
+                ),
+                None => log::warn!(
+                    "Partition {} has no other replica to take over its leadership, it stays offline until broker {} is back.",
+                    partition_id, broker_id
+                ),
+            }
+        }
+
+        brokers_lock[broker_index].disconnect();
+
+        // Releaseing lock for commit
+        drop(brokers_lock);
+
+        self.commit(snapshot)?;
+
+        let mut brokers_lock = self.brokers.lock().unwrap();
+
+        if let Some(stream) = brokers_lock
+            .iter_mut()
+            .find(|b| b.id == broker_id)
+            .and_then(|b| b.stream.as_mut())
+        {
+            Broadcast::to(stream, &Message::LeaveAcknowledged)?;
+        }
+
+        log::info!("Broker {} has left the cluster.", broker_id);
+
+        Ok(())
+    }
+
+    /// Describes a topic alongside the replicas of each of its partitions.
+    pub fn describe_topic(&self, topic_name: &str) -> Result<TopicDescription, String> {
+        let topic = self
+            .topics
+            .iter()
+            .find(|t| t.lock().unwrap().name == *topic_name)
+            .ok_or(format!("Topic `{}` doesn't exist.", topic_name))?;
+
+        let brokers_lock = self.brokers.lock().unwrap();
+
+        let mut partitions: Vec<PartitionDescription> = vec![];
+
+        let replicas = brokers_lock.iter().flat_map(|b| {
+            b.partitions
+                .iter()
+                .filter(|p| Arc::ptr_eq(&p.topic, topic))
+                .map(move |p| (b, p))
+        });
+
+        for (broker, replica) in replicas {
+            let replica_description = ReplicaDescription {
+                replica_id: replica.replica_id.clone(),
+                broker_id: broker.id.clone(),
+                role: replica.role,
+                status: replica.status,
+            };
+
+            match partitions.iter_mut().find(|p| p.id == replica.id) {
+                Some(partition) => partition.replicas.push(replica_description),
+                None => partitions.push(PartitionDescription {
+                    id: replica.id.clone(),
+                    partition_number: replica.partition_number,
+                    replicas: vec![replica_description],
+                }),
+            }
+        }
+
+        // Partitions that couldn't be placed on any broker yet
+        for (_, pending_partition) in self.pending_replication_partitions.iter() {
+            if Arc::ptr_eq(&pending_partition.topic, topic)
+                && partitions.iter().all(|p| p.id != pending_partition.id)
+            {
+                partitions.push(PartitionDescription {
+                    id: pending_partition.id.clone(),
+                    partition_number: pending_partition.partition_number,
+                    replicas: vec![],
+                });
+            }
+        }
+
+        partitions.sort_by_key(|p| p.partition_number);
+
+        Ok(TopicDescription {
+            topic: topic.lock().unwrap().clone(),
+            partitions,
+        })
+    }
+
+    pub fn describe_brokers(&self) -> Vec<BrokerDescription> {
+        let brokers_lock = self.brokers.lock().unwrap();
+
+        brokers_lock
+            .iter()
+            .map(|b| BrokerDescription {
+                id: b.id.clone(),
+                addr: b.addr.clone(),
+                rack: b.rack.clone(),
+                status: b.status,
+                last_heartbeat_ms: b.last_heartbeat.map(|h| h.elapsed().as_millis() as u64),
+                partitions: b.partitions.iter().map(|p| p.id.clone()).collect(),
+            })
+            .collect()
+    }
+
+    pub fn describe_broker(&self, broker_id: &str) -> Result<BrokerDescription, String> {
+        self.describe_brokers()
+            .into_iter()
+            .find(|b| b.id == broker_id)
+            .ok_or(format!("Broker `{}` doesn't exist.", broker_id))
+    }
+
+    pub fn describe_cluster(&self) -> Result<ClusterDescription, String> {
+        let under_replicated_partitions = self
+            .get_under_replicated_partitions()?
+            .into_iter()
+            .map(|(partition, replicas_up)| {
+                let topic_lock = partition.topic.lock().unwrap();
+                UnderReplicatedPartition {
+                    id: partition.id.clone(),
+                    topic: topic_lock.name.clone(),
+                    replicas_up,
+                    replica_factor: topic_lock.config.replica_factor,
+                }
+            })
+            .collect();
+
+        Ok(ClusterDescription {
+            brokers: self.describe_brokers(),
+            topics: self.list_topics(),
+            under_replicated_partitions,
+        })
+    }
+
+    pub fn list_topics(&self) -> Vec<Topic> {
+        self.topics
+            .iter()
+            .map(|t| t.lock().unwrap().clone())
+            .collect()
+    }
+
     // Need to rebalance if new partition is added to the broker
     pub fn create_partition(&mut self, topic_name: &str) -> Result<String, String> {
+        let snapshot = self.snapshot();
         let mut brokers_lock = self.brokers.lock().unwrap();
 
-        if brokers_lock.len() == 0 {
+        if brokers_lock.is_empty() {
             return Err(
                 "No brokers have been found, please make sure at least one broker is connected."
                     .to_string(),
@@ -266,17 +655,13 @@ impl DistributionManager {
             t.name == *topic_name
         });
 
-        let replica_factor = self
-            .config
-            .get_number("replica_factor")
-            .ok_or("Replica factor is not defined in the config, action aborted.")?;
-
         if let Some(topic) = topic {
             let mut topic_lock = topic.lock().unwrap();
             // We've got 1 partition, and N replications for each partition (where N brokers count)
             topic_lock.partition_count += 1;
 
             let partition = Partition::new(topic, topic_lock.partition_count);
+            let replica_factor = topic_lock.config.replica_factor;
 
             drop(topic_lock);
 
@@ -284,14 +669,14 @@ impl DistributionManager {
             replicate_partition(
                 &mut self.pending_replication_partitions,
                 &mut brokers_lock,
-                replica_factor as usize,
+                replica_factor,
                 &partition,
-            )?;
+            );
 
-            // Releaseing lock for broadcast_cluster_metadata
+            // Releaseing lock for commit
             drop(brokers_lock);
 
-            self.broadcast_cluster_metadata()?;
+            self.commit(snapshot)?;
 
             Ok(partition.id.clone())
 
@@ -301,14 +686,47 @@ impl DistributionManager {
         }
     }
 
+    // Returns every partition that has less replicas up than the replica factor of its topic,
+    // alongside the amount of its replicas that are currently up.
+    pub fn get_under_replicated_partitions(&self) -> Result<Vec<(Partition, usize)>, String> {
+        let brokers_lock = self.brokers.lock().unwrap();
+
+        let mut partitions: Vec<(Partition, usize)> = vec![];
+
+        for broker in brokers_lock.iter() {
+            for partition in broker.partitions.iter() {
+                let replica_up =
+                    usize::from(broker.status == Status::Up && partition.status == Status::Up);
+
+                match partitions.iter_mut().find(|(p, _)| p.id == partition.id) {
+                    Some((_, replicas_up)) => *replicas_up += replica_up,
+                    None => partitions.push((partition.clone(), replica_up)),
+                }
+            }
+        }
+
+        // Partitions that couldn't be placed on any broker yet
+        for (_, pending_partition) in self.pending_replication_partitions.iter() {
+            if partitions.iter().all(|(p, _)| p.id != pending_partition.id) {
+                partitions.push((pending_partition.clone(), 0));
+            }
+        }
+
+        partitions.retain(|(p, replicas_up)| {
+            *replicas_up < p.topic.lock().unwrap().config.replica_factor
+        });
+
+        Ok(partitions)
+    }
+
     fn get_broker_metadata(
         &self,
         mut stream: TcpStream,
-    ) -> Result<(String, String, TcpStream), String> {
-        if let Message::BrokerConnectionDetails { id, addr } =
+    ) -> Result<(String, String, Option<String>, TcpStream), String> {
+        if let Message::BrokerConnectionDetails { id, addr, rack } =
             Reader::read_one_message(&mut stream)?
         {
-            Ok((id, addr, stream))
+            Ok((id, addr, rack, stream))
         } else {
             Err("Handshake with client failed, wrong message received from client.".to_string())
         }
@@ -319,17 +737,46 @@ impl DistributionManager {
     // Each Partition should have a replica_id which is unique per replica to find it if such case occures.
 
     // TODO: What happens when a broker has lost connection? We need to find a new leader for all partition leaders.
+    pub fn get_session_timeout(&self) -> Duration {
+        self.config.session_timeout
+    }
+
+    // Marks brokers which haven't sent a heartbeat within the session timeout as down, this catches
+    // hung brokers and network partitions where the TCP connection itself stays open.
+    fn spawn_session_monitor(&self) {
+        let brokers = Arc::downgrade(&self.brokers);
+        let session_timeout = self.get_session_timeout();
+
+        std::thread::spawn(move || loop {
+            std::thread::sleep(session_timeout / 4);
+
+            // Distribution manager is gone
+            let Some(brokers) = brokers.upgrade() else {
+                break;
+            };
+
+            let mut brokers_lock = brokers.lock().unwrap();
+
+            for broker_id in expire_sessions(&mut brokers_lock, session_timeout) {
+                log::warn!(
+                    "Broker {} has not sent a heartbeat for {}ms, marking it as down.",
+                    broker_id,
+                    session_timeout.as_millis()
+                );
+            }
+        });
+    }
+
     fn spawn_broker_reader(&self, broker: &Broker) -> Result<(), String> {
         if let Some(broker_stream) = &broker.stream {
             let watch_stream = broker_stream.try_clone().map_err(|e| e.to_string())?;
+            let peer_addr = watch_stream.peer_addr().ok();
 
             let brokers = Arc::clone(&self.brokers);
+            let distribution_manager = self.this.clone();
             let broker_id = broker.id.clone();
 
-            let throttle = self
-                .config
-                .get_number("throttle")
-                .ok_or("Throttle is missing form the configuration file.")?;
+            let throttle = self.config.throttle;
 
             std::thread::spawn(move || {
                 let mut reader = BufReader::new(watch_stream);
@@ -339,20 +786,28 @@ impl DistributionManager {
                     let size = match reader.read_line(&mut buf) {
                         Ok(s) => s,
                         Err(e) => {
-                            println!("Error in broker read thread: {}", e);
-                            println!("Retrying to read with throttling at {}ms", throttle);
-                            std::thread::sleep(Duration::from_millis(throttle as u64));
+                            log::warn!(
+                                "Error in broker read thread, retrying with throttling at {}ms: {}",
+                                throttle.as_millis(),
+                                e
+                            );
+                            std::thread::sleep(throttle);
                             continue;
                         }
                     };
 
+                    let mut brokers_lock = brokers.lock().unwrap();
+
                     // TODO: Think what should happen to the metadata of the broker that has been disconnected.
                     if size == 0 {
-                        println!("Broker {} has disconnected.", broker_id);
-
-                        let mut brokers_lock = brokers.lock().unwrap();
+                        log::info!("Broker {} has disconnected.", broker_id);
 
                         if let Some(broker) = brokers_lock.iter_mut().find(|b| b.id == broker_id) {
+                            if !broker.is_connected_through(peer_addr) {
+                                // Session has expired or the broker has already reconnected
+                                break;
+                            }
+
                             broker.disconnect();
 
                             let offline_partitions: Vec<_> = broker
@@ -362,38 +817,92 @@ impl DistributionManager {
                                 .collect();
 
                             for offline_partition in offline_partitions.iter() {
-                                println!(
-                                    "Broker {}:\t{}\t{}\t{}",
+                                log::warn!(
+                                    "Broker {} took down replica {} of partition {} (replica count {})",
                                     broker.id,
-                                    offline_partition.0,
                                     offline_partition.1,
+                                    offline_partition.0,
                                     offline_partition.2
                                 );
                             }
                         } else {
-                            println!("Failed to find the Broker in the system, this can lead to major data loses.");
-                            println!("Please let us know about this message by creating an issue on our GitHub repository https://github.com/pwbh/nyx/issues/new");
+                            log::error!("Failed to find the Broker in the system, this can lead to major data loses.");
+                            log::error!("Please let us know about this message by creating an issue on our GitHub repository https://github.com/pwbh/nyx/issues/new");
                         }
                         break;
                     }
 
+                    // Every message from the broker is a sign of life, heartbeats included
+                    if let Some(broker) = brokers_lock
+                        .iter_mut()
+                        .find(|b| b.id == broker_id && b.is_connected_through(peer_addr))
+                    {
+                        broker.last_heartbeat = Some(Instant::now());
+                    }
+
+                    // The distribution manager is locked before the brokers everywhere else
+                    drop(brokers_lock);
+
+                    if let Ok(Message::BrokerLeaving) = MessageDecoder::decode(&buf) {
+                        log::info!("Broker {} is shutting down.", broker_id);
+
+                        if let Some(distribution_manager) = distribution_manager.upgrade() {
+                            if let Err(e) = distribution_manager
+                                .lock()
+                                .unwrap()
+                                .handle_broker_leaving(&broker_id)
+                            {
+                                log::warn!("Failed to let broker {} leave: {}", broker_id, e);
+                            }
+                        }
+                    }
+
                     buf.clear();
                 }
             });
             Ok(())
         } else {
-            println!("Ignoring spawning broker reader as Observer follower");
+            log::debug!("Ignoring spawning broker reader as Observer follower");
             Ok(())
         }
     }
 }
 
+// Creates the replicas which have been placed on the brokers but not created yet, replicas that fail to be
+// created are retried on the next commit.
+fn create_replicas(brokers: &mut [Broker]) {
+    for broker in brokers.iter_mut().filter(|b| b.stream.is_some()) {
+        let mut partitions = std::mem::take(&mut broker.partitions);
+
+        for replica in partitions
+            .iter_mut()
+            .filter(|p| p.status == Status::Created)
+        {
+            if let Err(e) = broadcast_replicate_partition(broker, replica) {
+                log::warn!(
+                    "Failed to create replica {} on broker {}: {}",
+                    replica.replica_id,
+                    broker.id,
+                    e
+                );
+            }
+        }
+
+        broker.partitions = partitions;
+    }
+}
+
 pub fn broadcast_replicate_partition(
     broker: &mut Broker,
     replica: &mut Partition,
 ) -> Result<(), String> {
     if let Some(broker_stream) = &mut broker.stream {
-        Broadcast::to(
+        let mut span = Span::start("observer replicate_partition", SpanKind::Client);
+        span.set_attribute("broker", broker.id.as_str());
+        span.set_attribute("partition", replica.id.as_str());
+        span.set_attribute("replica", replica.replica_id.as_str());
+
+        let result = Broadcast::to(
             broker_stream,
             &Message::CreatePartition {
                 id: replica.id.clone(),
@@ -401,10 +910,17 @@ pub fn broadcast_replicate_partition(
                 topic: replica.topic.lock().unwrap().clone(),
                 partition_number: replica.partition_number,
                 replica_count: replica.replica_count,
+                traceparent: span.context(),
             },
-        )?;
+        );
+
+        if let Err(e) = &result {
+            span.set_error(e);
+        }
+
+        result?;
     } else {
-        println!("Ignoring broadcasting message as Observer follower")
+        log::debug!("Ignoring broadcasting message as Observer follower")
     }
     // After successful creation of the partition on the broker,
     // we can set its status on the observer to Active.
@@ -413,36 +929,104 @@ pub fn broadcast_replicate_partition(
     Ok(())
 }
 
+// Expires the sessions of connected brokers which haven't been heard of within `session_timeout`,
+// returns the ids of the expired brokers.
+fn expire_sessions(brokers: &mut [Broker], session_timeout: Duration) -> Vec<String> {
+    brokers
+        .iter_mut()
+        .filter(|b| {
+            b.status == Status::Up
+                && b.stream.is_some()
+                && b.last_heartbeat
+                    .is_none_or(|h| h.elapsed() >= session_timeout)
+        })
+        .map(|b| {
+            b.expire_session();
+            b.id.clone()
+        })
+        .collect()
+}
+
+// Moves the leadership of every partition led by the broker at `leaving` to an up replica on another
+// broker, the replicas of the leaving broker become followers. Returns the ids of the partitions
+// alongside the id of the broker now leading them, `None` when no other replica could take over.
+fn hand_off_leadership(brokers: &mut [Broker], leaving: usize) -> Vec<(String, Option<String>)> {
+    let led_partitions: Vec<String> = brokers[leaving]
+        .partitions
+        .iter()
+        .filter(|p| p.role == Role::Leader)
+        .map(|p| p.id.clone())
+        .collect();
+
+    let mut handed_off = Vec::with_capacity(led_partitions.len());
+
+    for partition_id in led_partitions {
+        let new_leader = brokers
+            .iter_mut()
+            .enumerate()
+            .filter(|(i, b)| *i != leaving && b.status == Status::Up)
+            .find_map(|(_, b)| {
+                let replica = b
+                    .partitions
+                    .iter_mut()
+                    .find(|p| p.id == partition_id && p.status == Status::Up)?;
+                replica.role = Role::Leader;
+                Some(b.id.clone())
+            });
+
+        for replica in brokers[leaving]
+            .partitions
+            .iter_mut()
+            .filter(|p| p.id == partition_id)
+        {
+            replica.role = Role::Follower;
+        }
+
+        handed_off.push((partition_id, new_leader));
+    }
+
+    handed_off
+}
+
+// Places the pending replicas on a new broker, they are created once the change is committed
 fn replicate_pending_partitions_once(
     pending_replication_partitions: &mut Vec<(usize, Partition)>,
     new_broker: &mut Broker,
-) -> Result<(), String> {
+) {
     for (replications_needed, partition) in pending_replication_partitions.iter_mut().rev() {
-        let mut replica = Partition::replicate(partition, partition.replica_count + 1);
-        broadcast_replicate_partition(new_broker, &mut replica)?;
+        // A broker should never hold two replicas of the same partition
+        if *replications_needed == 0 || new_broker.partitions.iter().any(|p| p.id == partition.id) {
+            continue;
+        }
+
+        let replica = Partition::replicate(partition, partition.replica_count + 1);
         new_broker.partitions.push(replica);
         partition.replica_count += 1;
         *replications_needed -= 1;
     }
 
-    println!("{:#?}", pending_replication_partitions);
+    log::trace!("Pending replications: {:?}", pending_replication_partitions);
 
     // Remove totally replicated partitions
-    loop {
-        let current = pending_replication_partitions.last();
+    pending_replication_partitions.retain(|(pending_replications, _)| *pending_replications > 0);
+}
 
-        if let Some((pending_replications, _)) = current {
-            if *pending_replications == 0 {
-                pending_replication_partitions.pop();
-            } else {
-                break;
-            }
-        } else {
-            break;
-        }
+// Places all the replicas of the next partition of `topic`, the callers make sure that there are at least
+// as many brokers up as the replica factor of the topic, a new partition fits on any of them.
+fn place_partition(topic: &Arc<Mutex<Topic>>, brokers_lock: &mut MutexGuard<'_, Vec<Broker>>) {
+    let mut topic_lock = topic.lock().unwrap();
+    topic_lock.partition_count += 1;
+    let partition = Partition::new(topic, topic_lock.partition_count);
+    let replica_factor = topic_lock.config.replica_factor;
+    drop(topic_lock);
+
+    let selected_brokers = select_replica_brokers(brokers_lock, &partition, replica_factor);
+    debug_assert_eq!(selected_brokers.len(), replica_factor);
+
+    for (i, broker_index) in selected_brokers.into_iter().enumerate() {
+        let replica = Partition::replicate(&partition, i + 1);
+        brokers_lock[broker_index].partitions.push(replica);
     }
-
-    Ok(())
 }
 
 fn replicate_partition(
@@ -450,58 +1034,71 @@ fn replicate_partition(
     brokers_lock: &mut MutexGuard<'_, Vec<Broker>>,
     replica_factor: usize,
     partition: &Partition,
-) -> Result<(), String> {
-    // Here we create a variable containing the total available brokers in the cluster to check whether it is less
-    // then replication factor, if so we certain either way that we will be able to replicate to all partitions
-    let total_available_brokers = brokers_lock
-        .iter()
-        .filter(|b| b.status == Status::Up)
-        .count();
-    let mut future_replications_required = replica_factor as i32 - total_available_brokers as i32;
+) {
+    let selected_brokers = select_replica_brokers(brokers_lock, partition, replica_factor);
+
+    // Not enough distinct brokers are available at the moment, the rest of the replicas
+    // will be created once new brokers join the cluster.
+    let future_replications_required = replica_factor - selected_brokers.len();
 
     if future_replications_required > 0 {
-        // total_available_brokers - is the next replication that should be added by the count.
-        let replica = Partition::replicate(partition, total_available_brokers);
-        pending_replication_partitions.push((future_replications_required as usize, replica));
-    } else {
-        future_replications_required = 0;
+        // selected_brokers.len() - is the next replication that should be added by the count.
+        let replica = Partition::replicate(partition, selected_brokers.len());
+        pending_replication_partitions.push((future_replications_required, replica));
     }
 
-    let current_max_replications = replica_factor - future_replications_required as usize;
-
-    for replica_count in 1..=current_max_replications {
-        let least_distributed_broker = get_least_distributed_broker(brokers_lock, partition)?;
-        let mut replica = Partition::replicate(partition, replica_count);
-        broadcast_replicate_partition(least_distributed_broker, &mut replica)?;
-        least_distributed_broker.partitions.push(replica);
+    for (i, broker_index) in selected_brokers.into_iter().enumerate() {
+        let replica = Partition::replicate(partition, i + 1);
+        brokers_lock[broker_index].partitions.push(replica);
     }
-
-    Ok(())
 }
 
-fn get_least_distributed_broker<'a>(
-    brokers_lock: &'a mut MutexGuard<'_, Vec<Broker>>,
-    partition: &'a Partition,
-) -> Result<&'a mut Broker, String> {
-    let mut brokers_lock_iter = brokers_lock.iter().enumerate();
-
-    let first_element = brokers_lock_iter
-        .next()
-        .ok_or("At least 1 registerd broker is expected in the system.")?;
+// Returns the indexes of up to `replica_factor` distinct brokers that should receive a replica of `partition`.
+// Only brokers that are up and don't hold a replica of the partition yet are eligible. Brokers in racks with the
+// least replicas of the partition are preferred, ties are broken by the total amount of partitions on the broker.
+// As long as there are at least as many racks as replicas, no two replicas of a partition end up in the same rack.
+fn select_replica_brokers(
+    brokers: &[Broker],
+    partition: &Partition,
+    replica_factor: usize,
+) -> Vec<usize> {
+    let mut selected: Vec<usize> = Vec::with_capacity(replica_factor);
 
-    let mut least_distribured_broker_index: usize = first_element.0;
-    let mut least_distributed_broker: usize = first_element.1.partitions.len();
+    let replicas_in_rack = |rack: &str, selected: &[usize]| {
+        brokers
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.failure_domain() == rack)
+            .map(|(i, b)| {
+                let existing = b.partitions.iter().filter(|p| p.id == partition.id).count();
+                existing + usize::from(selected.contains(&i))
+            })
+            .sum::<usize>()
+    };
 
-    for (i, b) in brokers_lock_iter {
-        if b.partitions.iter().all(|p| p.id != partition.id)
-            && least_distributed_broker > b.partitions.len()
-        {
-            least_distributed_broker = b.partitions.len();
-            least_distribured_broker_index = i;
+    while selected.len() < replica_factor {
+        let candidate = brokers
+            .iter()
+            .enumerate()
+            .filter(|(i, b)| {
+                b.status == Status::Up
+                    && !selected.contains(i)
+                    && b.partitions.iter().all(|p| p.id != partition.id)
+            })
+            .min_by_key(|(_, b)| {
+                (
+                    replicas_in_rack(b.failure_domain(), &selected),
+                    b.partitions.len(),
+                )
+            });
+
+        match candidate {
+            Some((i, _)) => selected.push(i),
+            None => break,
         }
     }
 
-    Ok(&mut brokers_lock[least_distribured_broker_index])
+    selected
 }
 
 #[cfg(test)]
@@ -517,7 +1114,9 @@ mod tests {
         custom_path.push("/observer/");
         custom_path.push(custom_test_name);
 
-        let test_files_path = DirManager::get_base_dir(Some(&custom_path)).unwrap();
+        let test_files_path = DirManager::for_tests(Some(&custom_path))
+            .base_dir()
+            .unwrap();
         match fs::remove_dir_all(&test_files_path) {
             Ok(_) => {
                 println!("Deleted {:?}", test_files_path)
@@ -528,11 +1127,21 @@ mod tests {
     }
 
     fn config_mock() -> Config {
-        Config::from("../config/dev.properties".into()).unwrap()
+        let mut config = Config::from("../config/dev.properties".into()).unwrap();
+        config.data_dir = Some(DirManager::test_root());
+        config
+    }
+
+    fn create_topic(
+        distribution_manager: &mut DistributionManager,
+        topic_name: &str,
+    ) -> Result<String, String> {
+        let topic_config = distribution_manager.default_topic_config();
+        distribution_manager.create_topic(topic_name, 0, topic_config)
     }
 
     fn get_custom_test_name() -> String {
-        format!("test_{}", Uuid::new_v4().to_string())
+        format!("test_{}", Uuid::new_v4())
     }
 
     fn bootstrap_distribution_manager(
@@ -551,16 +1160,17 @@ mod tests {
         distribution_manager
     }
 
-    fn mock_connecting_broker(addr: &str) -> TcpStream {
+    fn mock_connecting_broker(addr: &str, rack: Option<&str>) -> TcpStream {
         let mut mock_stream = TcpStream::connect(addr).unwrap();
 
         let mut payload = serde_json::to_string(&Message::BrokerConnectionDetails {
             id: uuid::Uuid::new_v4().to_string(),
             addr: "localhost:123123".to_string(),
+            rack: rack.map(|r| r.to_string()),
         })
         .unwrap();
         payload.push('\n');
-        mock_stream.write(payload.as_bytes()).unwrap();
+        mock_stream.write_all(payload.as_bytes()).unwrap();
 
         let read_stream = mock_stream.try_clone().unwrap();
 
@@ -584,24 +1194,66 @@ mod tests {
         mock_stream
     }
 
+    // Connects a mock broker which sends the topic of every replica it is asked to create to `sender`
+    fn mock_connecting_broker_reporting_to(
+        sender: &std::sync::mpsc::Sender<String>,
+        addr: &str,
+    ) -> TcpStream {
+        let mut mock_stream = TcpStream::connect(addr).unwrap();
+
+        Broadcast::to(
+            &mut mock_stream,
+            &Message::BrokerConnectionDetails {
+                id: uuid::Uuid::new_v4().to_string(),
+                addr: "localhost:123123".to_string(),
+                rack: None,
+            },
+        )
+        .unwrap();
+
+        let read_stream = mock_stream.try_clone().unwrap();
+        let sender = sender.clone();
+
+        std::thread::spawn(move || {
+            let mut reader = BufReader::new(read_stream);
+            let mut buf = String::with_capacity(1024);
+
+            while reader.read_line(&mut buf).unwrap_or(0) > 0 {
+                if let Ok(Message::CreatePartition { topic, .. }) = MessageDecoder::decode(&buf) {
+                    let _ = sender.send(topic.name);
+                }
+
+                buf.clear();
+            }
+        });
+
+        mock_stream
+    }
+
     fn setup_distribution_for_tests(
         config: Config,
         port: &str,
         custom_test_name: &str,
     ) -> Arc<Mutex<DistributionManager>> {
-        let distribution_manager = bootstrap_distribution_manager(Some(config), &custom_test_name);
+        // Create 3 brokers to test the balancing of created partitions
+        setup_distribution_with_racks_for_tests(config, port, custom_test_name, &[None; 3])
+    }
+
+    fn setup_distribution_with_racks_for_tests(
+        config: Config,
+        port: &str,
+        custom_test_name: &str,
+        racks: &[Option<&str>],
+    ) -> Arc<Mutex<DistributionManager>> {
+        let distribution_manager = bootstrap_distribution_manager(Some(config), custom_test_name);
         let mut distribution_manager_lock = distribution_manager.lock().unwrap();
 
         let addr = format!("localhost:{}", port);
         let listener = TcpListener::bind(&addr).unwrap();
 
-        // Create 3 brokers to test the balancing of created partitions
-        mock_connecting_broker(&addr);
-        mock_connecting_broker(&addr);
-        mock_connecting_broker(&addr);
-
-        // Simulate acceptence of brokers
-        for _ in 0..3 {
+        // Simulate acceptence of brokers, one at a time so each broker keeps its rack
+        for rack in racks {
+            mock_connecting_broker(&addr, *rack);
             let stream = listener.incoming().next().unwrap().unwrap();
             distribution_manager_lock.connect_broker(stream).unwrap();
         }
@@ -628,7 +1280,7 @@ mod tests {
             stream
         });
 
-        mock_connecting_broker(&connection_addr);
+        mock_connecting_broker(&connection_addr, None);
 
         let stream = spawned_thread.join().unwrap();
 
@@ -651,9 +1303,7 @@ mod tests {
         let topic_name = "new_user_registered";
 
         // Before
-        let result = distribution_manager_lock
-            .create_topic(topic_name)
-            .unwrap_err();
+        let result = create_topic(&mut distribution_manager_lock, topic_name).unwrap_err();
 
         assert!(result.contains("No brokers have been found"));
 
@@ -673,30 +1323,26 @@ mod tests {
 
         let topic_name = "new_user_registered";
 
-        let topics_count_before_add = distribution_manager_lock.topics.iter().count();
+        let topics_count_before_add = distribution_manager_lock.topics.len();
 
-        distribution_manager_lock.create_topic(topic_name).unwrap();
+        create_topic(&mut distribution_manager_lock, topic_name).unwrap();
 
-        let topic_count_after_add = distribution_manager_lock.topics.iter().count();
+        let topic_count_after_add = distribution_manager_lock.topics.len();
 
         assert_eq!(topic_count_after_add, topics_count_before_add + 1);
 
         // We cant add the same topic name twice - Should error
-        let result = distribution_manager_lock
-            .create_topic(topic_name)
-            .unwrap_err();
+        let result = create_topic(&mut distribution_manager_lock, topic_name).unwrap_err();
 
         assert!(result.contains("already exist."));
 
-        let topics_count_before_add = distribution_manager_lock.topics.iter().count();
+        let topics_count_before_add = distribution_manager_lock.topics.len();
 
         let another_topic_name = "notification_resent";
 
-        distribution_manager_lock
-            .create_topic(another_topic_name)
-            .unwrap();
+        create_topic(&mut distribution_manager_lock, another_topic_name).unwrap();
 
-        let topic_count_after_add = distribution_manager_lock.topics.iter().count();
+        let topic_count_after_add = distribution_manager_lock.topics.len();
 
         assert_eq!(topic_count_after_add, topics_count_before_add + 1);
 
@@ -728,7 +1374,7 @@ mod tests {
         let custom_test_name = get_custom_test_name();
         let config = config_mock();
 
-        let replica_factor = config.get_number("replica_factor").unwrap();
+        let replica_factor = config.replica_factor;
 
         let distribution_manager = setup_distribution_for_tests(config, "5002", &custom_test_name);
         let mut distribution_manager_lock = distribution_manager.lock().unwrap();
@@ -736,9 +1382,7 @@ mod tests {
         let notifications_topic = "notifications";
 
         // Create 'notifications' topic
-        distribution_manager_lock
-            .create_topic(notifications_topic)
-            .unwrap();
+        create_topic(&mut distribution_manager_lock, notifications_topic).unwrap();
 
         // First partition for topic 'notifications'
         let partition_id_1 = distribution_manager_lock
@@ -747,7 +1391,7 @@ mod tests {
 
         let brokers_lock = distribution_manager_lock.brokers.lock().unwrap();
         let total_brokers_with_replicas = get_brokers_with_replicas(&brokers_lock, &partition_id_1);
-        assert_eq!(total_brokers_with_replicas, replica_factor as usize);
+        assert_eq!(total_brokers_with_replicas, replica_factor);
         drop(brokers_lock);
 
         // Second partition for topic 'notifications'
@@ -757,15 +1401,13 @@ mod tests {
 
         let brokers_lock = distribution_manager_lock.brokers.lock().unwrap();
         let total_brokers_with_replicas = get_brokers_with_replicas(&brokers_lock, &partition_id_2);
-        assert_eq!(total_brokers_with_replicas, replica_factor as usize);
+        assert_eq!(total_brokers_with_replicas, replica_factor);
         drop(brokers_lock);
 
         let comments_topic = "comments";
 
         // Create 'comments' topic
-        distribution_manager_lock
-            .create_topic(comments_topic)
-            .unwrap();
+        create_topic(&mut distribution_manager_lock, comments_topic).unwrap();
 
         // First partition for topic 'comments'
         let partition_id_3 = distribution_manager_lock
@@ -774,7 +1416,7 @@ mod tests {
 
         let brokers_lock = distribution_manager_lock.brokers.lock().unwrap();
         let total_brokers_with_replicas = get_brokers_with_replicas(&brokers_lock, &partition_id_3);
-        assert_eq!(total_brokers_with_replicas, replica_factor as usize);
+        assert_eq!(total_brokers_with_replicas, replica_factor);
         drop(brokers_lock);
 
         // Second partition for topic 'comments'
@@ -784,15 +1426,13 @@ mod tests {
 
         let brokers_lock = distribution_manager_lock.brokers.lock().unwrap();
         let total_brokers_with_replicas = get_brokers_with_replicas(&brokers_lock, &partition_id_4);
-        assert_eq!(total_brokers_with_replicas, replica_factor as usize);
+        assert_eq!(total_brokers_with_replicas, replica_factor);
         drop(brokers_lock);
 
         let friend_requests_topic = "friend_requests";
 
         // Create 'friend_requests' topic
-        distribution_manager_lock
-            .create_topic(friend_requests_topic)
-            .unwrap();
+        create_topic(&mut distribution_manager_lock, friend_requests_topic).unwrap();
 
         // First partition for topic 'friend_requests'
         let partition_id_5 = distribution_manager_lock
@@ -801,7 +1441,7 @@ mod tests {
 
         let brokers_lock = distribution_manager_lock.brokers.lock().unwrap();
         let total_brokers_with_replicas = get_brokers_with_replicas(&brokers_lock, &partition_id_5);
-        assert_eq!(total_brokers_with_replicas, replica_factor as usize);
+        assert_eq!(total_brokers_with_replicas, replica_factor);
         drop(brokers_lock);
 
         let mut unique_partitions = distribution_manager_lock
@@ -841,4 +1481,630 @@ mod tests {
 
         cleanup_after_test(&custom_test_name);
     }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn create_partition_spreads_replicas_across_racks() {
+        let custom_test_name = get_custom_test_name();
+        let config = config_mock();
+
+        let replica_factor = config.replica_factor;
+
+        // Two brokers per rack, the least loaded brokers alone would pick two brokers
+        // of the same rack since they are connected one after another.
+        let racks = [
+            Some("rack-a"),
+            Some("rack-a"),
+            Some("rack-b"),
+            Some("rack-b"),
+            Some("rack-c"),
+            Some("rack-c"),
+        ];
+
+        let distribution_manager =
+            setup_distribution_with_racks_for_tests(config, "5003", &custom_test_name, &racks);
+        let mut distribution_manager_lock = distribution_manager.lock().unwrap();
+
+        let notifications_topic = "notifications";
+
+        create_topic(&mut distribution_manager_lock, notifications_topic).unwrap();
+
+        for _ in 0..4 {
+            let partition_id = distribution_manager_lock
+                .create_partition(notifications_topic)
+                .unwrap();
+
+            let brokers_lock = distribution_manager_lock.brokers.lock().unwrap();
+
+            let mut racks_with_replicas: Vec<_> = brokers_lock
+                .iter()
+                .filter(|b| b.partitions.iter().any(|p| p.id == partition_id))
+                .map(|b| b.rack.clone().unwrap())
+                .collect();
+
+            assert_eq!(racks_with_replicas.len(), replica_factor);
+
+            racks_with_replicas.sort();
+            racks_with_replicas.dedup();
+
+            assert_eq!(racks_with_replicas.len(), replica_factor);
+        }
+
+        cleanup_after_test(&custom_test_name);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn list_under_replicated_partitions_when_not_enough_brokers() {
+        let custom_test_name = get_custom_test_name();
+        let config = config_mock();
+
+        let replica_factor = config.replica_factor;
+
+        // One broker less than the replica factor
+        let racks = vec![None; replica_factor - 1];
+
+        let distribution_manager =
+            setup_distribution_with_racks_for_tests(config, "5004", &custom_test_name, &racks);
+        let mut distribution_manager_lock = distribution_manager.lock().unwrap();
+
+        let notifications_topic = "notifications";
+
+        create_topic(&mut distribution_manager_lock, notifications_topic).unwrap();
+
+        let partition_id = distribution_manager_lock
+            .create_partition(notifications_topic)
+            .unwrap();
+
+        let brokers_lock = distribution_manager_lock.brokers.lock().unwrap();
+        let total_brokers_with_replicas = get_brokers_with_replicas(&brokers_lock, &partition_id);
+        assert_eq!(total_brokers_with_replicas, replica_factor - 1);
+        drop(brokers_lock);
+
+        let under_replicated_partitions = distribution_manager_lock
+            .get_under_replicated_partitions()
+            .unwrap();
+
+        assert_eq!(under_replicated_partitions.len(), 1);
+        assert_eq!(under_replicated_partitions[0].0.id, partition_id);
+        assert_eq!(under_replicated_partitions[0].1, replica_factor - 1);
+
+        cleanup_after_test(&custom_test_name);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn topic_config_is_set_on_create_and_altered() {
+        let custom_test_name = get_custom_test_name();
+        let config = config_mock();
+
+        let distribution_manager = setup_distribution_for_tests(config, "5006", &custom_test_name);
+        let mut distribution_manager_lock = distribution_manager.lock().unwrap();
+
+        let mut topic_config = distribution_manager_lock.default_topic_config();
+        topic_config.replica_factor = 1;
+
+        distribution_manager_lock
+            .create_topic("notifications", 0, topic_config)
+            .unwrap();
+
+        // Replicas are placed by the topic's replica factor rather than the global one
+        let partition_id = distribution_manager_lock
+            .create_partition("notifications")
+            .unwrap();
+        let brokers_lock = distribution_manager_lock.brokers.lock().unwrap();
+        assert_eq!(get_brokers_with_replicas(&brokers_lock, &partition_id), 1);
+        drop(brokers_lock);
+
+        let changes = vec![("retention_period".to_string(), "1h".to_string())];
+        let altered_config = distribution_manager_lock
+            .alter_topic("notifications", &changes)
+            .unwrap();
+        assert_eq!(altered_config.retention_period, Duration::from_secs(3600));
+
+        let cluster_metadata = distribution_manager_lock.get_cluster_metadata().unwrap();
+        assert_eq!(cluster_metadata.topics[0].config, altered_config);
+
+        let changes = vec![("replica_factor".to_string(), "2".to_string())];
+        assert!(distribution_manager_lock
+            .alter_topic("notifications", &changes)
+            .is_err());
+
+        cleanup_after_test(&custom_test_name);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn dead_letter_topic_has_to_exist() {
+        let custom_test_name = get_custom_test_name();
+        let config = config_mock();
+
+        let distribution_manager = setup_distribution_for_tests(config, "5012", &custom_test_name);
+        let mut distribution_manager_lock = distribution_manager.lock().unwrap();
+
+        let mut topic_config = distribution_manager_lock.default_topic_config();
+        topic_config.dead_letter_topic = Some("notifications-dlq".to_string());
+
+        assert!(distribution_manager_lock
+            .create_topic("notifications", 0, topic_config.clone())
+            .unwrap_err()
+            .contains("doesn't exist"));
+
+        let dead_letter_config = distribution_manager_lock.default_topic_config();
+        distribution_manager_lock
+            .create_topic("notifications-dlq", 0, dead_letter_config)
+            .unwrap();
+        distribution_manager_lock
+            .create_topic("notifications", 0, topic_config)
+            .unwrap();
+
+        let changes = vec![("dead_letter_topic".to_string(), "notifications".to_string())];
+        assert!(distribution_manager_lock
+            .alter_topic("notifications", &changes)
+            .is_err());
+
+        // A dead-letter topic in use can't be deleted
+        assert!(distribution_manager_lock
+            .delete_topic("notifications-dlq")
+            .unwrap_err()
+            .contains("is the dead-letter topic of `notifications`"));
+
+        let changes = vec![("dead_letter_topic".to_string(), String::new())];
+        distribution_manager_lock
+            .alter_topic("notifications", &changes)
+            .unwrap();
+        distribution_manager_lock
+            .delete_topic("notifications-dlq")
+            .unwrap();
+
+        cleanup_after_test(&custom_test_name);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn create_topic_with_partitions_places_all_replicas() {
+        let custom_test_name = get_custom_test_name();
+        let config = config_mock();
+
+        let distribution_manager = setup_distribution_for_tests(config, "5007", &custom_test_name);
+        let mut distribution_manager_lock = distribution_manager.lock().unwrap();
+
+        let mut topic_config = distribution_manager_lock.default_topic_config();
+        topic_config.replica_factor = 2;
+
+        distribution_manager_lock
+            .create_topic("notifications", 4, topic_config)
+            .unwrap();
+
+        let topic = distribution_manager_lock.topics[0].clone();
+        assert_eq!(topic.lock().unwrap().partition_count, 4);
+
+        let brokers_lock = distribution_manager_lock.brokers.lock().unwrap();
+        let replicas: Vec<_> = brokers_lock
+            .iter()
+            .flat_map(|b| b.partitions.iter())
+            .collect();
+        assert_eq!(replicas.len(), 8);
+
+        for partition_number in 1..=4 {
+            let partition = replicas
+                .iter()
+                .find(|p| p.partition_number == partition_number)
+                .unwrap();
+            assert_eq!(get_brokers_with_replicas(&brokers_lock, &partition.id), 2);
+        }
+
+        drop(brokers_lock);
+
+        cleanup_after_test(&custom_test_name);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn create_topic_fails_cleanly_when_replicas_cant_be_placed() {
+        let custom_test_name = get_custom_test_name();
+        let config = config_mock();
+
+        let distribution_manager = setup_distribution_with_racks_for_tests(
+            config,
+            "5008",
+            &custom_test_name,
+            &[None, None],
+        );
+        let mut distribution_manager_lock = distribution_manager.lock().unwrap();
+
+        let mut topic_config = distribution_manager_lock.default_topic_config();
+        topic_config.replica_factor = 3;
+
+        let error = distribution_manager_lock
+            .create_topic("notifications", 2, topic_config)
+            .unwrap_err();
+        assert!(error.contains("only 2 brokers are available"));
+
+        assert!(distribution_manager_lock.topics.is_empty());
+        let brokers_lock = distribution_manager_lock.brokers.lock().unwrap();
+        assert!(brokers_lock.iter().all(|b| b.partitions.is_empty()));
+        drop(brokers_lock);
+
+        cleanup_after_test(&custom_test_name);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn create_topic_creates_replicas_only_once_the_topic_is_committed() {
+        let custom_test_name = get_custom_test_name();
+        let distribution_manager = bootstrap_distribution_manager(None, &custom_test_name);
+        let mut distribution_manager_lock = distribution_manager.lock().unwrap();
+
+        let listener = TcpListener::bind("localhost:5011").unwrap();
+        let (sender, received) = std::sync::mpsc::channel();
+
+        // Brokers report every replica they are asked to create
+        let mut mock_streams = vec![];
+
+        for _ in 0..2 {
+            mock_streams.push(mock_connecting_broker_reporting_to(
+                &sender,
+                "localhost:5011",
+            ));
+            let stream = listener.incoming().next().unwrap().unwrap();
+            distribution_manager_lock.connect_broker(stream).unwrap();
+        }
+
+        let raft_dir = PathBuf::from(format!("/observer/{}/raft", custom_test_name));
+        distribution_manager_lock.raft = Some(Arc::new(Mutex::new(
+            Raft::new(
+                "localhost:1".to_string(),
+                vec!["localhost:2".to_string()],
+                DirManager::for_tests(Some(&raft_dir)),
+            )
+            .unwrap(),
+        )));
+
+        let mut topic_config = distribution_manager_lock.default_topic_config();
+        topic_config.replica_factor = 2;
+
+        // Not committed, nothing is left behind on either side
+        assert!(distribution_manager_lock
+            .create_topic("payments", 3, topic_config.clone())
+            .is_err());
+        assert!(distribution_manager_lock.topics.is_empty());
+        assert!(distribution_manager_lock
+            .brokers
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|b| b.partitions.is_empty()));
+
+        distribution_manager_lock.raft = None;
+        distribution_manager_lock
+            .create_topic("notifications", 3, topic_config)
+            .unwrap();
+
+        // Replicas of the rolled back topic would have been received first
+        for _ in 0..6 {
+            let topic_name = received
+                .recv_timeout(Duration::from_secs(5))
+                .expect("Every committed replica is created");
+            assert_eq!(topic_name, "notifications");
+        }
+
+        let brokers_lock = distribution_manager_lock.brokers.lock().unwrap();
+        assert!(brokers_lock
+            .iter()
+            .flat_map(|b| b.partitions.iter())
+            .all(|p| p.status == Status::Up));
+        drop(brokers_lock);
+
+        cleanup_after_test(&custom_test_name);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn changes_that_cant_be_committed_are_rolled_back() {
+        let custom_test_name = get_custom_test_name();
+        let config = config_mock();
+
+        let distribution_manager = setup_distribution_for_tests(config, "5010", &custom_test_name);
+        let mut distribution_manager_lock = distribution_manager.lock().unwrap();
+
+        let topic_config = distribution_manager_lock.default_topic_config();
+        distribution_manager_lock
+            .create_topic("notifications", 1, topic_config.clone())
+            .unwrap();
+
+        // A follower can't commit anything
+        let raft_dir = PathBuf::from(format!("/observer/{}/raft", custom_test_name));
+        distribution_manager_lock.raft = Some(Arc::new(Mutex::new(
+            Raft::new(
+                "localhost:1".to_string(),
+                vec!["localhost:2".to_string()],
+                DirManager::for_tests(Some(&raft_dir)),
+            )
+            .unwrap(),
+        )));
+
+        let metadata_before = distribution_manager_lock.get_cluster_metadata().unwrap();
+
+        let error = distribution_manager_lock
+            .alter_topic(
+                "notifications",
+                &[("retention_period".to_string(), "1d".to_string())],
+            )
+            .unwrap_err();
+        assert!(error.contains("rolled back"));
+        assert!(distribution_manager_lock
+            .create_partition("notifications")
+            .is_err());
+        assert!(distribution_manager_lock
+            .delete_topic("notifications")
+            .is_err());
+        assert!(distribution_manager_lock
+            .create_topic("payments", 1, topic_config)
+            .is_err());
+
+        let metadata_after = distribution_manager_lock.get_cluster_metadata().unwrap();
+        assert_eq!(metadata_after.topics, metadata_before.topics);
+        assert_eq!(
+            serde_json::to_string(&metadata_after.brokers).unwrap(),
+            serde_json::to_string(&metadata_before.brokers).unwrap()
+        );
+
+        cleanup_after_test(&custom_test_name);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn expire_sessions_marks_silent_brokers_down() {
+        let custom_test_name = get_custom_test_name();
+        let config = config_mock();
+
+        let distribution_manager = setup_distribution_for_tests(config, "5005", &custom_test_name);
+        let distribution_manager_lock = distribution_manager.lock().unwrap();
+        let mut brokers_lock = distribution_manager_lock.brokers.lock().unwrap();
+
+        let session_timeout = distribution_manager_lock.get_session_timeout();
+
+        // Brokers have just connected, their sessions are still valid
+        assert!(expire_sessions(&mut brokers_lock, session_timeout).is_empty());
+
+        let silent_broker_id = brokers_lock[0].id.clone();
+        brokers_lock[0].last_heartbeat = Some(Instant::now() - session_timeout);
+
+        assert_eq!(
+            expire_sessions(&mut brokers_lock, session_timeout),
+            vec![silent_broker_id]
+        );
+
+        assert_eq!(brokers_lock[0].status, Status::Down);
+        assert!(brokers_lock[0].stream.is_none());
+        assert!(brokers_lock[1..].iter().all(|b| b.status == Status::Up));
+
+        drop(brokers_lock);
+        drop(distribution_manager_lock);
+
+        cleanup_after_test(&custom_test_name);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn delete_topic_removes_its_partitions() {
+        let custom_test_name = get_custom_test_name();
+        let config = config_mock();
+
+        let distribution_manager = setup_distribution_for_tests(config, "5009", &custom_test_name);
+        let mut distribution_manager_lock = distribution_manager.lock().unwrap();
+
+        let mut topic_config = distribution_manager_lock.default_topic_config();
+        topic_config.replica_factor = 2;
+
+        distribution_manager_lock
+            .create_topic("notifications", 2, topic_config.clone())
+            .unwrap();
+        distribution_manager_lock
+            .create_topic("payments", 1, topic_config)
+            .unwrap();
+
+        let description = distribution_manager_lock
+            .describe_topic("notifications")
+            .unwrap();
+        assert_eq!(description.partitions.len(), 2);
+        assert_eq!(description.partitions[0].partition_number, 1);
+        assert!(description.partitions.iter().all(|p| p.replicas.len() == 2));
+
+        distribution_manager_lock
+            .delete_topic("notifications")
+            .unwrap();
+
+        assert_eq!(
+            distribution_manager_lock
+                .list_topics()
+                .iter()
+                .map(|t| t.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["payments"]
+        );
+        assert!(distribution_manager_lock
+            .describe_topic("notifications")
+            .is_err());
+        assert!(distribution_manager_lock
+            .delete_topic("notifications")
+            .is_err());
+
+        let brokers_lock = distribution_manager_lock.brokers.lock().unwrap();
+        let replicas: Vec<_> = brokers_lock
+            .iter()
+            .flat_map(|b| b.partitions.iter())
+            .collect();
+        assert_eq!(replicas.len(), 2);
+        assert!(replicas
+            .iter()
+            .all(|p| p.topic.lock().unwrap().name == "payments"));
+
+        drop(brokers_lock);
+
+        cleanup_after_test(&custom_test_name);
+    }
+
+    #[test]
+    fn leaving_broker_hands_off_leadership_to_up_replicas() {
+        let topic = Arc::new(Mutex::new(Topic::from("notifications".to_string())));
+        let first_partition = Partition::new(&topic, 1);
+        let second_partition = Partition::new(&topic, 2);
+
+        let mut brokers: Vec<Broker> = (0..3)
+            .map(|i| {
+                Broker::from(
+                    format!("broker_{}", i),
+                    None,
+                    format!("localhost:{}", 7000 + i),
+                    None,
+                )
+                .unwrap()
+            })
+            .collect();
+
+        // Leaving broker leads both partitions, only the first one has another replica that is up
+        for (broker_index, partition, role) in [
+            (0, &first_partition, Role::Leader),
+            (0, &second_partition, Role::Leader),
+            (1, &first_partition, Role::Follower),
+            (2, &second_partition, Role::Follower),
+        ] {
+            let mut replica = Partition::replicate(partition, broker_index + 1);
+            replica.role = role;
+            replica.status = Status::Up;
+            brokers[broker_index].partitions.push(replica);
+        }
+
+        brokers[2].disconnect();
+
+        let handed_off = hand_off_leadership(&mut brokers, 0);
+
+        assert_eq!(
+            handed_off,
+            vec![
+                (first_partition.id.clone(), Some("broker_1".to_string())),
+                (second_partition.id.clone(), None)
+            ]
+        );
+        assert!(brokers[0]
+            .partitions
+            .iter()
+            .all(|p| p.role == Role::Follower));
+        assert_eq!(brokers[1].partitions[0].role, Role::Leader);
+        assert_eq!(brokers[2].partitions[0].role, Role::Follower);
+    }
+
+    mod placement {
+        use proptest::prelude::*;
+
+        use super::*;
+
+        fn mock_brokers(brokers: &[(Option<u8>, bool)]) -> Vec<Broker> {
+            brokers
+                .iter()
+                .enumerate()
+                .map(|(i, (rack, up))| {
+                    let mut broker = Broker::from(
+                        format!("broker_{}", i),
+                        None,
+                        format!("localhost:{}", 6000 + i),
+                        rack.map(|r| format!("rack_{}", r)),
+                    )
+                    .unwrap();
+
+                    if !up {
+                        broker.disconnect();
+                    }
+
+                    broker
+                })
+                .collect()
+        }
+
+        fn brokers_with_replicas<'a>(brokers: &'a [Broker], partition_id: &str) -> Vec<&'a Broker> {
+            brokers
+                .iter()
+                .filter(|b| b.partitions.iter().any(|p| p.id == partition_id))
+                .collect()
+        }
+
+        proptest! {
+            #[test]
+            #[cfg_attr(miri, ignore)]
+            fn replicas_are_placed_on_distinct_brokers(
+                brokers in prop::collection::vec((prop::option::of(0..4u8), any::<bool>()), 1..10),
+                replica_factor in 1..6usize,
+                partition_count in 1..8usize,
+            ) {
+                let brokers = Mutex::new(mock_brokers(&brokers));
+                let mut brokers_lock = brokers.lock().unwrap();
+                let mut pending_replication_partitions = vec![];
+
+                let up_brokers: Vec<_> = brokers_lock.iter().filter(|b| b.status == Status::Up).collect();
+                let mut failure_domains: Vec<_> = up_brokers.iter().map(|b| b.failure_domain()).collect();
+                failure_domains.sort();
+                failure_domains.dedup();
+                let up_brokers_count = up_brokers.len();
+                let failure_domains_count = failure_domains.len();
+
+                let topic = Topic::new_shared("notifications".to_string());
+                let mut partition_ids = vec![];
+
+                for partition_number in 1..=partition_count {
+                    let partition = Partition::new(&topic, partition_number);
+                    replicate_partition(
+                        &mut pending_replication_partitions,
+                        &mut brokers_lock,
+                        replica_factor,
+                        &partition,
+                    );
+                    partition_ids.push(partition.id);
+                }
+
+                for partition_id in partition_ids.iter() {
+                    let brokers_with_replicas = brokers_with_replicas(&brokers_lock, partition_id);
+
+                    prop_assert_eq!(brokers_with_replicas.len(), replica_factor.min(up_brokers_count));
+
+                    for broker in brokers_with_replicas.iter() {
+                        prop_assert_eq!(broker.status, Status::Up);
+                        prop_assert_eq!(
+                            broker.partitions.iter().filter(|p| p.id == *partition_id).count(),
+                            1
+                        );
+                    }
+
+                    if failure_domains_count >= replica_factor {
+                        let mut racks: Vec<_> = brokers_with_replicas.iter().map(|b| b.failure_domain()).collect();
+                        racks.sort();
+                        racks.dedup();
+                        prop_assert_eq!(racks.len(), brokers_with_replicas.len());
+                    }
+                }
+
+                // Brokers joining later complete the pending replications, still without duplicates
+                for i in 0..replica_factor {
+                    let mut new_broker = Broker::from(format!("new_broker_{}", i), None, "localhost:7000".to_string(), None).unwrap();
+                    replicate_pending_partitions_once(&mut pending_replication_partitions, &mut new_broker);
+                    brokers_lock.push(new_broker);
+                }
+
+                prop_assert!(pending_replication_partitions.is_empty());
+
+                for partition_id in partition_ids.iter() {
+                    let brokers_with_replicas = brokers_with_replicas(&brokers_lock, partition_id);
+                    prop_assert_eq!(brokers_with_replicas.len(), replica_factor);
+
+                    for broker in brokers_with_replicas.iter() {
+                        prop_assert_eq!(
+                            broker.partitions.iter().filter(|p| p.id == *partition_id).count(),
+                            1
+                        );
+                    }
+                }
+            }
+        }
+    }
 }
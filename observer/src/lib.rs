@@ -1,16 +1,20 @@
+pub mod admin;
 pub mod command_processor;
-pub mod config;
 pub mod distribution_manager;
+pub mod metrics;
+pub mod raft;
 
 use std::{
-    net::TcpListener,
+    net::{Shutdown, TcpListener},
+    path::Path,
     sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
-use command_processor::CommandProcessor;
-use config::Config;
+use command_processor::{read_script, Command, CommandProcessor, OnError, HISTORY_FILE};
 use distribution_manager::DistributionManager;
-use shared_structures::Role;
+use raft::Raft;
+use shared_structures::{AdminResponse, Config, Status};
 use sysinfo::{CpuExt, DiskExt, System, SystemExt};
 use uuid::Uuid;
 
@@ -18,47 +22,87 @@ pub const DEV_CONFIG: &str = "dev.properties";
 pub const PROD_CONFIG: &str = "prod.properties";
 
 const DEFAULT_PORT: u16 = 2828;
+// The admin API is served on the port of the Observer plus this offset unless `admin_addr` is set
+const ADMIN_PORT_OFFSET: u16 = 1000;
 
 pub const CLUSTER_FILE: &str = "cluster.json";
+pub const RAFT_FILE: &str = "raft.json";
 
 pub struct Observer {
     pub id: String,
-    pub role: Role,
+    pub raft: Arc<Mutex<Raft>>,
     pub listener: TcpListener,
+    pub admin_listener: TcpListener,
     pub distribution_manager: Arc<Mutex<DistributionManager>>,
     pub command_processor: CommandProcessor,
     pub system: System,
 }
 
 impl Observer {
-    pub fn from(
-        config_path: &str,
-        leader: Option<&String>,
-        name: Option<&String>,
-    ) -> Result<Self, String> {
-        let role = if leader.is_none() {
-            Role::Leader
-        } else {
-            Role::Follower
-        };
-
+    /// `peers` are the addresses of the other Observers in the cluster, the leader
+    /// among all of them is elected automatically.
+    pub fn from(config: Config, peers: &[String], name: Option<&String>) -> Result<Self, String> {
         let mut system = System::new_all();
 
-        let port: u16 = if let Ok(port) = std::env::var("PORT") {
-            port.parse::<u16>().unwrap_or(DEFAULT_PORT)
-        } else {
-            DEFAULT_PORT
-        };
-
-        let config = Config::from(config_path.into())?;
+        let port = config.port.unwrap_or(DEFAULT_PORT);
+        let bind_addr = format!("{}:{}", config.host, port);
+        // Only reachable from the machine of the Observer unless configured otherwise
+        let admin_addr = config.admin_addr.clone().unwrap_or(format!(
+            "127.0.0.1:{}",
+            port.saturating_add(ADMIN_PORT_OFFSET)
+        ));
+        // Address the other Observers know this Observer by
+        let advertised_addr = config.advertised_addr.clone().unwrap_or(bind_addr.clone());
+        let metrics_addr = config.metrics_addr.clone();
 
         let distribution_manager = DistributionManager::from(config, name)?;
 
-        let command_processor = CommandProcessor::new();
+        let history_file = distribution_manager
+            .lock()
+            .unwrap()
+            .cluster_dir
+            .base_dir()?
+            .join(HISTORY_FILE);
+
+        let mut command_processor = CommandProcessor::with_history_file(history_file)?;
+        command_processor.complete_from(distribution_manager.clone());
+
+        let listener = TcpListener::bind(&bind_addr).map_err(|e| e.to_string())?;
+        let admin_listener = TcpListener::bind(&admin_addr)
+            .map_err(|e| format!("Failed to bind the admin address {}: {}", admin_addr, e))?;
+
+        let cluster_dir = distribution_manager.lock().unwrap().cluster_dir.clone();
+
+        let raft = Arc::new(Mutex::new(Raft::new(
+            advertised_addr,
+            peers.to_vec(),
+            cluster_dir,
+        )?));
+
+        distribution_manager.lock().unwrap().raft = Some(raft.clone());
+
+        // A single Observer is elected right away, its log may hold a newer state than the cluster file
+        if let Some(metadata) = raft.lock().unwrap().take_leadership_metadata() {
+            distribution_manager
+                .lock()
+                .unwrap()
+                .load_cluster_state(&metadata)?;
+        }
+
+        raft::start(raft.clone(), distribution_manager.clone());
+
+        if let Some(metrics_addr) = metrics_addr {
+            let metrics_raft = raft.clone();
+            let metrics_distribution_manager = distribution_manager.clone();
+            let registry = distribution_manager.lock().unwrap().metrics.clone();
 
-        let host = format!("localhost:{}", port);
+            let metrics_addr =
+                shared_structures::metrics::serve(&metrics_addr, registry, move |registry| {
+                    metrics::collect(registry, &metrics_raft, &metrics_distribution_manager)
+                })?;
 
-        let listener = TcpListener::bind(host).map_err(|e| e.to_string())?;
+            log::info!("Metrics are served on http://{}/metrics", metrics_addr);
+        }
 
         system.refresh_all();
 
@@ -72,7 +116,7 @@ impl Observer {
         total_disk_utilization =
             (total_disk_utilization / system.disks().len() as f64) * 1.0 * 10f64.powf(-9.0);
 
-        println!("Disk space: {:.2} GiB", total_disk_utilization);
+        log::info!("Disk space: {:.2} GiB", total_disk_utilization);
         let mut total_cpu_utilization = 0f32;
 
         for cpu in system.cpus() {
@@ -81,17 +125,122 @@ impl Observer {
 
         total_cpu_utilization /= system.cpus().len() as f32;
 
-        println!("CPU utilization: {:.1}%", total_cpu_utilization);
+        log::info!("CPU utilization: {:.1}%", total_cpu_utilization);
 
         let observer = Self {
             id: Uuid::new_v4().to_string(),
-            role,
+            raft,
             distribution_manager,
             command_processor,
             listener,
+            admin_listener,
             system,
         };
 
         Ok(observer)
     }
+
+    /// Parses and executes a single command, commands that changed the cluster are persisted to the history.
+    pub fn execute_command(&mut self, raw_command: &str) -> Result<AdminResponse, String> {
+        let request = Command::from(raw_command)?.to_admin_request()?;
+        admin::execute(&self.raft, &self.distribution_manager, request)
+    }
+
+    /// Executes the commands of a script one after another, with `OnError::Stop` the first
+    /// failing command fails the whole script.
+    pub fn run_script(&mut self, path: &Path, on_error: OnError) -> Result<(), String> {
+        let commands = read_script(path)?;
+
+        for (line, raw_command) in commands {
+            println!("> {}", raw_command);
+
+            match self.execute_command(&raw_command) {
+                Ok(response) => print!("{}", response),
+                Err(e) => {
+                    let error = format!("{}:{}: {}", path.display(), line, e);
+
+                    if on_error == OnError::Stop {
+                        return Err(error);
+                    }
+
+                    println!("\x1b[38;5;1mERROR:\x1b[0m {}", error);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Saves the cluster state and disconnects the brokers, so that they look for another Observer right away.
+    /// A leader hands its leadership over to another Observer first.
+    pub fn shutdown(&mut self) -> Result<(), String> {
+        shutdown(&self.raft, &self.distribution_manager)
+    }
+
+    /// Blocks until at least `count` brokers are up, scripts creating partitions need the brokers connected.
+    pub fn wait_for_brokers(&self, count: usize) {
+        loop {
+            let brokers_up = self
+                .distribution_manager
+                .lock()
+                .unwrap()
+                .brokers
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|b| b.status == Status::Up)
+                .count();
+
+            if brokers_up >= count {
+                return;
+            }
+
+            log::info!("Waiting for brokers to connect ({}/{})", brokers_up, count);
+            std::thread::sleep(Duration::from_millis(1000));
+        }
+    }
+}
+
+/// Shuts down the Observer owning `raft` and `distribution_manager`, see [`Observer::shutdown`].
+/// Used by the signal handler, which has no access to the `Observer` itself.
+pub fn shutdown(
+    raft: &Arc<Mutex<Raft>>,
+    distribution_manager: &Arc<Mutex<DistributionManager>>,
+) -> Result<(), String> {
+    let mut raft_lock = raft.lock().unwrap();
+
+    if raft_lock.is_leader() && raft_lock.peers_count() > 0 {
+        raft_lock.transfer_leadership()?;
+        drop(raft_lock);
+
+        // The peer has to catch up before it runs for election, which takes up to a few heartbeats
+        let deadline = Instant::now() + raft::ELECTION_TIMEOUT_MAX;
+
+        while raft.lock().unwrap().is_leader() {
+            if Instant::now() >= deadline {
+                log::warn!("Leadership was not handed over in time, the Observers will elect a new leader once this one is gone.");
+                break;
+            }
+
+            std::thread::sleep(raft::TICK);
+        }
+    } else {
+        drop(raft_lock);
+    }
+
+    let distribution_manager_lock = distribution_manager.lock().unwrap();
+
+    distribution_manager_lock.save_cluster_state()?;
+
+    for broker in distribution_manager_lock.brokers.lock().unwrap().iter() {
+        if let Some(stream) = &broker.stream {
+            // The broker may have disconnected already
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    // Spans of the last requests would be lost with the process otherwise
+    shared_structures::tracing::flush();
+
+    Ok(())
 }
@@ -1,296 +1,369 @@
 use clap::{arg, command};
-use observer::{distribution_manager::DistributionManager, Observer, DEV_CONFIG, PROD_CONFIG};
-use shared_structures::{println_c, Broadcast, EntityType, Message, MessageDecoder, Reader, Role};
+use observer::{
+    command_processor::{help::help, Input, OnError},
+    distribution_manager::DistributionManager,
+    raft::Raft,
+    Observer, DEV_CONFIG, PROD_CONFIG,
+};
+use shared_structures::{logger, tracing, Broadcast, Config, EntityType, Message, Reader};
 use std::{
-    io::{BufRead, BufReader},
     net::TcpStream,
-    sync::{Arc, Mutex, MutexGuard},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
 };
 
-// TODO: Leader should delegate all messages to followers, for example it should delegate create broker commands to followers, etc.
 fn main() -> Result<(), String> {
     let default_config_path_by_env = get_config_path_by_env();
     let matches = command!().arg(
         clap::Arg::new("config")
         .required(false)
     ).arg(
-        arg!(-f --follow <HOST> "Runs the Observer as a follower for leader located at <HOST>, Host MUST by booted without -f flag.")
+        arg!(-p --peers <PEERS> "List of the other Observers in the cluster seperated by comma e.g. localhost:2829,localhost:2830, the leader is elected automatically among all of them.")
+        .required(false)
+    ).arg(
+        arg!(-d --"data-dir" <DIR> "Directory the Observer stores its data in instead of ~/.config/nyx. Overrides `data_dir` of the configuration.")
+        .required(false)
+    ).arg(
+        arg!(--"metrics-addr" <ADDR> "Address the Prometheus metrics are served on at /metrics, e.g. localhost:9100. Overrides `metrics_addr` of the configuration.")
+        .required(false)
+    ).arg(
+        arg!(--"admin-addr" <ADDR> "Address nyx-admin connects to, defaults to the loopback interface on the port of the Observer plus 1000. Overrides `admin_addr` of the configuration.")
+        .required(false)
+    ).arg(
+        arg!(--"trace-exporter" <DEST> "OTLP/HTTP endpoint, e.g. http://localhost:4318, or file the trace spans of the admin requests and replica creations are exported to. Overrides `trace_exporter` of the configuration.")
+        .required(false)
+    ).arg(
+        arg!(-n --name <NAME> "Assigns a name to the Observer, its cluster metadata and raft state are kept under `observer/<NAME>` of the data directory. Required with `--peers`, every Observer of a cluster running on the same machine needs a name of its own.")
+        .required(false)
+    ).arg(
+        arg!(-i --"init-script" <PATH> "File of commands executed at startup, one command per line. The history of the Observer can be replayed by passing its history.nyx file.")
+        .required(false)
+    ).arg(
+        arg!(--"on-error" <ACTION> "What happens when a command of the init script fails, `stop` fails the startup while `continue` executes the rest of the script.")
         .required(false)
+        .value_parser(["stop", "continue"])
+        .default_value("stop")
     ).arg(
-        arg!(-n --name <NAME> "Assigns a name to the broker, names are useful if you want to run two brokers on the same machine. Useful for nyx maintainers testing multi-node features.")
+        arg!(--"wait-for-brokers" <COUNT> "Number of brokers to wait for before executing the init script.")
         .required(false)
+        .value_parser(clap::value_parser!(usize))
+    ).arg(
+        arg!(--"non-interactive" "Doesn't read commands from stdin, the Observer is administered through nyx-admin only.")
+    ).arg(
+        arg!(--"log-level" <LEVEL> "Most verbose level of the logs written to stderr.")
+        .required(false)
+        .value_parser(logger::LEVELS)
+        .default_value("info")
+    ).arg(
+        arg!(--"log-format" <FORMAT> "Format of the logs, `json` writes one object per line for log collectors.")
+        .required(false)
+        .value_parser(logger::FORMATS)
+        .default_value("text")
     ).get_matches();
 
-    let leader = matches.get_one::<String>("follow");
+    logger::init(
+        matches.get_one::<String>("log-level").unwrap(),
+        matches.get_one::<String>("log-format").unwrap(),
+    )?;
+
+    let peers: Vec<String> = matches
+        .get_one::<String>("peers")
+        .map(|p| {
+            p.split_terminator(',')
+                .map(|p| p.trim().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
     let name = matches.get_one::<String>("name");
 
-    if leader.is_some() && name.is_none() {
-        return Err("Name should be provided if following functionality enabled.".to_string());
+    if !peers.is_empty() && name.is_none() {
+        return Err("Name should be provided if peers are provided.".to_string());
     }
 
     let config_path = matches
         .get_one::<String>("config")
         .unwrap_or(&default_config_path_by_env);
 
-    let mut observer = Observer::from(config_path, leader, name)?;
-
-    if observer.role == Role::Leader {
-        println_c(
-            &format!(
-                "Observer is ready to accept brokers on port {}",
-                observer.listener.local_addr().unwrap().port()
-            ),
-            35,
-        );
-    } else if let Some(name) = name {
-        println_c(&format!("Started following leader as {}", name), 50)
-    } else {
-        println_c("Started following leader", 50)
+    let mut observer = match load_config(
+        config_path,
+        matches.get_one::<String>("data-dir"),
+        matches.get_one::<String>("metrics-addr"),
+        matches.get_one::<String>("admin-addr"),
+        matches.get_one::<String>("trace-exporter"),
+    )
+    .and_then(|config| {
+        if let Some(trace_exporter) = &config.trace_exporter {
+            tracing::init("observer", trace_exporter)?;
+            log::info!("Trace spans are exported to {}", trace_exporter);
+        }
+
+        Observer::from(config, &peers, name)
+    }) {
+        Ok(observer) => observer,
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    log::info!(
+        "Observer is ready to accept brokers on port {}",
+        observer.listener.local_addr().unwrap().port()
+    );
+
+    log::info!(
+        "Admin clients are served on {}",
+        observer.admin_listener.local_addr().unwrap()
+    );
+
+    if !peers.is_empty() {
+        log::info!("Electing a leader among the Observers {:?}", peers);
     }
 
+    let signal_raft = observer.raft.clone();
+    let signal_distribution_manager = observer.distribution_manager.clone();
+    let shutting_down = AtomicBool::new(false);
+
+    // SIGINT / SIGTERM shut the Observer down like EXIT does, a second signal exits right away
+    ctrlc::set_handler(move || {
+        if shutting_down.swap(true, Ordering::SeqCst) {
+            log::error!("Observer was forced to exit before shutting down.");
+            std::process::exit(1);
+        }
+
+        log::info!("Shutting down the Observer...");
+
+        // The handler has to return to receive the second signal
+        let raft = signal_raft.clone();
+        let distribution_manager = signal_distribution_manager.clone();
+
+        std::thread::spawn(move || {
+            if let Err(e) = observer::shutdown(&raft, &distribution_manager) {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
+
+            log::info!("Observer has been shut down.");
+            std::process::exit(0);
+        });
+    })
+    .map_err(|e| format!("Failed to set the signal handler: {}", e))?;
+
     let mut connections_distribution_manager = observer.distribution_manager.clone();
+    let connections_raft = observer.raft.clone();
+
+    let listener = observer.listener.try_clone().map_err(|e| e.to_string())?;
 
     // Connections listener
     std::thread::spawn(move || loop {
-        let connection = observer.listener.incoming().next();
+        let connection = listener.incoming().next();
 
         if let Some(stream) = connection {
             match stream {
                 Ok(mut stream) => {
-                    println!("stream: {:#?}", stream);
+                    log::debug!("Connection from {:?}", stream.peer_addr().ok());
                     if let Ok(message) = Reader::read_one_message(&mut stream) {
                         match message {
                             Message::EntityWantsToConnect {
                                 entity_type: EntityType::Observer,
                             } => {
-                                match handle_connect_observer_follower(
-                                    &mut connections_distribution_manager,
+                                if let Err(e) = observer::raft::serve_peer(
+                                    connections_raft.clone(),
+                                    connections_distribution_manager.clone(),
                                     stream,
                                 ) {
-                                    Ok(observer_follower_id) => {
-                                        println!(
-                                            "Observer follower connected {}",
-                                            observer_follower_id
-                                        )
-                                    }
-                                    Err(e) => {
-                                        println!("Error while establishing connection: {}", e)
-                                    }
+                                    log::warn!("Error while establishing connection: {}", e)
                                 }
                             }
                             Message::EntityWantsToConnect {
                                 entity_type: EntityType::Broker,
                             } => match handle_connect_broker(
                                 &mut connections_distribution_manager,
+                                &connections_raft,
                                 stream,
                             ) {
-                                Ok(broker_id) => println!("Broker {} connected", broker_id),
+                                Ok(broker_id) => log::info!("Broker {} connected", broker_id),
                                 Err(e) => {
-                                    println!("Error while establishing connection: {}", e)
+                                    log::warn!("Error while establishing connection: {}", e)
                                 }
                             },
                             _ => {
-                                println!("Handhsake failed, message could not be verified from connecting entity.")
+                                log::warn!("Handshake failed, message could not be verified from connecting entity.")
                             }
                         }
                     } else {
-                        println!("Could not decode the provided message, skipping connection.")
+                        log::warn!("Could not decode the provided message, skipping connection.")
                     }
                 }
-                Err(e) => println!("Failed to establish basic TCP connection: {}", e),
+                Err(e) => log::warn!("Failed to establish basic TCP connection: {}", e),
             }
         }
     });
 
-    let mut followers_distribution_manager = observer.distribution_manager.clone();
-
-    println!("{:?}", leader);
-
-    // Leader obsrver exists, enabling the follower functionality, instead of
-    // the leader functionality which is able  to create partitions, create topics etc
-    if let Some(leader) = leader.cloned() {
-        // TODO: connect to leader
-        let mut leader_stream = TcpStream::connect(leader).unwrap();
+    let admin_distribution_manager = observer.distribution_manager.clone();
+    let admin_raft = observer.raft.clone();
+
+    let admin_listener = observer
+        .admin_listener
+        .try_clone()
+        .map_err(|e| e.to_string())?;
+
+    // Admin listener, kept apart from the brokers and Observers
+    std::thread::spawn(move || {
+        for stream in admin_listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::warn!("Failed to establish basic TCP connection: {}", e);
+                    continue;
+                }
+            };
 
-        match Broadcast::to(
-            &mut leader_stream,
-            &shared_structures::Message::EntityWantsToConnect {
-                entity_type: EntityType::Observer,
-            },
-        ) {
-            Ok(_) => println!("Sent connection request to leader."),
-            Err(e) => {
-                return Err(format!("Failed connecting to leader: {}", e));
+            log::debug!("Admin connection from {:?}", stream.peer_addr().ok());
+
+            match Reader::read_one_message(&mut stream) {
+                Ok(Message::EntityWantsToConnect {
+                    entity_type: EntityType::Admin,
+                }) => {
+                    if let Err(e) = observer::admin::serve_admin(
+                        admin_raft.clone(),
+                        admin_distribution_manager.clone(),
+                        stream,
+                    ) {
+                        log::warn!("Error while establishing connection: {}", e)
+                    }
+                }
+                _ => {
+                    log::warn!("Handshake failed, only admin clients connect to the admin address.")
+                }
             }
-        };
-
-        let mut reader: BufReader<&mut TcpStream> = BufReader::new(&mut leader_stream);
-        let mut buf = String::with_capacity(1024);
-
-        loop {
-            // TODO: constantly read delegated messages from leader
-            let bytes_read = reader
-                .read_line(&mut buf)
-                .map_err(|e| format!("Leader follower error: {}", e))?;
+        }
+    });
 
-            if bytes_read == 0 {
-                println!("Leader has closed connection. Exiting.");
-                break;
-            }
+    if let Some(init_script) = matches.get_one::<String>("init-script") {
+        if let Some(count) = matches.get_one::<usize>("wait-for-brokers") {
+            observer.wait_for_brokers(*count);
+        }
 
-            match handle_delegated_message(&buf, &mut followers_distribution_manager) {
-                Ok(_) => println!("Received delgated cluster metadata successfully"),
-                Err(e) => println!("Cluster metadata delegation error: {}", e),
-            };
+        let on_error = match matches.get_one::<String>("on-error").unwrap().as_str() {
+            "continue" => OnError::Continue,
+            _ => OnError::Stop,
+        };
 
-            buf.clear();
-        }
-    } else {
-        // This will make sure our main thread will never exit until the user will issue an EXIT command by himself
-        loop {
-            match observer.command_processor.process_raw_command() {
-                Ok(command) => match command {
-                    observer::command_processor::Command {
-                        name: observer::command_processor::CommandName::Create,
-                        ..
-                    } => {
-                        match handle_create_command(&mut observer.distribution_manager, &command) {
-                            Ok(()) => println!("\x1b[38;5;2mOK\x1b[0m"),
-                            Err(e) => println!("\x1b[38;5;1mERROR:\x1b[0m {}", e),
-                        }
-                    }
-                    observer::command_processor::Command {
-                        name: observer::command_processor::CommandName::List,
-                        ..
-                    } => match handle_list_command(&mut observer.distribution_manager, &command) {
-                        Ok(()) => println!("\x1b[38;5;2mOK\x1b[0m"),
-                        Err(e) => println!("\x1b[38;5;1mERROR:\x1b[0m {}", e),
-                    },
-                },
-                Err(e) => println!("\x1b[38;5;1mERROR:\x1b[0m {}", e),
-            };
+        if let Err(e) = observer.run_script(Path::new(init_script), on_error) {
+            log::error!("{}", e);
+            std::process::exit(1);
         }
     }
 
-    Ok(())
-}
-
-fn get_config_path_by_env() -> String {
-    let file_name = if cfg!(debug_assertions) {
-        DEV_CONFIG
-    } else {
-        PROD_CONFIG
-    };
+    if !matches.get_flag("non-interactive") {
+        read_commands(&mut observer);
+    }
 
-    format!("./config/{}", file_name)
+    // Keeps serving brokers and admin clients
+    loop {
+        std::thread::park();
+    }
 }
 
-fn handle_delegated_message(
-    raw_message: &str,
-    distribution_manager: &mut Arc<Mutex<DistributionManager>>,
-) -> Result<(), String> {
-    let delegated_message = MessageDecoder::decode(raw_message)?;
-
-    println!("Delegated messaeg: {:?}", delegated_message);
+// Executes the commands typed into the prompt until stdin is closed
+fn read_commands(observer: &mut Observer) {
+    loop {
+        let raw_command = match observer.command_processor.read_input() {
+            Ok(Input::Command(raw_command)) => raw_command,
+            Ok(Input::Help(command)) => {
+                match help(command.as_deref()) {
+                    Ok(text) => print!("{}", text),
+                    Err(e) => println!("\x1b[38;5;1mERROR:\x1b[0m {}", e),
+                }
+                continue;
+            }
+            Ok(Input::Exit) => {
+                if let Err(e) = observer.shutdown() {
+                    log::error!("{}", e);
+                    std::process::exit(1);
+                }
+                log::info!("Observer has been shut down.");
+                std::process::exit(0);
+            }
+            Ok(Input::Closed) => {
+                log::info!("Stdin has been closed, the Observer keeps running non-interactively.");
+                return;
+            }
+            Err(e) => {
+                println!("\x1b[38;5;1mERROR:\x1b[0m {}", e);
+                continue;
+            }
+        };
 
-    match delegated_message {
-        Message::ClusterMetadata { metadata } => {
-            let mut distribution_manager_lock = distribution_manager.lock().unwrap();
-            distribution_manager_lock.load_cluster_state(&metadata)?;
-            distribution_manager_lock.save_cluster_state()
-        }
-        _ => Err("Could not read delegated cluster metadata".to_string()),
+        match observer.execute_command(&raw_command) {
+            Ok(response) => {
+                print!("{}", response);
+                println!("\x1b[38;5;2mOK\x1b[0m")
+            }
+            Err(e) => println!("\x1b[38;5;1mERROR:\x1b[0m {}", e),
+        };
     }
 }
 
-fn handle_list_command(
-    distribution_manager: &mut Arc<Mutex<DistributionManager>>,
-    command: &observer::command_processor::Command,
-) -> Result<(), String> {
-    println!("TRYING TO AQUIRE LOCK!");
-    let distribution_manager_lock = distribution_manager.lock().unwrap();
-    println!("LOCK AQUIRED!!!");
-    let level = command.arguments.first().unwrap();
-
-    if level == "ALL" {
-        print_list_all(&distribution_manager_lock);
-    } else {
-        return Err(format!(
-            "Requested listing depth `{}` is not supported",
-            level
-        ));
+fn load_config(
+    config_path: &str,
+    data_dir: Option<&String>,
+    metrics_addr: Option<&String>,
+    admin_addr: Option<&String>,
+    trace_exporter: Option<&String>,
+) -> Result<Config, String> {
+    let mut config = Config::from(config_path.into())?;
+
+    if let Some(data_dir) = data_dir {
+        config.data_dir = Some(data_dir.into());
     }
 
-    Ok(())
-}
-
-fn print_list_all(distribution_manager_lock: &MutexGuard<'_, DistributionManager>) {
-    let brokers_lock = distribution_manager_lock.brokers.lock().unwrap();
+    if let Some(metrics_addr) = metrics_addr {
+        config.metrics_addr = Some(metrics_addr.clone());
+    }
 
-    println!(".");
-    for broker in brokers_lock.iter() {
-        println!("├── Broker {}", broker.id);
-        for partition in broker.partitions.iter() {
-            println!("│   ├── Partition {}", partition.id)
-        }
+    if let Some(admin_addr) = admin_addr {
+        config.admin_addr = Some(admin_addr.clone());
     }
-}
 
-fn handle_create_command(
-    distribution_manager: &mut Arc<Mutex<DistributionManager>>,
-    command: &observer::command_processor::Command,
-) -> Result<(), String> {
-    let mut arguments_iter = command.arguments.iter();
-
-    match arguments_iter.next() {
-        Some(entity) => match entity.trim() {
-            "TOPIC" => handle_create_topic(distribution_manager, &mut arguments_iter),
-            "PARTITION" => handle_create_partition(distribution_manager, &mut arguments_iter),
-            _ => Err("Unrecognized entity has been provided.".to_string()),
-        },
-        None => Err("Entity type was not provided.".to_string()),
+    if let Some(trace_exporter) = trace_exporter {
+        config.trace_exporter = Some(trace_exporter.clone());
     }
+
+    Ok(config)
 }
 
-fn handle_connect_observer_follower(
-    distribution_manager: &mut Arc<Mutex<DistributionManager>>,
-    stream: TcpStream,
-) -> Result<String, String> {
-    let mut distribution_manager_lock = distribution_manager.lock().unwrap();
-    let stream_addr = stream.peer_addr().map_err(|e| e.to_string())?;
-    distribution_manager_lock.followers.push(stream);
-    Ok(stream_addr.ip().to_string())
+fn get_config_path_by_env() -> String {
+    let file_name = if cfg!(debug_assertions) {
+        DEV_CONFIG
+    } else {
+        PROD_CONFIG
+    };
+
+    format!("./config/{}", file_name)
 }
 
 fn handle_connect_broker(
     distribution_manager: &mut Arc<Mutex<DistributionManager>>,
-    stream: TcpStream,
+    raft: &Arc<Mutex<Raft>>,
+    mut stream: TcpStream,
 ) -> Result<String, String> {
-    let mut distribution_manager_lock = distribution_manager.lock().unwrap();
-    distribution_manager_lock.connect_broker(stream)
-}
+    let raft_lock = raft.lock().unwrap();
+
+    // Only the leader manages brokers, others point the broker to the leader
+    if !raft_lock.is_leader() {
+        let leader_addr = raft_lock.leader_addr.clone();
+        drop(raft_lock);
+        Broadcast::to(&mut stream, &Message::NotLeader { leader_addr })?;
+        return Err("Broker has been redirected to the leader Observer.".to_string());
+    }
 
-fn handle_create_topic(
-    distribution_manager: &mut Arc<Mutex<DistributionManager>>,
-    arguments_iter: &mut std::slice::Iter<'_, String>,
-) -> Result<(), String> {
-    let topic_name = arguments_iter
-        .next()
-        .ok_or("Please provide topic name for which you want to create the topic.".to_string())?;
-    let mut distribution_manager_lock: std::sync::MutexGuard<'_, DistributionManager> =
-        distribution_manager.lock().unwrap();
-    distribution_manager_lock.create_topic(topic_name)?;
-    Ok(())
-}
+    drop(raft_lock);
 
-fn handle_create_partition(
-    distribution_manager: &mut Arc<Mutex<DistributionManager>>,
-    arguments_iter: &mut std::slice::Iter<'_, String>,
-) -> Result<(), String> {
-    let topic_name = arguments_iter.next().ok_or(
-        "Please provide a valid topic name for which you want to create a partition.".to_string(),
-    )?;
     let mut distribution_manager_lock = distribution_manager.lock().unwrap();
-    distribution_manager_lock.create_partition(topic_name)?;
-    Ok(())
+    distribution_manager_lock.connect_broker(stream)
 }
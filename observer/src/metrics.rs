@@ -0,0 +1,201 @@
+use std::sync::{Arc, Mutex};
+
+use shared_structures::{
+    metrics::{Metric, MetricKind},
+    AdminRequest, Metrics, Status,
+};
+
+use crate::{distribution_manager::DistributionManager, raft::Raft};
+
+pub const BROKERS: Metric = Metric {
+    name: "nyx_observer_brokers",
+    help: "Brokers known to the cluster by status",
+    kind: MetricKind::Gauge,
+};
+
+pub const TOPICS: Metric = Metric {
+    name: "nyx_observer_topics",
+    help: "Topics of the cluster",
+    kind: MetricKind::Gauge,
+};
+
+pub const PARTITIONS: Metric = Metric {
+    name: "nyx_observer_partitions",
+    help: "Partitions of a topic",
+    kind: MetricKind::Gauge,
+};
+
+pub const UNDER_REPLICATED_PARTITIONS: Metric = Metric {
+    name: "nyx_observer_under_replicated_partitions",
+    help: "Partitions of a topic with less replicas up than the replica factor of the topic",
+    kind: MetricKind::Gauge,
+};
+
+pub const IS_LEADER: Metric = Metric {
+    name: "nyx_observer_is_leader",
+    help: "Whether the Observer is the leader of the Observers, 1 when it is",
+    kind: MetricKind::Gauge,
+};
+
+pub const RAFT_TERM: Metric = Metric {
+    name: "nyx_observer_raft_term",
+    help: "Current raft term of the Observer",
+    kind: MetricKind::Gauge,
+};
+
+pub const RAFT_COMMIT_INDEX: Metric = Metric {
+    name: "nyx_observer_raft_commit_index",
+    help: "Index of the last metadata change committed by a quorum of Observers",
+    kind: MetricKind::Gauge,
+};
+
+pub const ADMIN_REQUEST_DURATION: Metric = Metric {
+    name: "nyx_observer_admin_request_duration_seconds",
+    help: "Time taken to execute an admin request, including the replication of metadata changes",
+    kind: MetricKind::Histogram,
+};
+
+pub const ADMIN_REQUESTS_FAILED: Metric = Metric {
+    name: "nyx_observer_admin_requests_failed_total",
+    help: "Admin requests that failed",
+    kind: MetricKind::Counter,
+};
+
+/// Label of the request in the admin request metrics.
+pub fn request_name(request: &AdminRequest) -> &'static str {
+    match request {
+        AdminRequest::CreateTopic { .. } => "create_topic",
+        AdminRequest::CreatePartition { .. } => "create_partition",
+        AdminRequest::AlterTopic { .. } => "alter_topic",
+        AdminRequest::DeleteTopic { .. } => "delete_topic",
+        AdminRequest::ListTopics => "list_topics",
+        AdminRequest::ListBrokers => "list_brokers",
+        AdminRequest::DescribeTopic { .. } => "describe_topic",
+        AdminRequest::DescribeBroker { .. } => "describe_broker",
+        AdminRequest::DescribeCluster => "describe_cluster",
+    }
+}
+
+/// Refreshes the gauges read from the state of the Observer, called before every metrics scrape.
+pub fn collect(
+    metrics: &Metrics,
+    raft: &Arc<Mutex<Raft>>,
+    distribution_manager: &Arc<Mutex<DistributionManager>>,
+) {
+    let raft_lock = raft.lock().unwrap();
+    metrics.set(&IS_LEADER, &[], f64::from(u8::from(raft_lock.is_leader())));
+    metrics.set(&RAFT_TERM, &[], raft_lock.current_term() as f64);
+    metrics.set(&RAFT_COMMIT_INDEX, &[], raft_lock.commit_index() as f64);
+    drop(raft_lock);
+
+    let distribution_manager_lock = distribution_manager.lock().unwrap();
+
+    let brokers = distribution_manager_lock.describe_brokers();
+    let brokers_up = brokers.iter().filter(|b| b.status == Status::Up).count();
+    metrics.set(&BROKERS, &[("status", "up")], brokers_up as f64);
+    metrics.set(
+        &BROKERS,
+        &[("status", "down")],
+        (brokers.len() - brokers_up) as f64,
+    );
+
+    let topics = distribution_manager_lock.list_topics();
+    metrics.set(&TOPICS, &[], topics.len() as f64);
+
+    let under_replicated_partitions = distribution_manager_lock
+        .get_under_replicated_partitions()
+        .unwrap_or_default();
+
+    // Deleted topics shouldn't linger around
+    metrics.clear(&PARTITIONS);
+    metrics.clear(&UNDER_REPLICATED_PARTITIONS);
+
+    for topic in topics.iter() {
+        let labels = [("topic", topic.name.as_str())];
+        let under_replicated = under_replicated_partitions
+            .iter()
+            .filter(|(p, _)| p.topic.lock().unwrap().name == topic.name)
+            .count();
+
+        metrics.set(&PARTITIONS, &labels, topic.partition_count as f64);
+        metrics.set(
+            &UNDER_REPLICATED_PARTITIONS,
+            &labels,
+            under_replicated as f64,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+    };
+
+    use shared_structures::{Config, DirManager};
+
+    use super::*;
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn scrapes_the_observer_metrics() {
+        let data_dir = DirManager::test_root().join(format!("metrics_{}", uuid::Uuid::new_v4()));
+        let config = Config {
+            data_dir: Some(data_dir.clone()),
+            ..Config::default()
+        };
+
+        let distribution_manager = DistributionManager::from(config, None).unwrap();
+        // A single Observer is the leader right away
+        let raft = Arc::new(Mutex::new(
+            Raft::new(
+                "localhost:1".to_string(),
+                vec![],
+                DirManager::with_root(Some(&data_dir), None),
+            )
+            .unwrap(),
+        ));
+
+        let registry = distribution_manager.lock().unwrap().metrics.clone();
+        let collect_raft = raft.clone();
+        let collect_distribution_manager = distribution_manager.clone();
+
+        let addr = shared_structures::metrics::serve("localhost:0", registry, move |registry| {
+            collect(registry, &collect_raft, &collect_distribution_manager)
+        })
+        .unwrap();
+
+        crate::admin::execute(&raft, &distribution_manager, AdminRequest::ListTopics).unwrap();
+        assert!(crate::admin::execute(
+            &raft,
+            &distribution_manager,
+            AdminRequest::DescribeTopic {
+                name: "missing".to_string()
+            }
+        )
+        .is_err());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        for expected in [
+            "nyx_observer_is_leader 1\n",
+            "nyx_observer_brokers{status=\"up\"} 0\n",
+            "nyx_observer_topics 0\n",
+            "nyx_observer_admin_request_duration_seconds_count{request=\"list_topics\"} 1\n",
+            "nyx_observer_admin_requests_failed_total{request=\"describe_topic\"} 1\n",
+        ] {
+            assert!(
+                response.contains(expected),
+                "{} is missing in {}",
+                expected,
+                response
+            );
+        }
+
+        let _ = std::fs::remove_dir_all(data_dir);
+    }
+}
@@ -0,0 +1,1004 @@
+use std::time::{Duration, Instant};
+
+use shared_structures::{DirManager, LogEntry, Message, Metadata};
+use uuid::Uuid;
+
+mod peer;
+
+pub use peer::{replicate, serve_peer, start};
+
+use crate::RAFT_FILE;
+
+pub const TICK: Duration = Duration::from_millis(50);
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(300);
+pub const ELECTION_TIMEOUT_MIN: Duration = Duration::from_millis(1500);
+pub const ELECTION_TIMEOUT_MAX: Duration = Duration::from_millis(3000);
+// Maximum amount of entries sent to a follower in a single AppendEntries message
+const MAX_ENTRIES_PER_APPEND: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum State {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+// State that has to survive restarts, it is saved before answering any request
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+struct PersistentState {
+    current_term: u64,
+    voted_for: Option<String>,
+    // Latest committed entry, the entries up to it are compacted into it. Every entry holds
+    // a full snapshot of the metadata, so nothing else has to be kept of them.
+    #[serde(default)]
+    snapshot: Option<LogEntry>,
+    // Entries following the snapshot
+    log: Vec<LogEntry>,
+}
+
+#[derive(Debug)]
+struct PeerProgress {
+    addr: String,
+    next_index: u64,
+    match_index: u64,
+    vote_requested: bool,
+    last_sent: Option<Instant>,
+}
+
+/// Raft consensus among the Observers of the cluster over the log of metadata changes.
+///
+/// `Raft` only holds the state of the algorithm, network I/O is driven by the threads
+/// spawned in [`start`] and [`serve_peer`], which makes the algorithm itself testable
+/// without any sockets involved. Observers are identified by the address they listen on.
+#[derive(Debug)]
+pub struct Raft {
+    pub addr: String,
+    pub state: State,
+    pub leader_addr: Option<String>,
+    persistent: PersistentState,
+    commit_index: u64,
+    last_applied: u64,
+    peers: Vec<PeerProgress>,
+    votes_granted: usize,
+    election_deadline: Instant,
+    // Metadata the Observer should load into its distribution manager after becoming the leader
+    pending_leadership_metadata: Option<Metadata>,
+    // Peer the leadership is being handed over to, the leader stops accepting changes meanwhile
+    transfer_target: Option<usize>,
+    dir_manager: DirManager,
+}
+
+impl Raft {
+    pub fn new(addr: String, peers: Vec<String>, dir_manager: DirManager) -> Result<Self, String> {
+        let persistent = dir_manager
+            .open_optional::<PersistentState>(RAFT_FILE)?
+            .unwrap_or_default();
+
+        // Only committed entries are compacted
+        let commit_index = persistent.snapshot.as_ref().map_or(0, |s| s.index);
+
+        let peers = peers
+            .into_iter()
+            .filter(|p| *p != addr)
+            .map(|addr| PeerProgress {
+                addr,
+                next_index: 1,
+                match_index: 0,
+                vote_requested: false,
+                last_sent: None,
+            })
+            .collect();
+
+        let mut raft = Self {
+            addr,
+            state: State::Follower,
+            leader_addr: None,
+            persistent,
+            commit_index,
+            last_applied: 0,
+            peers,
+            votes_granted: 0,
+            election_deadline: Instant::now() + random_election_timeout(),
+            pending_leadership_metadata: None,
+            transfer_target: None,
+            dir_manager,
+        };
+
+        // A single Observer is a quorum by itself, no need to wait for an election timeout
+        if raft.peers.is_empty() {
+            raft.start_election()?;
+        }
+
+        Ok(raft)
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.state == State::Leader
+    }
+
+    pub fn current_term(&self) -> u64 {
+        self.persistent.current_term
+    }
+
+    pub fn commit_index(&self) -> u64 {
+        self.commit_index
+    }
+
+    pub fn peers_count(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn peer_addr(&self, peer: usize) -> &str {
+        &self.peers[peer].addr
+    }
+
+    fn quorum(&self) -> usize {
+        let cluster_size = self.peers.len() + 1;
+        cluster_size / 2 + 1
+    }
+
+    fn snapshot_index(&self) -> u64 {
+        self.persistent.snapshot.as_ref().map_or(0, |s| s.index)
+    }
+
+    fn last_log_index(&self) -> u64 {
+        self.snapshot_index() + self.persistent.log.len() as u64
+    }
+
+    fn last_log_term(&self) -> u64 {
+        self.term_at(self.last_log_index())
+    }
+
+    // Entries before the snapshot have been compacted away
+    fn entry_at(&self, index: u64) -> Option<&LogEntry> {
+        match &self.persistent.snapshot {
+            Some(snapshot) if snapshot.index == index => Some(snapshot),
+            _ => index
+                .checked_sub(self.snapshot_index() + 1)
+                .and_then(|i| self.persistent.log.get(i as usize)),
+        }
+    }
+
+    fn term_at(&self, index: u64) -> u64 {
+        self.entry_at(index).map(|e| e.term).unwrap_or(0)
+    }
+
+    // Compacts the committed entries into the snapshot, so the log and the file it is saved to
+    // don't grow with every change. Returns whether the log has changed.
+    fn compact(&mut self) -> bool {
+        if self.commit_index <= self.snapshot_index() {
+            return false;
+        }
+
+        let compacted = (self.commit_index - self.snapshot_index()) as usize;
+        self.persistent.snapshot = self.persistent.log.drain(..compacted).next_back();
+
+        true
+    }
+
+    fn persist(&self) -> Result<(), String> {
+        self.dir_manager.save(RAFT_FILE, &self.persistent)
+    }
+
+    /// Starts an election once the leader hasn't been heard of for the election timeout.
+    pub fn tick(&mut self, now: Instant) -> Result<(), String> {
+        if self.state != State::Leader && now >= self.election_deadline {
+            self.start_election()?;
+        }
+
+        Ok(())
+    }
+
+    fn start_election(&mut self) -> Result<(), String> {
+        self.persistent.current_term += 1;
+        self.persistent.voted_for = Some(self.addr.clone());
+        self.state = State::Candidate;
+        self.leader_addr = None;
+        self.votes_granted = 1;
+        self.election_deadline = Instant::now() + random_election_timeout();
+
+        for peer in self.peers.iter_mut() {
+            peer.vote_requested = false;
+        }
+
+        self.persist()?;
+
+        log::info!(
+            "Observer {} started an election for term {}",
+            self.addr,
+            self.persistent.current_term
+        );
+
+        if self.votes_granted >= self.quorum() {
+            self.become_leader()?;
+        }
+
+        Ok(())
+    }
+
+    fn become_leader(&mut self) -> Result<(), String> {
+        self.state = State::Leader;
+        self.leader_addr = Some(self.addr.clone());
+
+        let next_index = self.last_log_index() + 1;
+
+        for peer in self.peers.iter_mut() {
+            peer.next_index = next_index;
+            peer.match_index = 0;
+            peer.last_sent = None;
+        }
+
+        log::info!(
+            "Observer {} became the leader for term {}",
+            self.addr,
+            self.persistent.current_term
+        );
+
+        // Entries of previous terms can only be committed through an entry of the current term,
+        // the latest known metadata is appended again for that purpose.
+        if let Some(last_entry) = self
+            .persistent
+            .log
+            .last()
+            .or(self.persistent.snapshot.as_ref())
+        {
+            let metadata = last_entry.metadata.clone();
+            self.pending_leadership_metadata = Some(metadata.clone());
+            self.propose(metadata)?;
+        }
+
+        Ok(())
+    }
+
+    fn step_down(&mut self, term: u64) -> Result<(), String> {
+        if term > self.persistent.current_term {
+            self.persistent.current_term = term;
+            self.persistent.voted_for = None;
+            self.persist()?;
+        }
+
+        if self.state == State::Leader {
+            self.leader_addr = None;
+            // Its election timer hasn't run while leading, the new leader should be heard of first
+            self.election_deadline = Instant::now() + random_election_timeout();
+        }
+
+        self.state = State::Follower;
+        self.pending_leadership_metadata = None;
+        self.transfer_target = None;
+
+        Ok(())
+    }
+
+    /// Appends a metadata change to the log, only the leader accepts changes.
+    /// Returns the index of the appended entry.
+    pub fn propose(&mut self, metadata: Metadata) -> Result<u64, String> {
+        if !self.is_leader() {
+            return Err(self.not_leader_error());
+        }
+
+        if self.transfer_target.is_some() {
+            return Err(
+                "Observer is handing its leadership over, please retry once a new leader is elected."
+                    .to_string(),
+            );
+        }
+
+        let index = self.last_log_index() + 1;
+
+        self.persistent.log.push(LogEntry {
+            term: self.persistent.current_term,
+            index,
+            metadata,
+        });
+
+        // A single Observer commits the entry right away
+        self.advance_commit_index();
+        self.compact();
+        self.persist()?;
+
+        Ok(index)
+    }
+
+    /// Hands the leadership over to the most up to date peer, the peer is told to start an election
+    /// once it has every entry of the leader's log. Returns the address of the chosen peer.
+    pub fn transfer_leadership(&mut self) -> Result<String, String> {
+        if !self.is_leader() {
+            return Err(self.not_leader_error());
+        }
+
+        let peer = self
+            .peers
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, p)| p.match_index)
+            .map(|(i, _)| i)
+            .ok_or("Observer has no peers to hand its leadership over to.".to_string())?;
+
+        self.transfer_target = Some(peer);
+
+        log::info!(
+            "Observer {} is handing its leadership over to {}",
+            self.addr,
+            self.peers[peer].addr
+        );
+
+        Ok(self.peers[peer].addr.clone())
+    }
+
+    pub fn not_leader_error(&self) -> String {
+        match &self.leader_addr {
+            Some(leader_addr) => format!(
+                "Observer is not the leader, please issue the command on the leader at {}.",
+                leader_addr
+            ),
+            None => "Observer is not the leader and no leader has been elected yet.".to_string(),
+        }
+    }
+
+    fn advance_commit_index(&mut self) {
+        let quorum = self.quorum();
+
+        for index in (self.commit_index + 1..=self.last_log_index()).rev() {
+            // Only entries of the current term are committed by counting replicas
+            if self.term_at(index) != self.persistent.current_term {
+                break;
+            }
+
+            let replicas = 1 + self.peers.iter().filter(|p| p.match_index >= index).count();
+
+            if replicas >= quorum {
+                self.commit_index = index;
+                break;
+            }
+        }
+    }
+
+    /// Returns the message that should be sent to `peer` at the moment, if any.
+    pub fn request_for_peer(&mut self, peer: usize, now: Instant) -> Option<Message> {
+        let term = self.persistent.current_term;
+        let last_log_index = self.last_log_index();
+        let last_log_term = self.last_log_term();
+
+        match self.state {
+            State::Leader => {
+                let progress = &self.peers[peer];
+
+                let heartbeat_due = progress
+                    .last_sent
+                    .is_none_or(|s| now.duration_since(s) >= HEARTBEAT_INTERVAL);
+
+                if progress.next_index > last_log_index && !heartbeat_due {
+                    return None;
+                }
+
+                // The entries the peer is missing have been compacted
+                if progress.next_index <= self.snapshot_index() {
+                    self.peers[peer].last_sent = Some(now);
+
+                    return Some(Message::InstallSnapshot {
+                        term,
+                        leader_addr: self.addr.clone(),
+                        snapshot: self.persistent.snapshot.clone()?,
+                    });
+                }
+
+                // The chosen peer has caught up, it can win the election with the votes of the rest
+                if self.transfer_target == Some(peer)
+                    && progress.match_index == last_log_index
+                    && heartbeat_due
+                {
+                    self.peers[peer].last_sent = Some(now);
+                    return Some(Message::TimeoutNow { term });
+                }
+
+                let prev_log_index = progress.next_index - 1;
+                let prev_log_term = self.term_at(prev_log_index);
+
+                let entries: Vec<_> = self
+                    .persistent
+                    .log
+                    .iter()
+                    .skip((prev_log_index - self.snapshot_index()) as usize)
+                    .take(MAX_ENTRIES_PER_APPEND)
+                    .cloned()
+                    .collect();
+
+                self.peers[peer].last_sent = Some(now);
+
+                Some(Message::AppendEntries {
+                    term,
+                    leader_addr: self.addr.clone(),
+                    prev_log_index,
+                    prev_log_term,
+                    entries,
+                    leader_commit: self.commit_index,
+                })
+            }
+            State::Candidate => {
+                if self.peers[peer].vote_requested {
+                    return None;
+                }
+
+                self.peers[peer].vote_requested = true;
+
+                Some(Message::RequestVote {
+                    term,
+                    candidate_addr: self.addr.clone(),
+                    last_log_index,
+                    last_log_term,
+                })
+            }
+            State::Follower => None,
+        }
+    }
+
+    /// Called when the request to `peer` couldn't be delivered, so it will be retried.
+    pub fn handle_peer_failure(&mut self, peer: usize) {
+        self.peers[peer].vote_requested = false;
+    }
+
+    pub fn handle_response(&mut self, peer: usize, response: Message) -> Result<(), String> {
+        match response {
+            Message::RequestVoteResponse { term, vote_granted } => {
+                if term > self.persistent.current_term {
+                    return self.step_down(term);
+                }
+
+                if term == self.persistent.current_term
+                    && self.state == State::Candidate
+                    && vote_granted
+                {
+                    self.votes_granted += 1;
+
+                    if self.votes_granted >= self.quorum() {
+                        self.become_leader()?;
+                    }
+                }
+
+                Ok(())
+            }
+            Message::AppendEntriesResponse {
+                term,
+                success,
+                match_index,
+            } => {
+                if term > self.persistent.current_term {
+                    return self.step_down(term);
+                }
+
+                if term != self.persistent.current_term || self.state != State::Leader {
+                    return Ok(());
+                }
+
+                let progress = &mut self.peers[peer];
+
+                if success {
+                    progress.match_index = progress.match_index.max(match_index);
+                    progress.next_index = progress.match_index + 1;
+                    self.advance_commit_index();
+
+                    if self.compact() {
+                        self.persist()?;
+                    }
+                } else {
+                    progress.next_index = (progress.next_index - 1).min(match_index + 1).max(1);
+                }
+
+                Ok(())
+            }
+            _ => Err(format!(
+                "Message {:?} is not a valid response from an Observer peer.",
+                response
+            )),
+        }
+    }
+
+    pub fn handle_request_vote(
+        &mut self,
+        term: u64,
+        candidate_addr: &str,
+        last_log_index: u64,
+        last_log_term: u64,
+    ) -> Result<Message, String> {
+        if term > self.persistent.current_term {
+            self.step_down(term)?;
+        }
+
+        let log_up_to_date = last_log_term > self.last_log_term()
+            || (last_log_term == self.last_log_term() && last_log_index >= self.last_log_index());
+
+        let can_vote = match &self.persistent.voted_for {
+            Some(voted_for) => voted_for == candidate_addr,
+            None => true,
+        };
+
+        let vote_granted = term == self.persistent.current_term && can_vote && log_up_to_date;
+
+        if vote_granted {
+            self.persistent.voted_for = Some(candidate_addr.to_string());
+            self.election_deadline = Instant::now() + random_election_timeout();
+            self.persist()?;
+        }
+
+        Ok(Message::RequestVoteResponse {
+            term: self.persistent.current_term,
+            vote_granted,
+        })
+    }
+
+    pub fn handle_append_entries(
+        &mut self,
+        term: u64,
+        leader_addr: &str,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<LogEntry>,
+        leader_commit: u64,
+    ) -> Result<Message, String> {
+        if term < self.persistent.current_term {
+            return Ok(Message::AppendEntriesResponse {
+                term: self.persistent.current_term,
+                success: false,
+                match_index: self.last_log_index(),
+            });
+        }
+
+        self.step_down(term)?;
+        self.leader_addr = Some(leader_addr.to_string());
+        self.election_deadline = Instant::now() + random_election_timeout();
+
+        // Compacted entries are committed, they match the leader's
+        if prev_log_index > self.last_log_index()
+            || (prev_log_index >= self.snapshot_index()
+                && self.term_at(prev_log_index) != prev_log_term)
+        {
+            return Ok(Message::AppendEntriesResponse {
+                term: self.persistent.current_term,
+                success: false,
+                match_index: self.last_log_index().min(prev_log_index.saturating_sub(1)),
+            });
+        }
+
+        let match_index = prev_log_index + entries.len() as u64;
+        let mut log_changed = false;
+
+        for entry in entries {
+            if entry.index <= self.snapshot_index() {
+                continue;
+            }
+
+            if entry.index <= self.last_log_index() {
+                if self.term_at(entry.index) == entry.term {
+                    continue;
+                }
+                // Conflicting entries are removed with everything that follows them
+                self.persistent
+                    .log
+                    .truncate((entry.index - self.snapshot_index() - 1) as usize);
+            }
+
+            self.persistent.log.push(entry);
+            log_changed = true;
+        }
+
+        if leader_commit > self.commit_index {
+            self.commit_index = leader_commit.min(match_index);
+        }
+
+        if self.compact() || log_changed {
+            self.persist()?;
+        }
+
+        Ok(Message::AppendEntriesResponse {
+            term: self.persistent.current_term,
+            success: true,
+            match_index,
+        })
+    }
+
+    /// Starts an election right away on request of the leader handing its leadership over.
+    pub fn handle_timeout_now(&mut self, term: u64) -> Result<Message, String> {
+        if term == self.persistent.current_term && self.state == State::Follower {
+            self.start_election()?;
+        }
+
+        // Answered with the term of the election, the former leader steps down on seeing it
+        Ok(Message::AppendEntriesResponse {
+            term: self.persistent.current_term,
+            success: false,
+            match_index: self.last_log_index(),
+        })
+    }
+
+    /// Replaces the log up to the snapshot of the leader, sent once the leader has compacted the entries
+    /// this Observer is missing.
+    pub fn handle_install_snapshot(
+        &mut self,
+        term: u64,
+        leader_addr: &str,
+        snapshot: LogEntry,
+    ) -> Result<Message, String> {
+        if term < self.persistent.current_term {
+            return Ok(Message::AppendEntriesResponse {
+                term: self.persistent.current_term,
+                success: false,
+                match_index: self.last_log_index(),
+            });
+        }
+
+        self.step_down(term)?;
+        self.leader_addr = Some(leader_addr.to_string());
+        self.election_deadline = Instant::now() + random_election_timeout();
+
+        let match_index = snapshot.index;
+
+        if snapshot.index > self.snapshot_index() {
+            // Entries following the snapshot are kept as long as the log agrees with it
+            if self.term_at(snapshot.index) == snapshot.term {
+                let compacted = (snapshot.index - self.snapshot_index()) as usize;
+                self.persistent.log.drain(..compacted);
+            } else {
+                self.persistent.log.clear();
+            }
+
+            self.commit_index = self.commit_index.max(snapshot.index);
+            self.persistent.snapshot = Some(snapshot);
+            self.persist()?;
+        }
+
+        Ok(Message::AppendEntriesResponse {
+            term: self.persistent.current_term,
+            success: true,
+            match_index,
+        })
+    }
+
+    /// Returns the latest committed metadata which hasn't been applied yet on a follower.
+    /// Every entry is a full snapshot, so only the latest one has to be applied.
+    pub fn take_metadata_to_apply(&mut self) -> Option<Metadata> {
+        if self.last_applied >= self.commit_index {
+            return None;
+        }
+
+        self.last_applied = self.commit_index;
+
+        // The leader's distribution manager is the source of the entries
+        if self.is_leader() {
+            return None;
+        }
+
+        self.entry_at(self.commit_index).map(|e| e.metadata.clone())
+    }
+
+    pub fn take_leadership_metadata(&mut self) -> Option<Metadata> {
+        self.pending_leadership_metadata.take()
+    }
+}
+
+fn random_election_timeout() -> Duration {
+    let range = (ELECTION_TIMEOUT_MAX - ELECTION_TIMEOUT_MIN).as_millis();
+    let jitter = Uuid::new_v4().as_u128() % range;
+    ELECTION_TIMEOUT_MIN + Duration::from_millis(jitter as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use shared_structures::Topic;
+
+    use super::*;
+
+    fn custom_test_dir() -> PathBuf {
+        format!("/observer/test_raft_{}", Uuid::new_v4()).into()
+    }
+
+    fn cleanup_after_test(custom_dir: &PathBuf) {
+        let test_files_path = DirManager::for_tests(Some(custom_dir)).base_dir().unwrap();
+        let _ = fs::remove_dir_all(test_files_path);
+    }
+
+    fn mock_node(addr: &str, peers: &[&str], custom_dir: &Path) -> Raft {
+        let mut dir = custom_dir.to_path_buf();
+        dir.push(addr.replace(':', "_"));
+        Raft::new(
+            addr.to_string(),
+            peers.iter().map(|p| p.to_string()).collect(),
+            DirManager::for_tests(Some(&dir)),
+        )
+        .unwrap()
+    }
+
+    fn mock_metadata(topic_name: &str) -> Metadata {
+        Metadata {
+            brokers: vec![],
+            topics: vec![Topic::from(topic_name.to_string())],
+        }
+    }
+
+    fn election_timed_out() -> Instant {
+        Instant::now() + ELECTION_TIMEOUT_MAX
+    }
+
+    // Delivers the next request of `from` to `peer` and its response back
+    fn deliver(from: &mut Raft, peer: usize, to: &mut Raft) {
+        // Makes a heartbeat due whenever there are no entries to send
+        from.peers[peer].last_sent = None;
+
+        let request = from.request_for_peer(peer, Instant::now()).unwrap();
+
+        let response = match request {
+            Message::RequestVote {
+                term,
+                candidate_addr,
+                last_log_index,
+                last_log_term,
+            } => to
+                .handle_request_vote(term, &candidate_addr, last_log_index, last_log_term)
+                .unwrap(),
+            Message::AppendEntries {
+                term,
+                leader_addr,
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit,
+            } => to
+                .handle_append_entries(
+                    term,
+                    &leader_addr,
+                    prev_log_index,
+                    prev_log_term,
+                    entries,
+                    leader_commit,
+                )
+                .unwrap(),
+            Message::TimeoutNow { term } => to.handle_timeout_now(term).unwrap(),
+            Message::InstallSnapshot {
+                term,
+                leader_addr,
+                snapshot,
+            } => to
+                .handle_install_snapshot(term, &leader_addr, snapshot)
+                .unwrap(),
+            _ => unreachable!(),
+        };
+
+        from.handle_response(peer, response).unwrap();
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn single_observer_is_leader_and_commits_immediately() {
+        let custom_dir = custom_test_dir();
+        let mut node = mock_node("localhost:1", &[], &custom_dir);
+
+        assert!(node.is_leader());
+
+        let index = node.propose(mock_metadata("notifications")).unwrap();
+
+        assert_eq!(node.commit_index(), index);
+
+        cleanup_after_test(&custom_dir);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn elects_leader_and_commits_once_quorum_has_the_entry() {
+        let custom_dir = custom_test_dir();
+        let addrs = ["localhost:1", "localhost:2", "localhost:3"];
+
+        let mut a = mock_node(addrs[0], &addrs, &custom_dir);
+        let mut b = mock_node(addrs[1], &addrs, &custom_dir);
+        let mut c = mock_node(addrs[2], &addrs, &custom_dir);
+
+        assert!(a.propose(mock_metadata("notifications")).is_err());
+
+        a.tick(election_timed_out()).unwrap();
+        assert_eq!(a.state, State::Candidate);
+
+        // A single vote together with its own is a quorum of 3
+        deliver(&mut a, 0, &mut b);
+        assert!(a.is_leader());
+
+        let index = a.propose(mock_metadata("notifications")).unwrap();
+        assert_eq!(a.commit_index(), 0);
+
+        deliver(&mut a, 0, &mut b);
+        assert_eq!(a.commit_index(), index);
+
+        // Follower learns about the commit on the next heartbeat and applies it
+        deliver(&mut a, 0, &mut b);
+        let applied = b.take_metadata_to_apply().unwrap();
+        assert_eq!(applied.topics[0].name, "notifications");
+        assert_eq!(b.leader_addr.as_deref(), Some(addrs[0]));
+
+        // Lagging follower catches up
+        deliver(&mut a, 1, &mut c);
+        deliver(&mut a, 1, &mut c);
+        assert!(c.take_metadata_to_apply().is_some());
+
+        cleanup_after_test(&custom_dir);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn denies_vote_to_candidate_with_stale_log() {
+        let custom_dir = custom_test_dir();
+        let addrs = ["localhost:1", "localhost:2", "localhost:3"];
+
+        let mut a = mock_node(addrs[0], &addrs, &custom_dir);
+        let mut b = mock_node(addrs[1], &addrs, &custom_dir);
+        let mut c = mock_node(addrs[2], &addrs, &custom_dir);
+
+        a.tick(election_timed_out()).unwrap();
+        deliver(&mut a, 0, &mut b);
+        a.propose(mock_metadata("notifications")).unwrap();
+        deliver(&mut a, 0, &mut b);
+
+        // `c` has missed the entry, it can't become the leader with b's vote
+        c.tick(election_timed_out() + ELECTION_TIMEOUT_MAX).unwrap();
+        let term = c.current_term();
+        let response = b.handle_request_vote(term, addrs[2], 0, 0).unwrap();
+
+        assert!(matches!(
+            response,
+            Message::RequestVoteResponse {
+                vote_granted: false,
+                ..
+            }
+        ));
+
+        cleanup_after_test(&custom_dir);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn leader_hands_leadership_over_to_up_to_date_peer() {
+        let custom_dir = custom_test_dir();
+        let addrs = ["localhost:1", "localhost:2", "localhost:3"];
+
+        let mut a = mock_node(addrs[0], &addrs, &custom_dir);
+        let mut b = mock_node(addrs[1], &addrs, &custom_dir);
+        let mut c = mock_node(addrs[2], &addrs, &custom_dir);
+
+        a.tick(election_timed_out()).unwrap();
+        deliver(&mut a, 0, &mut b);
+        a.propose(mock_metadata("notifications")).unwrap();
+        deliver(&mut a, 0, &mut b);
+        a.propose(mock_metadata("payments")).unwrap();
+
+        // `b` has more of the log than `c`
+        assert_eq!(a.transfer_leadership().unwrap(), addrs[1]);
+        assert!(a.propose(mock_metadata("logs")).is_err());
+
+        // Catches up first, only then it's told to run for election
+        deliver(&mut a, 0, &mut b);
+        assert_eq!(b.state, State::Follower);
+        deliver(&mut a, 0, &mut b);
+        assert_eq!(b.state, State::Candidate);
+        assert!(!a.is_leader());
+
+        deliver(&mut b, 0, &mut a);
+        assert!(b.is_leader());
+        assert_eq!(b.current_term(), a.current_term());
+
+        deliver(&mut b, 1, &mut c);
+        assert_eq!(c.leader_addr.as_deref(), Some(addrs[1]));
+
+        cleanup_after_test(&custom_dir);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn follower_replaces_conflicting_entries() {
+        let custom_dir = custom_test_dir();
+        let mut follower = mock_node("localhost:2", &["localhost:1"], &custom_dir);
+
+        let entry = |term: u64, index: u64, topic: &str| LogEntry {
+            term,
+            index,
+            metadata: mock_metadata(topic),
+        };
+
+        follower
+            .handle_append_entries(
+                1,
+                "localhost:1",
+                0,
+                0,
+                vec![entry(1, 1, "a"), entry(1, 2, "stale")],
+                1,
+            )
+            .unwrap();
+
+        // Previous entry doesn't match, the leader should retry from an earlier index
+        let response = follower
+            .handle_append_entries(2, "localhost:3", 2, 2, vec![entry(2, 3, "b")], 1)
+            .unwrap();
+        assert!(matches!(
+            response,
+            Message::AppendEntriesResponse { success: false, .. }
+        ));
+
+        let response = follower
+            .handle_append_entries(2, "localhost:3", 1, 1, vec![entry(2, 2, "b")], 2)
+            .unwrap();
+        assert!(matches!(
+            response,
+            Message::AppendEntriesResponse {
+                success: true,
+                match_index: 2,
+                ..
+            }
+        ));
+
+        let applied = follower.take_metadata_to_apply().unwrap();
+        assert_eq!(applied.topics[0].name, "b");
+
+        cleanup_after_test(&custom_dir);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn compacts_committed_entries_and_catches_followers_up_with_the_snapshot() {
+        let custom_dir = custom_test_dir();
+        let addrs = ["localhost:1", "localhost:2", "localhost:3"];
+
+        let mut a = mock_node(addrs[0], &addrs, &custom_dir);
+        let mut b = mock_node(addrs[1], &addrs, &custom_dir);
+        let mut c = mock_node(addrs[2], &addrs, &custom_dir);
+
+        a.tick(election_timed_out()).unwrap();
+        deliver(&mut a, 0, &mut b);
+
+        for i in 0..10 {
+            a.propose(mock_metadata(&format!("topic_{}", i))).unwrap();
+            deliver(&mut a, 0, &mut b);
+        }
+
+        // Only the latest committed entry is kept
+        assert_eq!(a.commit_index(), 10);
+        assert!(a.persistent.log.is_empty());
+        assert_eq!(a.snapshot_index(), 10);
+
+        // The follower compacts once it learns about the commit
+        deliver(&mut a, 0, &mut b);
+        assert!(b.persistent.log.is_empty());
+        assert_eq!(
+            b.take_metadata_to_apply().unwrap().topics[0].name,
+            "topic_9"
+        );
+
+        // `c` missed every entry, it gets the snapshot instead
+        deliver(&mut a, 1, &mut c);
+        assert_eq!(c.last_log_index(), 10);
+        assert_eq!(
+            c.take_metadata_to_apply().unwrap().topics[0].name,
+            "topic_9"
+        );
+
+        // Entries following the snapshot are replicated as usual
+        a.propose(mock_metadata("payments")).unwrap();
+        deliver(&mut a, 1, &mut c);
+        assert_eq!(a.commit_index(), 11);
+
+        // The snapshot survives restarts
+        let mut dir = custom_dir.clone();
+        dir.push(addrs[0].replace(':', "_"));
+        let restarted = Raft::new(
+            addrs[0].to_string(),
+            addrs.iter().map(|p| p.to_string()).collect(),
+            DirManager::for_tests(Some(&dir)),
+        )
+        .unwrap();
+        assert_eq!(restarted.last_log_index(), 11);
+        assert_eq!(restarted.commit_index(), 11);
+
+        cleanup_after_test(&custom_dir);
+    }
+}
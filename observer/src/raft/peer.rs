@@ -0,0 +1,237 @@
+use std::{
+    io::{BufRead, BufReader},
+    net::TcpStream,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use shared_structures::{Broadcast, EntityType, Message, MessageDecoder, Metadata, Reader};
+
+use crate::distribution_manager::DistributionManager;
+
+use super::{Raft, HEARTBEAT_INTERVAL, TICK};
+
+// How long the leader waits for a metadata change to be acknowledged by a quorum
+const COMMIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Spawns the election timer and one replication thread per peer Observer.
+pub fn start(raft: Arc<Mutex<Raft>>, distribution_manager: Arc<Mutex<DistributionManager>>) {
+    let peers_count = raft.lock().unwrap().peers_count();
+
+    for peer in 0..peers_count {
+        let raft = raft.clone();
+        std::thread::spawn(move || replicate_to_peer(raft, peer));
+    }
+
+    std::thread::spawn(move || loop {
+        std::thread::sleep(TICK);
+
+        let mut raft_lock = raft.lock().unwrap();
+
+        if let Err(e) = raft_lock.tick(Instant::now()) {
+            log::warn!("Raft tick error: {}", e);
+        }
+
+        let leadership_metadata = raft_lock.take_leadership_metadata();
+
+        drop(raft_lock);
+
+        if let Some(metadata) = leadership_metadata {
+            if let Err(e) = apply_metadata(&distribution_manager, &metadata) {
+                log::error!("Failed to load cluster metadata as the new leader: {}", e);
+            }
+        }
+    });
+}
+
+fn replicate_to_peer(raft: Arc<Mutex<Raft>>, peer: usize) {
+    let mut stream: Option<TcpStream> = None;
+
+    loop {
+        std::thread::sleep(TICK);
+
+        let mut raft_lock = raft.lock().unwrap();
+        let request = raft_lock.request_for_peer(peer, Instant::now());
+        let peer_addr = raft_lock.peer_addr(peer).to_string();
+        drop(raft_lock);
+
+        let request = match request {
+            Some(request) => request,
+            None => continue,
+        };
+
+        match send_request(&mut stream, &peer_addr, &request) {
+            Ok(response) => {
+                if let Err(e) = raft.lock().unwrap().handle_response(peer, response) {
+                    log::warn!("Raft response error from {}: {}", peer_addr, e);
+                }
+            }
+            Err(e) => {
+                // Peer is unreachable, it's expected while an Observer is down
+                log::debug!("Observer {} is unreachable: {}", peer_addr, e);
+                stream = None;
+                raft.lock().unwrap().handle_peer_failure(peer);
+            }
+        }
+    }
+}
+
+fn send_request(
+    stream: &mut Option<TcpStream>,
+    peer_addr: &str,
+    request: &Message,
+) -> Result<Message, String> {
+    if stream.is_none() {
+        let mut new_stream = TcpStream::connect(peer_addr).map_err(|e| e.to_string())?;
+        new_stream
+            .set_read_timeout(Some(HEARTBEAT_INTERVAL * 2))
+            .map_err(|e| e.to_string())?;
+        Broadcast::to(
+            &mut new_stream,
+            &Message::EntityWantsToConnect {
+                entity_type: EntityType::Observer,
+            },
+        )?;
+        *stream = Some(new_stream);
+    }
+
+    let stream = stream.as_mut().ok_or("Peer stream is not available.")?;
+
+    Broadcast::to(stream, request)?;
+    Reader::read_one_message(stream)
+}
+
+/// Answers the requests of a peer Observer connected to this Observer.
+pub fn serve_peer(
+    raft: Arc<Mutex<Raft>>,
+    distribution_manager: Arc<Mutex<DistributionManager>>,
+    stream: TcpStream,
+) -> Result<(), String> {
+    let reader_stream = stream.try_clone().map_err(|e| e.to_string())?;
+
+    std::thread::spawn(move || {
+        let mut stream = stream;
+        let mut reader = BufReader::new(reader_stream);
+        let mut buf = String::with_capacity(1024);
+
+        loop {
+            match reader.read_line(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+
+            if let Err(e) = handle_peer_message(&raft, &distribution_manager, &mut stream, &buf) {
+                log::warn!("Raft peer error: {}", e);
+                break;
+            }
+
+            buf.clear();
+        }
+    });
+
+    Ok(())
+}
+
+fn handle_peer_message(
+    raft: &Arc<Mutex<Raft>>,
+    distribution_manager: &Arc<Mutex<DistributionManager>>,
+    stream: &mut TcpStream,
+    raw_message: &str,
+) -> Result<(), String> {
+    let mut raft_lock = raft.lock().unwrap();
+
+    let response = match MessageDecoder::decode(raw_message)? {
+        Message::RequestVote {
+            term,
+            candidate_addr,
+            last_log_index,
+            last_log_term,
+        } => raft_lock.handle_request_vote(term, &candidate_addr, last_log_index, last_log_term)?,
+        Message::AppendEntries {
+            term,
+            leader_addr,
+            prev_log_index,
+            prev_log_term,
+            entries,
+            leader_commit,
+        } => raft_lock.handle_append_entries(
+            term,
+            &leader_addr,
+            prev_log_index,
+            prev_log_term,
+            entries,
+            leader_commit,
+        )?,
+        Message::TimeoutNow { term } => raft_lock.handle_timeout_now(term)?,
+        Message::InstallSnapshot {
+            term,
+            leader_addr,
+            snapshot,
+        } => raft_lock.handle_install_snapshot(term, &leader_addr, snapshot)?,
+        message => {
+            return Err(format!(
+                "Message {:?} is not handled between Observers.",
+                message
+            ))
+        }
+    };
+
+    let metadata_to_apply = raft_lock.take_metadata_to_apply();
+
+    // The distribution manager lock may be held by a pending `replicate`,
+    // which needs the raft lock to find out it lost the leadership.
+    drop(raft_lock);
+
+    Broadcast::to(stream, &response)?;
+
+    if let Some(metadata) = metadata_to_apply {
+        apply_metadata(distribution_manager, &metadata)?;
+    }
+
+    Ok(())
+}
+
+fn apply_metadata(
+    distribution_manager: &Arc<Mutex<DistributionManager>>,
+    metadata: &Metadata,
+) -> Result<(), String> {
+    let mut distribution_manager_lock = distribution_manager.lock().unwrap();
+    distribution_manager_lock.load_cluster_state(metadata)?;
+    distribution_manager_lock.save_cluster_state()
+}
+
+/// Appends the metadata change to the replicated log and waits until a quorum of Observers has it.
+pub fn replicate(raft: &Arc<Mutex<Raft>>, metadata: Metadata) -> Result<(), String> {
+    let mut raft_lock = raft.lock().unwrap();
+    let term = raft_lock.current_term();
+    let index = raft_lock.propose(metadata)?;
+    drop(raft_lock);
+
+    let deadline = Instant::now() + COMMIT_TIMEOUT;
+
+    loop {
+        let raft_lock = raft.lock().unwrap();
+
+        if raft_lock.current_term() != term || !raft_lock.is_leader() {
+            return Err(
+                "Observer lost the leadership before the metadata change was acknowledged."
+                    .to_string(),
+            );
+        }
+
+        if raft_lock.commit_index() >= index {
+            return Ok(());
+        }
+
+        drop(raft_lock);
+
+        if Instant::now() >= deadline {
+            return Err(
+                "Metadata change was not acknowledged by a quorum of Observers in time."
+                    .to_string(),
+            );
+        }
+
+        std::thread::sleep(TICK);
+    }
+}
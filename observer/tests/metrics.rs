@@ -0,0 +1,150 @@
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use shared_structures::{Broadcast, DirManager, EntityType, Message};
+
+// Kills the Observer even when an assertion fails
+struct ObserverProcess(Child);
+
+impl Drop for ObserverProcess {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn free_port() -> u16 {
+    let listener = TcpListener::bind("localhost:0").unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+fn scrape(addr: &str) -> String {
+    let deadline = Instant::now() + Duration::from_secs(10);
+
+    loop {
+        if let Ok(mut stream) = TcpStream::connect(addr) {
+            write!(stream, "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            return response;
+        }
+
+        assert!(
+            Instant::now() < deadline,
+            "Metrics are not served on {}",
+            addr
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn nyx_admin(admin_addr: &str, command: &str) -> bool {
+    Command::new(env!("CARGO_BIN_EXE_nyx-admin"))
+        .arg("--observers")
+        .arg(admin_addr)
+        .args(command.split_whitespace())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap()
+        .success()
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn scrapes_the_observer_metrics_after_admin_requests() {
+    let data_dir =
+        DirManager::test_root().join(format!("observer_metrics_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&data_dir).unwrap();
+
+    let observer_addr = format!("localhost:{}", free_port());
+    let metrics_addr = format!("localhost:{}", free_port());
+    let admin_addr = format!("127.0.0.1:{}", free_port());
+
+    let config_path = data_dir.join("observer.properties");
+    std::fs::write(
+        &config_path,
+        format!(
+            "port={}\nreplica_factor=1\nmetrics_addr={}\nadmin_addr={}\n",
+            observer_addr.rsplit(':').next().unwrap(),
+            metrics_addr,
+            admin_addr
+        ),
+    )
+    .unwrap();
+
+    let observer = ObserverProcess(
+        Command::new(env!("CARGO_BIN_EXE_observer"))
+            .arg(&config_path)
+            .arg("--data-dir")
+            .arg(&data_dir)
+            .arg("--non-interactive")
+            .arg("--log-level")
+            .arg("off")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+
+    // Served once the Observer is up
+    scrape(&metrics_addr);
+
+    // Stands in for a broker, partitions are placed on it
+    let mut broker = TcpStream::connect(&observer_addr).unwrap();
+    Broadcast::to_many(
+        &mut broker,
+        &[
+            Message::EntityWantsToConnect {
+                entity_type: EntityType::Broker,
+            },
+            Message::BrokerConnectionDetails {
+                id: "mocked_broker_id".to_string(),
+                addr: "localhost:0".to_string(),
+                rack: None,
+            },
+        ],
+    )
+    .unwrap();
+
+    // The broker is connected once it is counted as up
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !scrape(&metrics_addr).contains("nyx_observer_brokers{status=\"up\"} 1\n") {
+        assert!(Instant::now() < deadline, "The broker never connected");
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    // Admin clients are only served on the admin address
+    assert!(!nyx_admin(&observer_addr, "LIST TOPICS"));
+    assert!(nyx_admin(
+        &admin_addr,
+        "CREATE TOPIC notifications PARTITIONS 2"
+    ));
+    assert!(!nyx_admin(&admin_addr, "DESCRIBE TOPIC missing"));
+
+    let response = scrape(&metrics_addr);
+
+    for expected in [
+        "nyx_observer_is_leader 1\n",
+        "nyx_observer_brokers{status=\"up\"} 1\n",
+        "nyx_observer_topics 1\n",
+        "nyx_observer_partitions{topic=\"notifications\"} 2\n",
+        "nyx_observer_admin_request_duration_seconds_count{request=\"create_topic\"} 1\n",
+        "nyx_observer_admin_requests_failed_total{request=\"describe_topic\"} 1\n",
+    ] {
+        assert!(
+            response.contains(expected),
+            "{} is missing in {}",
+            expected,
+            response
+        );
+    }
+
+    drop(observer);
+    let _ = std::fs::remove_dir_all(data_dir);
+}
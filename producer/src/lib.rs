@@ -1,12 +1,12 @@
-use std::{
-    io::{BufRead, BufReader},
-    net::TcpStream,
-};
+use std::net::TcpStream;
 
-use shared_structures::{metadata::BrokerDetails, Broadcast, Reader};
+use shared_structures::{
+    metadata::BrokerDetails,
+    tracing::{Span, SpanKind},
+    Broadcast, Message, Reader, Record,
+};
 
 pub struct Producer {
-    pub mode: String,
     pub broker_details: BrokerDetails,
     pub stream: TcpStream,
     pub topic: String,
@@ -14,7 +14,7 @@ pub struct Producer {
 }
 
 impl Producer {
-    pub fn from(brokers: &str, mode: &str, topic: &str) -> Result<Self, String> {
+    pub fn from(brokers: &str, topic: &str) -> Result<Self, String> {
         let brokers: Vec<_> = brokers
             .split_terminator(',')
             .map(|b| b.to_string())
@@ -63,47 +63,65 @@ impl Producer {
                     TcpStream::connect(&broker_details.addr).map_err(|e| e.to_string())?
                 };
 
-                println!("Stream: {:#?}", stream);
+                log::debug!(
+                    "Connected to broker {} at {}",
+                    broker_details.id,
+                    broker_details.addr
+                );
 
-                let producer = Self {
-                    mode: mode.to_string(),
+                Ok(Self {
                     broker_details: broker_details.clone(),
                     stream,
                     topic: topic.to_string(),
                     destination_replica_id: partition_details.replica_id.clone(),
-                };
-
-                producer.open_broker_reader()?;
-
-                Ok(producer)
+                })
             }
             _ => Err("Wrong message received on handshake".to_string()),
         }
     }
 
-    fn open_broker_reader(&self) -> Result<(), String> {
-        let reader_stream = self
-            .stream
-            .try_clone()
-            .map_err(|e| format!("Producer: {}", e))?;
-
-        std::thread::spawn(|| {
-            let mut buf = String::with_capacity(1024);
-            let mut reader = BufReader::new(reader_stream);
-
-            loop {
-                let bytes_read = reader.read_line(&mut buf).unwrap();
+    /// Sends `record` to the partition replica of the producer and waits until the broker has appended it,
+    /// a record the broker rejects is an error. The trace context of the send is passed along so the
+    /// broker's handling is part of the same trace.
+    pub fn produce(&mut self, record: Record) -> Result<(), String> {
+        let mut span = Span::start("producer produce", SpanKind::Client);
+        span.set_attribute("topic", self.topic.as_str());
+        span.set_attribute("replica", self.destination_replica_id.as_str());
+        span.set_attribute("broker", self.broker_details.addr.as_str());
+
+        let mut payload = vec![];
+        record.encode(&mut payload);
+
+        let result = Broadcast::with_payload(
+            &mut self.stream,
+            &Message::ProducerMessage {
+                replica_id: self.destination_replica_id.clone(),
+                record_size: payload.len(),
+                traceparent: span.context(),
+            },
+            &payload,
+        )
+        .and_then(|()| match Reader::read_one_message(&mut self.stream)? {
+            Message::ProduceResponse { response } => response.map(|offset| {
+                log::trace!("Record appended at offset {}", offset);
+            }),
+            message => Err(format!("Unexpected response to produce: {:?}", message)),
+        });
 
-                if bytes_read == 0 {
-                    break;
-                }
+        if let Err(e) = &result {
+            span.set_error(e);
+        }
 
-                println!("Recieved message from broker: {:#?}", buf);
+        result
+    }
 
-                buf.clear();
-            }
-        });
+    /// Sends raw bytes, e.g. a protobuf or Avro encoded message, as the value of a record.
+    pub fn produce_bytes(&mut self, value: impl Into<Vec<u8>>) -> Result<(), String> {
+        self.produce(Record::new(value))
+    }
 
-        Ok(())
+    /// Sends `value` serialized as JSON, see [`Record::from_json`].
+    pub fn produce_json<T: serde::Serialize>(&mut self, value: &T) -> Result<(), String> {
+        self.produce(Record::from_json(value)?)
     }
 }
@@ -3,44 +3,50 @@ use std::io::stdin;
 use clap::{arg, command};
 use producer::Producer;
 use serde_json::json;
-use shared_structures::Broadcast;
+use shared_structures::{logger, tracing};
 
 fn main() -> Result<(), String> {
     let matches = command!()
         .arg(arg!(-b --brokers <BROKERS> "List of brokers to connect to seperated by comma e.g. localhost:3000,localhost:4000,...").required(true))
         .arg(arg!(-t --topic <TOPIC> "The name of the topic onto which producer is going to push messages").required(true))
-        .arg(arg!(-m --mode <MODE> "In which mode you want to run the producer 'test' or 'production', defaults to 'production'").required(false).default_value("production"))
+        .arg(arg!(--"trace-exporter" <DEST> "OTLP/HTTP endpoint, e.g. http://localhost:4318, or file the trace spans of the produced messages are exported to.").required(false))
+        .arg(arg!(--"log-level" <LEVEL> "Most verbose level of the logs written to stderr.").required(false).value_parser(logger::LEVELS).default_value("info"))
+        .arg(arg!(--"log-format" <FORMAT> "Format of the logs, `json` writes one object per line for log collectors.").required(false).value_parser(logger::FORMATS).default_value("text"))
         .get_matches();
 
+    logger::init(
+        matches.get_one::<String>("log-level").unwrap(),
+        matches.get_one::<String>("log-format").unwrap(),
+    )?;
+
+    if let Some(trace_exporter) = matches.get_one::<String>("trace-exporter") {
+        tracing::init("producer", trace_exporter)?;
+    }
+
     let brokers = matches.get_one::<String>("brokers").unwrap();
-    let mode = matches.get_one::<String>("mode").unwrap();
     let topic = matches.get_one::<String>("topic").unwrap();
 
-    let mut producer = Producer::from(brokers, mode, topic)?;
+    let mut producer = Producer::from(brokers, topic)?;
 
-    println!("Broker details: {:#?}", producer.broker_details);
+    log::debug!("Broker details: {:?}", producer.broker_details);
 
-    println!("Broadcasting a test message to the partition");
+    log::info!("Broadcasting a test message to the partition");
 
-    Broadcast::to(
-        &mut producer.stream,
-        &shared_structures::Message::ProducerMessage {
-            replica_id: producer.destination_replica_id,
-            payload: json!({"message": "test"}),
-        },
-    )?;
+    producer.produce_json(&json!({"message": "test"}))?;
 
     let mut buf = String::with_capacity(1024);
 
     loop {
         stdin().read_line(&mut buf).unwrap();
 
-        if buf == "EXIT" {
+        if buf.trim_end() == "EXIT" {
             break;
         }
 
         buf.clear()
     }
 
+    tracing::flush();
+
     Ok(())
 }
@@ -0,0 +1,216 @@
+use std::fmt::Display;
+
+use crate::{Role, Status, Topic};
+
+/// Operations exposed by the leader Observer to admin clients such as `nyx-admin`.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum AdminRequest {
+    CreateTopic {
+        name: String,
+        partitions: usize,
+        // `key=value` pairs of the topic configuration, see `TopicConfig`
+        config: Vec<(String, String)>,
+    },
+    CreatePartition {
+        topic: String,
+    },
+    AlterTopic {
+        name: String,
+        changes: Vec<(String, String)>,
+    },
+    DeleteTopic {
+        name: String,
+    },
+    ListTopics,
+    ListBrokers,
+    DescribeTopic {
+        name: String,
+    },
+    DescribeBroker {
+        id: String,
+    },
+    DescribeCluster,
+}
+
+impl AdminRequest {
+    /// Requests that change the cluster metadata can only be handled by the leader Observer.
+    pub fn is_mutation(&self) -> bool {
+        matches!(
+            self,
+            Self::CreateTopic { .. }
+                | Self::CreatePartition { .. }
+                | Self::AlterTopic { .. }
+                | Self::DeleteTopic { .. }
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum AdminResponse {
+    TopicCreated { name: String },
+    PartitionCreated { id: String },
+    TopicAltered { topic: Topic },
+    TopicDeleted { name: String },
+    Topics { topics: Vec<Topic> },
+    Brokers { brokers: Vec<BrokerDescription> },
+    Topic { topic: TopicDescription },
+    Broker { broker: BrokerDescription },
+    Cluster { cluster: ClusterDescription },
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct BrokerDescription {
+    pub id: String,
+    pub addr: String,
+    pub rack: Option<String>,
+    pub status: Status,
+    // Milliseconds since the last heartbeat, `None` if none was received since the Observer started
+    pub last_heartbeat_ms: Option<u64>,
+    // Ids of the partitions the broker holds a replica of
+    pub partitions: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ReplicaDescription {
+    pub replica_id: String,
+    pub broker_id: String,
+    pub role: Role,
+    pub status: Status,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PartitionDescription {
+    pub id: String,
+    pub partition_number: usize,
+    pub replicas: Vec<ReplicaDescription>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TopicDescription {
+    pub topic: Topic,
+    pub partitions: Vec<PartitionDescription>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct UnderReplicatedPartition {
+    pub id: String,
+    pub topic: String,
+    pub replicas_up: usize,
+    pub replica_factor: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ClusterDescription {
+    pub brokers: Vec<BrokerDescription>,
+    pub topics: Vec<Topic>,
+    pub under_replicated_partitions: Vec<UnderReplicatedPartition>,
+}
+
+impl Display for BrokerDescription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rack = self
+            .rack
+            .as_ref()
+            .map(|r| format!(" (rack {})", r))
+            .unwrap_or_default();
+        let last_heartbeat = self
+            .last_heartbeat_ms
+            .map(|h| format!("{:.1}s ago", h as f32 / 1000.0))
+            .unwrap_or("never".to_string());
+        write!(
+            f,
+            "Broker {}{} at {} {:?}, last heartbeat {}",
+            self.id, rack, self.addr, self.status, last_heartbeat
+        )
+    }
+}
+
+fn fmt_topic(f: &mut std::fmt::Formatter<'_>, topic: &Topic) -> std::fmt::Result {
+    writeln!(
+        f,
+        "├── Topic `{}` ({} partitions) {}",
+        topic.name, topic.partition_count, topic.config
+    )
+}
+
+impl Display for AdminResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TopicCreated { name } => writeln!(f, "Topic `{}` has been created", name),
+            Self::PartitionCreated { id } => writeln!(f, "Partition {} has been created", id),
+            Self::TopicAltered { topic } => writeln!(f, "Topic `{}`: {}", topic.name, topic.config),
+            Self::TopicDeleted { name } => writeln!(f, "Topic `{}` has been deleted", name),
+            Self::Topics { topics } => {
+                writeln!(f, "Topics:")?;
+                for topic in topics {
+                    fmt_topic(f, topic)?;
+                }
+                Ok(())
+            }
+            Self::Brokers { brokers } => {
+                writeln!(f, "Brokers:")?;
+                for broker in brokers {
+                    writeln!(f, "├── {}", broker)?;
+                }
+                Ok(())
+            }
+            Self::Topic { topic } => {
+                fmt_topic(f, &topic.topic)?;
+                for partition in topic.partitions.iter() {
+                    writeln!(
+                        f,
+                        "│   ├── Partition {} ({})",
+                        partition.partition_number, partition.id
+                    )?;
+                    for replica in partition.replicas.iter() {
+                        writeln!(
+                            f,
+                            "│   │   ├── Replica {} on broker {} {:?} {:?}",
+                            replica.replica_id, replica.broker_id, replica.role, replica.status
+                        )?;
+                    }
+                }
+                Ok(())
+            }
+            Self::Broker { broker } => {
+                writeln!(f, "├── {}", broker)?;
+                for partition in broker.partitions.iter() {
+                    writeln!(f, "│   ├── Partition {}", partition)?;
+                }
+                Ok(())
+            }
+            Self::Cluster { cluster } => {
+                writeln!(f, ".")?;
+                for broker in cluster.brokers.iter() {
+                    writeln!(f, "├── {}", broker)?;
+                    for partition in broker.partitions.iter() {
+                        writeln!(f, "│   ├── Partition {}", partition)?;
+                    }
+                }
+
+                if !cluster.topics.is_empty() {
+                    writeln!(f, "Topics:")?;
+                    for topic in cluster.topics.iter() {
+                        fmt_topic(f, topic)?;
+                    }
+                }
+
+                if !cluster.under_replicated_partitions.is_empty() {
+                    writeln!(f, "Under-replicated partitions:")?;
+                    for partition in cluster.under_replicated_partitions.iter() {
+                        writeln!(
+                            f,
+                            "├── Partition {} of topic `{}` ({}/{} replicas up)",
+                            partition.id,
+                            partition.topic,
+                            partition.replicas_up,
+                            partition.replica_factor
+                        )?;
+                    }
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
@@ -35,7 +35,7 @@ impl Broadcast {
             .write(payload.as_bytes())
             .map_err(|e| e.to_string())?;
 
-        println!("Message broadcasted with {} bytes", bytes_written);
+        log::trace!("Message broadcasted with {} bytes", bytes_written);
 
         if bytes_written == 0 {
             return Err("0 bytes have been written, might be an error, please create a new issue in nyx repository.".to_string());
@@ -44,6 +44,25 @@ impl Broadcast {
         Ok(())
     }
 
+    /// Sends `message` followed by its binary `payload`, whose size the message declares.
+    pub fn with_payload(
+        stream: &mut TcpStream,
+        message: &Message,
+        payload: &[u8],
+    ) -> Result<(), String> {
+        let mut bytes = serde_json::to_vec(message)
+            .map_err(|_| "Couldn't serialize the data structure to send.".to_string())?;
+
+        bytes.push(b'\n');
+        bytes.extend_from_slice(payload);
+
+        stream.write_all(&bytes).map_err(|e| e.to_string())?;
+
+        log::trace!("Message broadcasted with {} bytes", bytes.len());
+
+        Ok(())
+    }
+
     pub fn to_many(stream: &mut TcpStream, messages: &[Message]) -> Result<(), String> {
         let mut payloads: Vec<String> = vec![];
 
@@ -54,7 +73,7 @@ impl Broadcast {
             payloads.push(payload);
         }
 
-        println!("{:#?}", payloads);
+        log::trace!("Broadcasting {:?}", payloads);
 
         let payload_bytes: Vec<_> = payloads
             .iter()
@@ -118,6 +137,7 @@ mod tests {
             topic: Topic::from("notifications".to_string()),
             replica_count: 1,
             partition_number: 1,
+            traceparent: None,
         };
 
         let result = Broadcast::all(&mut streams, &test_message);
@@ -161,4 +181,44 @@ mod tests {
             buf.clear();
         }
     }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn payloads_follow_their_messages() {
+        let listener = TcpListener::bind("localhost:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let client =
+            std::thread::spawn(move || TcpStream::connect(format!("localhost:{}", port)).unwrap());
+
+        let (mut server_to_client_stream, _) = listener.accept().unwrap();
+        let mut client_to_server_stream = client.join().unwrap();
+
+        let payload = [0u8, b'\n', 255];
+
+        Broadcast::with_payload(
+            &mut server_to_client_stream,
+            &Message::FetchResponse {
+                response: Ok(payload.len()),
+            },
+            &payload,
+        )
+        .unwrap();
+        Broadcast::to(&mut server_to_client_stream, &Message::Heartbeat).unwrap();
+
+        let message = crate::Reader::read_one_message(&mut client_to_server_stream).unwrap();
+        let read_payload =
+            crate::Reader::read_payload(&mut client_to_server_stream, &message).unwrap();
+
+        assert_eq!(read_payload, payload);
+
+        // The next message starts right after the payload
+        let message = crate::Reader::read_one_message(&mut client_to_server_stream).unwrap();
+        assert!(matches!(message, Message::Heartbeat));
+        assert!(
+            crate::Reader::read_payload(&mut client_to_server_stream, &message)
+                .unwrap()
+                .is_empty()
+        );
+    }
 }
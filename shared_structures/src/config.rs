@@ -0,0 +1,390 @@
+use std::{collections::HashMap, fs, path::PathBuf, time::Duration};
+
+use crate::topic::CleanupPolicy;
+
+// Environment variables starting with this prefix override the keys of the configuration file,
+// e.g. `NYX_REPLICA_FACTOR=2` overrides `replica_factor`.
+const ENV_PREFIX: &str = "NYX_";
+
+const KEYS: [&str; 15] = [
+    "host",
+    "port",
+    "advertised_addr",
+    "replica_factor",
+    "throttle",
+    "session_timeout",
+    "retention_period",
+    "max_message_size",
+    "cleanup_policy",
+    "min_insync_replicas",
+    "data_dir",
+    "strategy",
+    "metrics_addr",
+    "trace_exporter",
+    "admin_addr",
+];
+
+/// The strategy by which partitions are spread out between the brokers of the cluster.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Strategy {
+    #[default]
+    Balanced,
+}
+
+/// Configuration shared by the Observer and the broker, every key is optional and falls back to its default.
+///
+/// Durations accept the units `ms`, `s`, `m`, `h` and `d` (e.g. `7d`), plain numbers are milliseconds.
+/// Sizes accept the units `B`, `KB`, `MB`, `GB` and their binary counterparts `KiB`, `MiB`, `GiB` (e.g. `64MiB`),
+/// plain numbers are bytes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    /// Host the listener binds to.
+    pub host: String,
+    /// Port the listener binds to, each component has its own default.
+    pub port: Option<u16>,
+    /// Address other components connect to, defaults to the address of the listener.
+    pub advertised_addr: Option<String>,
+    pub replica_factor: usize,
+    pub throttle: Duration,
+    pub session_timeout: Duration,
+    pub retention_period: Duration,
+    pub max_message_size: u64,
+    pub cleanup_policy: CleanupPolicy,
+    pub min_insync_replicas: usize,
+    pub data_dir: Option<PathBuf>,
+    pub strategy: Strategy,
+    /// Address the Prometheus metrics are served on at `/metrics`, metrics are not served when not set.
+    pub metrics_addr: Option<String>,
+    /// OTLP/HTTP endpoint or file the trace spans are exported to, spans are not recorded when not set.
+    pub trace_exporter: Option<String>,
+    /// Address the Observer serves admin clients on, defaults to the loopback interface.
+    pub admin_addr: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            host: "localhost".to_string(),
+            port: None,
+            advertised_addr: None,
+            replica_factor: 3,
+            throttle: Duration::from_millis(500),
+            session_timeout: Duration::from_secs(10),
+            retention_period: Duration::from_secs(7 * 24 * 60 * 60),
+            max_message_size: 1024 * 1024,
+            cleanup_policy: CleanupPolicy::Delete,
+            min_insync_replicas: 1,
+            data_dir: None,
+            strategy: Strategy::Balanced,
+            metrics_addr: None,
+            trace_exporter: None,
+            admin_addr: None,
+        }
+    }
+}
+
+impl Config {
+    /// Reads the `key=value` properties file at `path` and applies the `NYX_*` environment overrides on top of it.
+    pub fn from(path: PathBuf) -> Result<Self, String> {
+        let content =
+            fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Self::parse(&content, &Self::env_overrides())
+    }
+
+    /// Configuration made of the defaults and the `NYX_*` environment overrides, for when there is no configuration file.
+    pub fn from_env() -> Result<Self, String> {
+        Self::parse("", &Self::env_overrides())
+    }
+
+    fn env_overrides() -> HashMap<String, String> {
+        std::env::vars()
+            .filter(|(k, _)| k.starts_with(ENV_PREFIX))
+            .collect()
+    }
+
+    /// Parses the properties `content`, `overrides` are environment variables such as `NYX_PORT`.
+    /// All the invalid keys are reported together in the returned error.
+    pub fn parse(content: &str, overrides: &HashMap<String, String>) -> Result<Self, String> {
+        let mut errors: Vec<String> = vec![];
+        let mut properties: HashMap<String, String> = HashMap::new();
+
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                errors.push(format!(
+                    "line {}: property format is incorrect, should be key=value",
+                    i + 1
+                ));
+                continue;
+            };
+
+            let key = key.trim();
+
+            if !KEYS.contains(&key) {
+                errors.push(format!("`{}`: unknown key", key));
+            } else if properties
+                .insert(key.to_string(), value.trim().to_string())
+                .is_some()
+            {
+                errors.push(format!("`{}`: defined more than once", key));
+            }
+        }
+
+        for key in KEYS {
+            if let Some(value) = overrides.get(&env_var_name(key)) {
+                properties.insert(key.to_string(), value.trim().to_string());
+            }
+        }
+
+        let mut config = Self::default();
+
+        for (key, value) in properties.iter() {
+            let result = match key.as_str() {
+                "host" => {
+                    config.host = value.clone();
+                    Ok(())
+                }
+                "port" => parse_number(value).map(|v| config.port = Some(v)),
+                "advertised_addr" => {
+                    config.advertised_addr = Some(value.clone());
+                    Ok(())
+                }
+                "replica_factor" => parse_number(value).and_then(|v| {
+                    if v == 0 {
+                        Err("should be at least 1".to_string())
+                    } else {
+                        config.replica_factor = v;
+                        Ok(())
+                    }
+                }),
+                "throttle" => parse_duration(value).map(|v| config.throttle = v),
+                "session_timeout" => parse_duration(value).and_then(|v| {
+                    if v.is_zero() {
+                        Err("should be greater than 0".to_string())
+                    } else {
+                        config.session_timeout = v;
+                        Ok(())
+                    }
+                }),
+                "retention_period" => parse_duration(value).map(|v| config.retention_period = v),
+                "max_message_size" => parse_size(value).map(|v| config.max_message_size = v),
+                "cleanup_policy" => CleanupPolicy::parse(value).map(|v| config.cleanup_policy = v),
+                "min_insync_replicas" => {
+                    parse_number(value).map(|v| config.min_insync_replicas = v)
+                }
+                "data_dir" => {
+                    config.data_dir = Some(value.into());
+                    Ok(())
+                }
+                "metrics_addr" => {
+                    config.metrics_addr = Some(value.clone());
+                    Ok(())
+                }
+                "trace_exporter" => {
+                    config.trace_exporter = Some(value.clone());
+                    Ok(())
+                }
+                "admin_addr" => {
+                    config.admin_addr = Some(value.clone());
+                    Ok(())
+                }
+                "strategy" => match value.as_str() {
+                    "balanced" => {
+                        config.strategy = Strategy::Balanced;
+                        Ok(())
+                    }
+                    _ => Err(format!(
+                        "unknown strategy `{}`, supported strategies: balanced",
+                        value
+                    )),
+                },
+                _ => unreachable!("only known keys are collected"),
+            };
+
+            if let Err(e) = result {
+                let source = if overrides.contains_key(&env_var_name(key)) {
+                    format!(" (set by {})", env_var_name(key))
+                } else {
+                    String::new()
+                };
+                errors.push(format!("`{}`{}: {}", key, source, e));
+            }
+        }
+
+        if errors.is_empty()
+            && (config.min_insync_replicas == 0
+                || config.min_insync_replicas > config.replica_factor)
+        {
+            errors.push(format!(
+                "`min_insync_replicas`: should be between 1 and `replica_factor` ({})",
+                config.replica_factor
+            ));
+        }
+
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            errors.sort();
+            Err(format!("Invalid configuration:\n  {}", errors.join("\n  ")))
+        }
+    }
+}
+
+fn env_var_name(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.to_uppercase())
+}
+
+pub(crate) fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse::<T>()
+        .map_err(|_| format!("expected a whole number, got `{}`", value))
+}
+
+// Splits values such as `64MiB` or `1.5e3ms` into the number and the unit
+fn split_unit(value: &str) -> Result<(f64, &str), String> {
+    let unit_start = value
+        .rfind(|c: char| !c.is_ascii_alphabetic())
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    let (number, unit) = value.split_at(unit_start);
+    let number = number
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|n| n.is_finite() && *n >= 0.0)
+        .ok_or(format!("expected a non-negative number, got `{}`", value))?;
+    Ok((number, unit))
+}
+
+/// Parses durations like `500`, `1.5s` or `7d`, numbers without a unit are milliseconds.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (number, unit) = split_unit(value)?;
+
+    let millis_per_unit: f64 = match unit {
+        "" | "ms" => 1.0,
+        "s" => 1000.0,
+        "m" => 60.0 * 1000.0,
+        "h" => 60.0 * 60.0 * 1000.0,
+        "d" => 24.0 * 60.0 * 60.0 * 1000.0,
+        _ => {
+            return Err(format!(
+                "unknown duration unit `{}`, supported units: ms, s, m, h, d",
+                unit
+            ))
+        }
+    };
+
+    Duration::try_from_secs_f64(number * millis_per_unit / 1000.0)
+        .map_err(|_| format!("duration `{}` is too long", value))
+}
+
+/// Parses sizes like `1024`, `64MiB` or `1.5GB`, numbers without a unit are bytes.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let (number, unit) = split_unit(value)?;
+
+    let bytes_per_unit: f64 = match unit {
+        "" | "B" => 1.0,
+        "KB" => 1e3,
+        "MB" => 1e6,
+        "GB" => 1e9,
+        "KiB" => 1024.0,
+        "MiB" => 1024.0 * 1024.0,
+        "GiB" => 1024.0 * 1024.0 * 1024.0,
+        _ => {
+            return Err(format!(
+                "unknown size unit `{}`, supported units: B, KB, MB, GB, KiB, MiB, GiB",
+                unit
+            ))
+        }
+    };
+
+    Ok((number * bytes_per_unit).round() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_keys_fall_back_to_defaults() {
+        let config = Config::parse("# Nothing but a comment\n", &HashMap::new()).unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn parses_typed_values_with_units() {
+        let content = "host=0.0.0.0\nport=3000\nadvertised_addr=nyx-1.local:3000\nreplica_factor=2\nthrottle=1.5e3\nsession_timeout=15s\nretention_period=7d\nmax_message_size=64MiB\ndata_dir=/var/lib/nyx\nstrategy=balanced\nmetrics_addr=0.0.0.0:9100\ntrace_exporter=http://localhost:4318\nadmin_addr=localhost:3000";
+        let config = Config::parse(content, &HashMap::new()).unwrap();
+
+        assert_eq!(config.host, "0.0.0.0");
+        assert_eq!(config.port, Some(3000));
+        assert_eq!(config.advertised_addr, Some("nyx-1.local:3000".to_string()));
+        assert_eq!(config.replica_factor, 2);
+        assert_eq!(config.throttle, Duration::from_millis(1500));
+        assert_eq!(config.session_timeout, Duration::from_secs(15));
+        assert_eq!(config.retention_period, Duration::from_secs(604800));
+        assert_eq!(config.max_message_size, 64 * 1024 * 1024);
+        assert_eq!(config.data_dir, Some(PathBuf::from("/var/lib/nyx")));
+        assert_eq!(config.strategy, Strategy::Balanced);
+        assert_eq!(config.metrics_addr, Some("0.0.0.0:9100".to_string()));
+        assert_eq!(
+            config.trace_exporter,
+            Some("http://localhost:4318".to_string())
+        );
+        assert_eq!(config.admin_addr, Some("localhost:3000".to_string()));
+    }
+
+    #[test]
+    fn environment_overrides_the_file() {
+        let overrides = HashMap::from([
+            ("NYX_REPLICA_FACTOR".to_string(), "1".to_string()),
+            ("NYX_UNRELATED".to_string(), "ignored".to_string()),
+        ]);
+        let config = Config::parse("replica_factor=3", &overrides).unwrap();
+        assert_eq!(config.replica_factor, 1);
+    }
+
+    #[test]
+    fn reports_every_invalid_key() {
+        let overrides = HashMap::from([("NYX_PORT".to_string(), "70000".to_string())]);
+        let content = "replica_factor=1.5e3\nthrottle=10 parsecs\nmax_message_size=64MiBs\nreplcia_factor=2\nstrategy=random\nnonsense";
+        let error = Config::parse(content, &overrides).unwrap_err();
+
+        for expected in [
+            "`port` (set by NYX_PORT)",
+            "`replica_factor`",
+            "`throttle`",
+            "`max_message_size`",
+            "`replcia_factor`: unknown key",
+            "`strategy`",
+            "line 6",
+        ] {
+            assert!(
+                error.contains(expected),
+                "{} is missing in {}",
+                expected,
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn parses_durations_and_sizes() {
+        assert_eq!(parse_duration("500").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("2m").unwrap(), Duration::from_secs(120));
+        assert_eq!(parse_duration("1h").unwrap(), Duration::from_secs(3600));
+        assert!(parse_duration("-1s").is_err());
+        assert!(parse_duration("1w").is_err());
+        assert!(parse_duration("1e300d").is_err());
+
+        assert_eq!(parse_size("1024").unwrap(), 1024);
+        assert_eq!(parse_size("1.5KB").unwrap(), 1500);
+        assert_eq!(parse_size("1GiB").unwrap(), 1 << 30);
+        assert!(parse_size("MiB").is_err());
+    }
+}
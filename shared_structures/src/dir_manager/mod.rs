@@ -1,19 +1,29 @@
 use std::{
     fmt::Debug,
     fs::{self},
-    io::Write,
-    path::PathBuf,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
 };
 
-#[derive(Debug, Default)]
+const TMP_EXTENSION: &str = "tmp";
+const BACKUP_EXTENSION: &str = "bak";
+
+/// Environment variable pointing to the root directory Nyx stores its data in.
+pub const DATA_DIR_ENV: &str = "NYX_DATA_DIR";
+
+#[derive(Clone, Debug, Default)]
 pub struct DirManager {
+    root: Option<PathBuf>,
     custom_dir: Option<PathBuf>,
 }
 
 impl DirManager {
-    /// Creates a directory manager in the predefined path of the root directory for Nyx.   
+    /// Creates a directory manager in the predefined path of the root directory for Nyx.
     pub fn new() -> Self {
-        Self { custom_dir: None }
+        Self {
+            root: None,
+            custom_dir: None,
+        }
     }
 
     /// Create a directory manager in the predefined path of the root directory
@@ -26,12 +36,61 @@ impl DirManager {
     /// work in the context of the Nyx foder.
     pub fn with_dir(custom_dir: Option<&PathBuf>) -> Self {
         Self {
+            root: None,
+            custom_dir: custom_dir.cloned(),
+        }
+    }
+
+    /// Same as `with_dir` but works in the context of `root` instead of the Nyx folder
+    /// in the home directory, when `root` is provided.
+    pub fn with_root(root: Option<&PathBuf>, custom_dir: Option<&PathBuf>) -> Self {
+        Self {
+            root: root.cloned(),
             custom_dir: custom_dir.cloned(),
         }
     }
 
+    /// Same as `with_root` with the root in the temporary directory, tests work in it
+    /// so they never touch the user's Nyx folder.
+    pub fn for_tests(custom_dir: Option<&PathBuf>) -> Self {
+        Self::with_root(Some(&Self::test_root()), custom_dir)
+    }
+
+    /// The root directory tests keep their files in.
+    pub fn test_root() -> PathBuf {
+        std::env::temp_dir().join("nyx_tests")
+    }
+
+    /// Creates a directory manager for the `dir` sub-directory of this manager's directory.
+    pub fn join(&self, dir: &str) -> Self {
+        let custom_dir = match &self.custom_dir {
+            Some(custom_dir) => custom_dir.join(dir),
+            None => dir.into(),
+        };
+
+        Self {
+            root: self.root.clone(),
+            custom_dir: Some(custom_dir),
+        }
+    }
+
+    /// The directory this manager works in.
+    pub fn base_dir(&self) -> Result<PathBuf, String> {
+        let mut dir = match &self.root {
+            Some(root) => root.clone(),
+            None => Self::get_default_root()?,
+        };
+
+        if let Some(custom_dir) = &self.custom_dir {
+            // Custom directories are always relative to the root, even when they start with a `/`
+            dir.push(custom_dir.strip_prefix("/").unwrap_or(custom_dir));
+        }
+
+        Ok(dir)
+    }
+
     pub fn create(&self, path: &str) -> Result<PathBuf, String> {
-        let nyx_dir = Self::get_base_dir(self.custom_dir.as_ref())?;
+        let nyx_dir = self.base_dir()?;
         let nyx_dir_str = nyx_dir
             .to_str()
             .ok_or("Failed while validating UTF-8 path integrity.")?;
@@ -39,38 +98,77 @@ impl DirManager {
         match fs::create_dir_all(&total_path) {
             Ok(_) => {}
             Err(e) => {
-                println!("DirManager -> create func error: {}", e)
+                log::warn!("Failed to create the directory {}: {}", total_path, e)
             }
         };
         Ok(total_path.into())
     }
 
+    /// Saves `content` to `path` crash-safely, the content is written to a temporary file first
+    /// which then atomically replaces the previous version. The previous version is kept
+    /// as a backup next to it with the `.bak` extension.
+    ///
+    /// Temporary files are unique per save so concurrent saves of the same file don't
+    /// write into each other's temporary file.
     pub fn save<'de, T: serde::Serialize + serde::Deserialize<'de>>(
         &self,
         path: &str,
         content: &T,
     ) -> Result<(), String> {
-        let nyx_dir = Self::get_base_dir(self.custom_dir.as_ref())?;
-        fs::create_dir_all(nyx_dir).map_err(|e| e.to_string())?;
-        let filepath = Self::get_filepath(path, self.custom_dir.as_ref())?;
-        let mut file = std::fs::File::create(filepath).map_err(|e| e.to_string())?;
+        let nyx_dir = self.base_dir()?;
+        fs::create_dir_all(&nyx_dir).map_err(|e| e.to_string())?;
+        let filepath = self.get_filepath(path)?;
+        let tmp_filepath = unique_tmp_filepath(&filepath);
+        let backup_filepath = with_extension_suffix(&filepath, BACKUP_EXTENSION);
+
         let payload = serde_json::to_string(content).map_err(|e| e.to_string())?;
-        file.write(payload.as_bytes()).map_err(|e| e.to_string())?;
-        Ok(())
+
+        let mut file = fs::File::create(&tmp_filepath).map_err(|e| e.to_string())?;
+        file.write_all(payload.as_bytes())
+            .map_err(|e| e.to_string())?;
+        file.sync_all().map_err(|e| e.to_string())?;
+        drop(file);
+
+        if filepath.exists() {
+            backup(&filepath, &backup_filepath)?;
+        }
+
+        fs::rename(&tmp_filepath, &filepath).map_err(|e| e.to_string())?;
+
+        sync_dir(&nyx_dir)
     }
 
     pub fn open<T: Debug + serde::Serialize + serde::de::DeserializeOwned>(
         &self,
         path: &str,
     ) -> Result<T, String> {
-        let filepath = Self::get_filepath(path, self.custom_dir.as_ref())?;
-        let content = fs::read_to_string(filepath).map_err(|e| e.to_string())?;
-        let data = serde_json::from_str::<T>(&content).map_err(|e| e.to_string())?;
-        Ok(data)
+        self.open_optional(path)?.ok_or(format!(
+            "{} does not exist.",
+            self.get_filepath(path)?.display()
+        ))
+    }
+
+    /// Same as `open` but a file that doesn't exist yet is not an error, a file that exists
+    /// but can't be read or parsed is reported as corrupted.
+    pub fn open_optional<T: Debug + serde::Serialize + serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+    ) -> Result<Option<T>, String> {
+        let filepath = self.get_filepath(path)?;
+
+        let content = match fs::read_to_string(&filepath) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(corrupted_file_error(&filepath, &e.to_string())),
+        };
+
+        serde_json::from_str::<T>(&content)
+            .map(Some)
+            .map_err(|e| corrupted_file_error(&filepath, &e.to_string()))
     }
 
-    fn get_filepath(path: &str, custom_path: Option<&PathBuf>) -> Result<PathBuf, String> {
-        let dir = Self::get_base_dir(custom_path)?;
+    fn get_filepath(&self, path: &str) -> Result<PathBuf, String> {
+        let dir = self.base_dir()?;
         let dir_str = dir
             .to_str()
             .ok_or("Not valid UTF-8 path is passed.".to_string())?;
@@ -80,33 +178,101 @@ impl DirManager {
     }
 
     pub fn get_base_dir(custom_path: Option<&PathBuf>) -> Result<PathBuf, String> {
-        let final_path = if let Some(custom_path) = custom_path {
-            let dist = custom_path
-                .clone()
-                .to_str()
-                .ok_or("Invalid format provided for the directory")?
-                .to_string();
-            format!("nyx/{}", dist)
-        } else {
-            "nyx".to_string()
-        };
+        Self::with_dir(custom_path).base_dir()
+    }
+
+    /// The root directory for Nyx when none is provided explicitly, it's taken from the `NYX_DATA_DIR`
+    /// environment variable and falls back to the Nyx folder in the system's configuration directory.
+    pub fn get_default_root() -> Result<PathBuf, String> {
+        if let Some(data_dir) = std::env::var_os(DATA_DIR_ENV).filter(|d| !d.is_empty()) {
+            return Ok(data_dir.into());
+        }
 
         let mut final_dir: Option<PathBuf> = None;
         // Unix-based machines
         if let Ok(home_dir) = std::env::var("HOME") {
-            let config_dir = format!("{}/.config/{}", home_dir, final_path);
-            final_dir = Some(config_dir.into());
+            final_dir = Some(PathBuf::from(home_dir).join(".config").join("nyx"));
         }
         // Windows based machines
         else if let Ok(user_profile) = std::env::var("USERPROFILE") {
-            let config_dir = format!(r"{}/AppData/Roaming/{}", user_profile, final_path);
-            final_dir = Some(config_dir.into());
+            final_dir = Some(
+                PathBuf::from(user_profile)
+                    .join("AppData")
+                    .join("Roaming")
+                    .join("nyx"),
+            );
         }
 
-        final_dir.ok_or("Couldn't get the systems home directory. Please setup a HOME env variable and pass your system's home directory there.".to_string())
+        final_dir.ok_or(format!("Couldn't get the systems home directory. Please setup a HOME env variable and pass your system's home directory there, or set {} to the directory Nyx should store its data in.", DATA_DIR_ENV))
+    }
+}
+
+// `metadata.json` -> `metadata.json.bak`
+fn with_extension_suffix(filepath: &Path, suffix: &str) -> PathBuf {
+    let mut filepath = filepath.as_os_str().to_owned();
+    filepath.push(".");
+    filepath.push(suffix);
+    filepath.into()
+}
+
+// `metadata.json` -> `metadata.json.<uuid>.tmp`
+fn unique_tmp_filepath(filepath: &Path) -> PathBuf {
+    with_extension_suffix(
+        filepath,
+        &format!("{}.{}", uuid::Uuid::new_v4(), TMP_EXTENSION),
+    )
+}
+
+// Keeps the current version of `filepath` in `backup_filepath`. The current version was synced
+// when it was saved, so hard linking it is enough and avoids copying the whole file on every save.
+// File systems without hard links get a synced copy instead. Either way the backup is prepared
+// under a unique name and renamed into place, so it's never torn.
+fn backup(filepath: &Path, backup_filepath: &Path) -> Result<(), String> {
+    let tmp_backup_filepath = unique_tmp_filepath(backup_filepath);
+
+    if fs::hard_link(filepath, &tmp_backup_filepath).is_err() {
+        fs::copy(filepath, &tmp_backup_filepath).map_err(|e| e.to_string())?;
+        fs::File::open(&tmp_backup_filepath)
+            .and_then(|f| f.sync_all())
+            .map_err(|e| e.to_string())?;
+    }
+
+    fs::rename(&tmp_backup_filepath, backup_filepath).map_err(|e| e.to_string())
+}
+
+fn corrupted_file_error(filepath: &Path, error: &str) -> String {
+    let backup_filepath = with_extension_suffix(filepath, BACKUP_EXTENSION);
+
+    if backup_filepath.exists() {
+        format!(
+            "{} is corrupted ({}). The previous version is kept in {}, restore it or remove the file to start over.",
+            filepath.display(),
+            error,
+            backup_filepath.display()
+        )
+    } else {
+        format!(
+            "{} is corrupted ({}). Remove the file to start over.",
+            filepath.display(),
+            error
+        )
     }
 }
 
+// Makes sure the rename of a file in `dir` is persisted as well
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> Result<(), String> {
+    fs::File::open(dir)
+        .and_then(|d| d.sync_all())
+        .map_err(|e| e.to_string())
+}
+
+// Directories can't be opened as files on other platforms, renames are persisted by the file system
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> Result<(), String> {
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -118,7 +284,7 @@ mod tests {
     }
 
     fn setup_nyx_dir_with_local_metadata(custom_dir: &PathBuf) -> DirManager {
-        let file_manager = DirManager::with_dir(Some(custom_dir));
+        let file_manager = DirManager::for_tests(Some(custom_dir));
 
         file_manager
             .save(
@@ -134,29 +300,31 @@ mod tests {
     }
 
     fn cleanup_nyx_dir(custom_dir: &PathBuf) {
-        let nyx_dir = DirManager::get_base_dir(Some(custom_dir)).unwrap();
+        let nyx_dir = DirManager::for_tests(Some(custom_dir)).base_dir().unwrap();
         fs::remove_dir_all(nyx_dir).unwrap();
     }
 
     #[test]
     #[cfg_attr(miri, ignore)]
     fn get_local_metadata_directory_returns_dir_as_expected() {
-        let dir = DirManager::get_base_dir(None).unwrap();
-        assert!(dir.to_str().unwrap().contains("nyx"));
+        let dir = DirManager::for_tests(None).base_dir().unwrap();
+        assert_eq!(dir, DirManager::test_root());
     }
 
     #[test]
     #[cfg_attr(miri, ignore)]
     fn get_local_metadata_filepath_returns_filepath_as_expected() {
-        let filepath = DirManager::get_filepath("metadata.json", None).unwrap();
-        assert!(filepath.to_str().unwrap().contains("nyx/metadata.json"));
+        let filepath = DirManager::for_tests(None)
+            .get_filepath("metadata.json")
+            .unwrap();
+        assert_eq!(filepath, DirManager::test_root().join("metadata.json"));
     }
 
     #[test]
     #[cfg_attr(miri, ignore)]
     fn save_local_metadata_file_saves_file_to_designated_dir() {
         let custom_dir: PathBuf = "save_metadata_file_saves_file_to_designated_dir".into();
-        let file_manager = DirManager::with_dir(Some(&custom_dir));
+        let file_manager = DirManager::for_tests(Some(&custom_dir));
         file_manager
             .save(
                 "metadata.json",
@@ -166,7 +334,7 @@ mod tests {
                 },
             )
             .unwrap();
-        let filepath = DirManager::get_filepath("metadata.json", Some(&custom_dir)).unwrap();
+        let filepath = file_manager.get_filepath("metadata.json").unwrap();
         let file = fs::File::open(filepath);
         assert!(file.is_ok());
         cleanup_nyx_dir(&custom_dir);
@@ -181,4 +349,153 @@ mod tests {
         assert!(result.is_ok());
         cleanup_nyx_dir(&custom_dir);
     }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn custom_dir_is_joined_without_duplicate_separators() {
+        let dir = DirManager::for_tests(Some(&"/broker/x".into()))
+            .base_dir()
+            .unwrap();
+        assert!(!dir.to_str().unwrap().contains("//"));
+        assert!(dir.ends_with("broker/x"));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn with_root_works_outside_of_nyx_dir() {
+        let root = DirManager::test_root().join("with_root_works_outside_of_nyx_dir");
+        let custom_dir: PathBuf = "/broker/test".into();
+        let file_manager = DirManager::with_root(Some(&root), Some(&custom_dir));
+
+        assert_eq!(file_manager.base_dir().unwrap(), root.join("broker/test"));
+        assert_eq!(
+            file_manager.join("storage").base_dir().unwrap(),
+            root.join("broker/test/storage")
+        );
+
+        file_manager
+            .save(
+                "metadata.json",
+                &LocalMetadata {
+                    id: "broker_metadata_id".to_string(),
+                    partitions: vec![],
+                },
+            )
+            .unwrap();
+        assert!(root.join("broker/test/metadata.json").exists());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn save_keeps_previous_version_as_backup() {
+        let root = DirManager::test_root().join("save_keeps_previous_version_as_backup");
+        let _ = fs::remove_dir_all(&root);
+        let file_manager = DirManager::with_root(Some(&root), None);
+
+        for id in ["first_id", "second_id"] {
+            file_manager
+                .save(
+                    "metadata.json",
+                    &LocalMetadata {
+                        id: id.to_string(),
+                        partitions: vec![],
+                    },
+                )
+                .unwrap();
+        }
+
+        let current = file_manager.open::<LocalMetadata>("metadata.json").unwrap();
+        let backup = file_manager
+            .open::<LocalMetadata>("metadata.json.bak")
+            .unwrap();
+        assert_eq!(current.id, "second_id");
+        assert_eq!(backup.id, "first_id");
+        assert!(fs::read_dir(&root).unwrap().all(|f| !f
+            .unwrap()
+            .path()
+            .to_str()
+            .unwrap()
+            .ends_with(".tmp")));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn concurrent_saves_of_the_same_file_succeed() {
+        let root = DirManager::test_root().join("concurrent_saves_of_the_same_file_succeed");
+        let _ = fs::remove_dir_all(&root);
+        let file_manager = DirManager::with_root(Some(&root), None);
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let file_manager = file_manager.clone();
+                std::thread::spawn(move || {
+                    for j in 0..10 {
+                        file_manager
+                            .save(
+                                "metadata.json",
+                                &LocalMetadata {
+                                    id: format!("{}_{}", i, j),
+                                    partitions: vec![],
+                                },
+                            )
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert!(file_manager.open::<LocalMetadata>("metadata.json").is_ok());
+        assert!(file_manager
+            .open::<LocalMetadata>("metadata.json.bak")
+            .is_ok());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn open_optional_reports_corrupted_file() {
+        let root = DirManager::test_root().join("open_optional_reports_corrupted_file");
+        let _ = fs::remove_dir_all(&root);
+        let file_manager = DirManager::with_root(Some(&root), None);
+
+        let missing = file_manager.open_optional::<LocalMetadata>("metadata.json");
+        assert!(matches!(missing, Ok(None)));
+
+        // Second save leaves a backup behind
+        for _ in 0..2 {
+            file_manager
+                .save(
+                    "metadata.json",
+                    &LocalMetadata {
+                        id: "some_mocked_id".to_string(),
+                        partitions: vec![],
+                    },
+                )
+                .unwrap();
+        }
+
+        // Half written file
+        fs::write(
+            root.join("metadata.json"),
+            r#"{"id":"some_mocked_id","partiti"#,
+        )
+        .unwrap();
+
+        let error = file_manager
+            .open_optional::<LocalMetadata>("metadata.json")
+            .unwrap_err();
+        assert!(error.contains("corrupted"));
+        assert!(error.contains("metadata.json.bak"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
 }
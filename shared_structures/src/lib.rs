@@ -4,14 +4,25 @@ mod message_decoder;
 mod reader;
 mod topic;
 
+pub mod admin;
+pub mod config;
+pub mod logger;
 pub mod metadata;
+pub mod metrics;
+pub mod record;
+pub mod tracing;
 
+pub use admin::{AdminRequest, AdminResponse};
 pub use broadcast::Broadcast;
-pub use dir_manager::DirManager;
+pub use config::Config;
+pub use dir_manager::{DirManager, DATA_DIR_ENV};
 pub use message_decoder::MessageDecoder;
-pub use metadata::Metadata;
+pub use metadata::{LogEntry, Metadata};
+pub use metrics::Metrics;
 pub use reader::Reader;
-pub use topic::Topic;
+pub use record::Record;
+pub use topic::{CleanupPolicy, Topic, TopicConfig};
+pub use tracing::TraceContext;
 
 #[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
 pub enum Status {
@@ -29,10 +40,24 @@ pub enum Role {
     Leader,
 }
 
+// Position in the log of a partition looked up with `ListOffsets`
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum OffsetSpec {
+    // Offset of the first record of the log
+    Earliest,
+    // Offset the next appended record is going to get
+    Latest,
+    // Offset of the first record appended at or after the timestamp in milliseconds since
+    // the unix epoch, the latest offset when there is none.
+    Timestamp(u64),
+}
+
 #[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
 pub enum EntityType {
     Broker,
     Observer,
+    // Admin clients such as `nyx-admin`
+    Admin,
 }
 
 // TODO: Think of a way to better organize this enum or split it into more enums
@@ -44,6 +69,9 @@ pub enum Message {
         topic: Topic,
         partition_number: usize,
         replica_count: usize,
+        // Trace context of the Observer's request, see `tracing`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        traceparent: Option<TraceContext>,
     },
     RequestLeadership {
         broker_id: String,
@@ -57,6 +85,9 @@ pub enum Message {
     BrokerConnectionDetails {
         id: String,
         addr: String,
+        // Failure domain (rack / zone) the broker is running in, used by
+        // the Observer to spread replicas of a partition across racks.
+        rack: Option<String>,
     },
     ProducerWantsToConnect {
         topic: String,
@@ -68,20 +99,157 @@ pub enum Message {
         entity_type: EntityType,
     },
     RequestClusterMetadata,
+    // Sent periodically by brokers so the Observer knows they are alive
+    Heartbeat,
+    // Sent by a broker that is shutting down, the Observer moves the leadership
+    // off its replicas and answers with `LeaveAcknowledged`.
+    BrokerLeaving,
+    LeaveAcknowledged,
     ClusterMetadata {
         metadata: Metadata,
     },
+    // Followed by the record in the binary encoding of `Record::encode`, `record_size` bytes long
     ProducerMessage {
         replica_id: String,
-        payload: serde_json::Value,
+        record_size: usize,
+        // Trace context of the producer, only sent while the producer is traced
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        traceparent: Option<TraceContext>,
+    },
+    // Answers a `ProducerMessage` with the offset the record was appended at, or why it was rejected
+    ProduceResponse {
+        response: Result<u64, String>,
+    },
+    // Requests up to `max_records` records of a partition replica starting at `offset`, adding up to at
+    // most `max_bytes`. The broker waits up to `max_wait_ms` for at least `min_bytes` to be available.
+    Fetch {
+        replica_id: String,
+        offset: u64,
+        max_records: usize,
+        min_bytes: usize,
+        max_bytes: usize,
+        max_wait_ms: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        traceparent: Option<TraceContext>,
+    },
+    // On success followed by the fetched records encoded by `record::encode_batch`,
+    // the size of the batch in bytes is the one of the response.
+    FetchResponse {
+        response: Result<usize, String>,
+    },
+    // Looks up an offset of a partition replica, answered with `ListOffsetsResponse`
+    ListOffsets {
+        replica_id: String,
+        spec: OffsetSpec,
+    },
+    ListOffsetsResponse {
+        response: Result<u64, String>,
+    },
+    // Stores `offset` as the offset consumer group `group` resumes a partition replica from,
+    // answered with `CommitOffsetResponse`
+    CommitOffset {
+        replica_id: String,
+        group: String,
+        offset: u64,
+    },
+    CommitOffsetResponse {
+        response: Result<(), String>,
+    },
+    // Looks up the offset consumer group `group` has committed last, answered with
+    // `FetchCommittedOffsetResponse` which holds `None` while the group hasn't committed any
+    FetchCommittedOffset {
+        replica_id: String,
+        group: String,
+    },
+    FetchCommittedOffsetResponse {
+        response: Result<Option<u64>, String>,
+    },
+    // Subscribes the connection to a partition replica, records from `offset` on are pushed to it
+    // with `PushedRecords` as soon as they are appended, as long as it has `credit` records left.
+    Subscribe {
+        replica_id: String,
+        offset: u64,
+        credit: usize,
+    },
+    SubscribeResponse {
+        response: Result<(), String>,
+    },
+    // Grants a subscription `credit` more records
+    Credit {
+        replica_id: String,
+        credit: usize,
+    },
+    // Answered with `Unsubscribed`, after which no more records of the replica are pushed
+    Unsubscribe {
+        replica_id: String,
+    },
+    Unsubscribed {
+        replica_id: String,
+    },
+    // Followed by the pushed records encoded by `record::encode_batch`, `batch_size` bytes long
+    PushedRecords {
+        replica_id: String,
+        batch_size: usize,
+    },
+    // Sent by an Observer which is not the leader to a connecting broker,
+    // `leader_addr` is `None` while there is no elected leader yet.
+    NotLeader {
+        leader_addr: Option<String>,
+    },
+    AdminRequest {
+        request: AdminRequest,
+    },
+    // Mutations sent to an Observer which is not the leader are answered with `NotLeader` instead
+    AdminResponse {
+        response: Result<AdminResponse, String>,
+    },
+    RequestVote {
+        term: u64,
+        candidate_addr: String,
+        last_log_index: u64,
+        last_log_term: u64,
+    },
+    RequestVoteResponse {
+        term: u64,
+        vote_granted: bool,
+    },
+    AppendEntries {
+        term: u64,
+        leader_addr: String,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<LogEntry>,
+        leader_commit: u64,
+    },
+    // On success `match_index` is the index of the last replicated entry,
+    // otherwise it is the last index in the follower's log.
+    AppendEntriesResponse {
+        term: u64,
+        success: bool,
+        match_index: u64,
+    },
+    // Sent by a leader handing its leadership over, the receiving Observer starts an
+    // election right away instead of waiting for the election timeout.
+    TimeoutNow {
+        term: u64,
+    },
+    // Sent instead of `AppendEntries` to a follower missing entries the leader has compacted,
+    // answered with an `AppendEntriesResponse`.
+    InstallSnapshot {
+        term: u64,
+        leader_addr: String,
+        snapshot: LogEntry,
     },
 }
 
-pub fn println_c(text: &str, color: usize) {
-    if color > 255 {
-        panic!("Color is out of range 0 to 255");
+impl Message {
+    /// Size of the binary payload following the message on the wire, if any.
+    pub fn payload_size(&self) -> Option<usize> {
+        match self {
+            Message::ProducerMessage { record_size, .. } => Some(*record_size),
+            Message::FetchResponse { response: Ok(size) } => Some(*size),
+            Message::PushedRecords { batch_size, .. } => Some(*batch_size),
+            _ => None,
+        }
     }
-
-    let t = format!("\x1b[38;5;{}m{}\x1b[0m", color, text);
-    println!("{}", t)
 }
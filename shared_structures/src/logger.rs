@@ -0,0 +1,230 @@
+use std::{
+    io::{IsTerminal, Write},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Levels accepted by `--log-level`.
+pub const LEVELS: [&str; 6] = ["off", "error", "warn", "info", "debug", "trace"];
+
+/// Formats accepted by `--log-format`.
+pub const FORMATS: [&str; 2] = ["text", "json"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    // Human readable lines, levels are colored when written to a terminal
+    Text,
+    // One JSON object per line, for log collectors
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "unknown log format `{}`, supported formats: {}",
+                s,
+                FORMATS.join(", ")
+            )),
+        }
+    }
+}
+
+/// Writes the log records of every component to stderr, the target of a record is the
+/// module it was logged from, e.g. `observer::raft`.
+struct Logger {
+    level: LevelFilter,
+    format: LogFormat,
+    color: bool,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = format_record(
+            SystemTime::now(),
+            record.level(),
+            record.target(),
+            &record.args().to_string(),
+            self.format,
+            self.color,
+        );
+
+        // Nothing else to report a failed write to
+        let _ = writeln!(std::io::stderr().lock(), "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+/// Installs the logger of the process, `level` and `format` are the values of `--log-level` and `--log-format`.
+pub fn init(level: &str, format: &str) -> Result<(), String> {
+    let level = LevelFilter::from_str(level).map_err(|_| {
+        format!(
+            "unknown log level `{}`, supported levels: {}",
+            level,
+            LEVELS.join(", ")
+        )
+    })?;
+    let format = LogFormat::from_str(format)?;
+
+    let logger = Logger {
+        level,
+        format,
+        color: format == LogFormat::Text && std::io::stderr().is_terminal(),
+    };
+
+    log::set_boxed_logger(Box::new(logger)).map_err(|e| e.to_string())?;
+    log::set_max_level(level);
+
+    Ok(())
+}
+
+fn format_record(
+    time: SystemTime,
+    level: Level,
+    target: &str,
+    message: &str,
+    format: LogFormat,
+    color: bool,
+) -> String {
+    let timestamp = format_timestamp(time);
+
+    match format {
+        LogFormat::Text if color => {
+            let color = match level {
+                Level::Error => 1,
+                Level::Warn => 3,
+                Level::Info => 2,
+                Level::Debug => 4,
+                Level::Trace => 8,
+            };
+            format!(
+                "{} \x1b[38;5;{}m{:<5}\x1b[0m {}: {}",
+                timestamp, color, level, target, message
+            )
+        }
+        LogFormat::Text => format!("{} {:<5} {}: {}", timestamp, level, target, message),
+        LogFormat::Json => serde_json::json!({
+            "timestamp": timestamp,
+            "level": level.as_str(),
+            "target": target,
+            "message": message,
+        })
+        .to_string(),
+    }
+}
+
+// RFC 3339 timestamp in UTC with milliseconds, e.g. `2023-07-21T09:41:07.312Z`
+fn format_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let secs_of_day = secs % 86400;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+// Converts days since the unix epoch into a (year, month, day) date of the proleptic Gregorian calendar,
+// see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn formats_timestamps_in_utc() {
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            format_timestamp(UNIX_EPOCH + Duration::from_millis(1689932467312)),
+            "2023-07-21T09:41:07.312Z"
+        );
+        // Leap day
+        assert_eq!(
+            format_timestamp(UNIX_EPOCH + Duration::from_secs(1709164800)),
+            "2024-02-29T00:00:00.000Z"
+        );
+    }
+
+    #[test]
+    fn formats_records_as_text_and_json() {
+        let time = UNIX_EPOCH + Duration::from_millis(1689932467312);
+
+        assert_eq!(
+            format_record(
+                time,
+                Level::Info,
+                "observer::raft",
+                "Observer became the leader",
+                LogFormat::Text,
+                false
+            ),
+            "2023-07-21T09:41:07.312Z INFO  observer::raft: Observer became the leader"
+        );
+
+        let line = format_record(
+            time,
+            Level::Warn,
+            "broker",
+            "Replica \"a\" is gone",
+            LogFormat::Json,
+            false,
+        );
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+
+        assert_eq!(json["timestamp"], "2023-07-21T09:41:07.312Z");
+        assert_eq!(json["level"], "WARN");
+        assert_eq!(json["target"], "broker");
+        assert_eq!(json["message"], "Replica \"a\" is gone");
+    }
+
+    #[test]
+    fn rejects_unknown_levels_and_formats() {
+        assert!("yaml".parse::<LogFormat>().is_err());
+        assert!(init("verbose", "text").is_err());
+    }
+}
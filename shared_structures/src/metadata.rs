@@ -13,6 +13,7 @@ pub struct PartitionDetails {
 pub struct BrokerDetails {
     pub id: String,
     pub addr: String,
+    pub rack: Option<String>,
     pub status: Status,
     pub partitions: Vec<PartitionDetails>,
 }
@@ -22,3 +23,12 @@ pub struct Metadata {
     pub brokers: Vec<BrokerDetails>,
     pub topics: Vec<Topic>,
 }
+
+// An entry of the replicated metadata log shared between the Observers,
+// every entry holds a full snapshot of the cluster metadata at the time of the change.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct LogEntry {
+    pub term: u64,
+    pub index: u64,
+    pub metadata: Metadata,
+}
@@ -0,0 +1,388 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+// Upper bounds of the histogram buckets in seconds, requests are expected to take from microseconds to seconds
+const BUCKETS: [f64; 12] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5, 1.0,
+];
+
+// A scraper that stops sending its request shouldn't keep the others waiting
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl MetricKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Counter => "counter",
+            Self::Gauge => "gauge",
+            Self::Histogram => "histogram",
+        }
+    }
+}
+
+/// Description of a metric, components declare their metrics as constants.
+#[derive(Debug)]
+pub struct Metric {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub kind: MetricKind,
+}
+
+#[derive(Debug)]
+enum Sample {
+    Value(f64),
+    Histogram {
+        buckets: [u64; BUCKETS.len()],
+        sum: f64,
+        count: u64,
+    },
+}
+
+#[derive(Debug)]
+struct Family {
+    help: &'static str,
+    kind: MetricKind,
+    // Keyed by the label pairs of the sample
+    samples: BTreeMap<Vec<(String, String)>, Sample>,
+}
+
+/// Registry of the counters, gauges and histograms of a process, rendered
+/// in the Prometheus text format by the endpoint started with [`serve`].
+#[derive(Debug, Default)]
+pub struct Metrics {
+    families: Mutex<BTreeMap<&'static str, Family>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `by` to a counter.
+    pub fn increment(&self, metric: &Metric, labels: &[(&str, &str)], by: f64) {
+        self.update(metric, labels, |sample| {
+            if let Sample::Value(value) = sample {
+                *value += by;
+            }
+        });
+    }
+
+    /// Sets the value of a gauge.
+    pub fn set(&self, metric: &Metric, labels: &[(&str, &str)], value: f64) {
+        self.update(metric, labels, |sample| *sample = Sample::Value(value));
+    }
+
+    /// Records a value, e.g. the duration of a request in seconds, in a histogram.
+    pub fn observe(&self, metric: &Metric, labels: &[(&str, &str)], value: f64) {
+        self.update(metric, labels, |sample| {
+            if let Sample::Histogram {
+                buckets,
+                sum,
+                count,
+            } = sample
+            {
+                for (bucket, upper_bound) in buckets.iter_mut().zip(BUCKETS) {
+                    if value <= upper_bound {
+                        *bucket += 1;
+                    }
+                }
+
+                *sum += value;
+                *count += 1;
+            }
+        });
+    }
+
+    /// Removes every sample of a metric, gauges collected on each scrape are cleared first
+    /// so that the samples of partitions or brokers which are gone disappear.
+    pub fn clear(&self, metric: &Metric) {
+        if let Some(family) = self.families.lock().unwrap().get_mut(metric.name) {
+            family.samples.clear();
+        }
+    }
+
+    fn update(&self, metric: &Metric, labels: &[(&str, &str)], f: impl FnOnce(&mut Sample)) {
+        let mut families = self.families.lock().unwrap();
+
+        let family = families.entry(metric.name).or_insert_with(|| Family {
+            help: metric.help,
+            kind: metric.kind,
+            samples: BTreeMap::new(),
+        });
+
+        let mut labels: Vec<(String, String)> = labels
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        labels.sort();
+
+        let sample = family
+            .samples
+            .entry(labels)
+            .or_insert_with(|| match metric.kind {
+                MetricKind::Histogram => Sample::Histogram {
+                    buckets: [0; BUCKETS.len()],
+                    sum: 0.0,
+                    count: 0,
+                },
+                _ => Sample::Value(0.0),
+            });
+
+        f(sample);
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let families = self.families.lock().unwrap();
+        let mut text = String::new();
+
+        for (name, family) in families.iter() {
+            let _ = writeln!(text, "# HELP {} {}", name, family.help);
+            let _ = writeln!(text, "# TYPE {} {}", name, family.kind.as_str());
+
+            for (labels, sample) in family.samples.iter() {
+                match sample {
+                    Sample::Value(value) => {
+                        let _ = writeln!(text, "{}{} {}", name, format_labels(labels, None), value);
+                    }
+                    Sample::Histogram {
+                        buckets,
+                        sum,
+                        count,
+                    } => {
+                        for (bucket, upper_bound) in buckets.iter().zip(BUCKETS) {
+                            let le = upper_bound.to_string();
+                            let _ = writeln!(
+                                text,
+                                "{}_bucket{} {}",
+                                name,
+                                format_labels(labels, Some(&le)),
+                                bucket
+                            );
+                        }
+
+                        let _ = writeln!(
+                            text,
+                            "{}_bucket{} {}",
+                            name,
+                            format_labels(labels, Some("+Inf")),
+                            count
+                        );
+                        let _ =
+                            writeln!(text, "{}_sum{} {}", name, format_labels(labels, None), sum);
+                        let _ = writeln!(
+                            text,
+                            "{}_count{} {}",
+                            name,
+                            format_labels(labels, None),
+                            count
+                        );
+                    }
+                }
+            }
+        }
+
+        text
+    }
+}
+
+fn format_labels(labels: &[(String, String)], le: Option<&str>) -> String {
+    let mut pairs: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, escape_label_value(v)))
+        .collect();
+
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{}\"", le));
+    }
+
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serves the metrics over HTTP at `GET /metrics` on `addr`, `collect` is called before every
+/// scrape to refresh the gauges that are read from the state of the process.
+/// Returns the address the endpoint listens on.
+pub fn serve(
+    addr: &str,
+    metrics: Arc<Metrics>,
+    collect: impl Fn(&Metrics) + Send + 'static,
+) -> Result<SocketAddr, String> {
+    let listener = TcpListener::bind(addr)
+        .map_err(|e| format!("Failed to serve the metrics on {}: {}", addr, e))?;
+    let local_addr = listener.local_addr().map_err(|e| e.to_string())?;
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream
+                .map_err(|e| e.to_string())
+                .and_then(|stream| answer_scrape(stream, &metrics, &collect));
+
+            if let Err(e) = result {
+                log::warn!("Failed to answer a metrics scrape: {}", e);
+            }
+        }
+    });
+
+    Ok(local_addr)
+}
+
+fn answer_scrape(
+    mut stream: TcpStream,
+    metrics: &Metrics,
+    collect: &impl Fn(&Metrics),
+) -> Result<(), String> {
+    stream
+        .set_read_timeout(Some(SCRAPE_TIMEOUT))
+        .map_err(|e| e.to_string())?;
+
+    let mut reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+    let mut request_line = String::new();
+    reader
+        .read_line(&mut request_line)
+        .map_err(|e| e.to_string())?;
+
+    // Headers are of no interest, they are read so the client doesn't get a reset connection
+    let mut header = String::new();
+    while reader.read_line(&mut header).map_err(|e| e.to_string())? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_ascii_whitespace();
+
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            collect(metrics);
+            ("200 OK", metrics.render())
+        }
+        _ => (
+            "404 Not Found",
+            "Metrics are served at GET /metrics\n".to_string(),
+        ),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+    .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    const REQUESTS: Metric = Metric {
+        name: "nyx_test_requests_total",
+        help: "Requests handled",
+        kind: MetricKind::Counter,
+    };
+
+    const CONNECTIONS: Metric = Metric {
+        name: "nyx_test_connections",
+        help: "Open connections",
+        kind: MetricKind::Gauge,
+    };
+
+    const LATENCY: Metric = Metric {
+        name: "nyx_test_request_duration_seconds",
+        help: "Duration of the requests",
+        kind: MetricKind::Histogram,
+    };
+
+    #[test]
+    fn renders_the_prometheus_text_format() {
+        let metrics = Metrics::new();
+
+        metrics.increment(
+            &REQUESTS,
+            &[("topic", "notifications"), ("partition", "1")],
+            1.0,
+        );
+        metrics.increment(
+            &REQUESTS,
+            &[("partition", "1"), ("topic", "notifications")],
+            2.0,
+        );
+        metrics.set(&CONNECTIONS, &[], 4.0);
+        metrics.observe(&LATENCY, &[("request", "produce")], 0.003);
+        metrics.observe(&LATENCY, &[("request", "produce")], 2.0);
+
+        let text = metrics.render();
+
+        assert!(text.contains("# TYPE nyx_test_requests_total counter\n"));
+        // Labels are sorted, the order they are passed in doesn't matter
+        assert!(
+            text.contains("nyx_test_requests_total{partition=\"1\",topic=\"notifications\"} 3\n")
+        );
+        assert!(text.contains("nyx_test_connections 4\n"));
+        assert!(text.contains(
+            "nyx_test_request_duration_seconds_bucket{request=\"produce\",le=\"0.0025\"} 0\n"
+        ));
+        assert!(text.contains(
+            "nyx_test_request_duration_seconds_bucket{request=\"produce\",le=\"0.005\"} 1\n"
+        ));
+        assert!(text.contains(
+            "nyx_test_request_duration_seconds_bucket{request=\"produce\",le=\"+Inf\"} 2\n"
+        ));
+        assert!(text.contains("nyx_test_request_duration_seconds_count{request=\"produce\"} 2\n"));
+
+        metrics.clear(&CONNECTIONS);
+        assert!(!metrics.render().contains("nyx_test_connections 4"));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn serves_metrics_over_http() {
+        let metrics = Arc::new(Metrics::new());
+
+        let addr = serve("localhost:0", metrics, |metrics| {
+            metrics.increment(&REQUESTS, &[("topic", "a \"quoted\" name")], 1.0)
+        })
+        .unwrap();
+
+        let scrape = |path: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+
+        let response = scrape("/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("nyx_test_requests_total{topic=\"a \\\"quoted\\\" name\"} 1\n"));
+
+        // Collected again on every scrape
+        assert!(scrape("/metrics").contains("} 2\n"));
+
+        assert!(scrape("/").starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
@@ -2,6 +2,9 @@ use std::{io::Read, net::TcpStream};
 
 use crate::Message;
 
+// Upper bound of a payload following a message, a bogus size shouldn't exhaust the memory
+pub const MAX_PAYLOAD_SIZE: usize = 256 * 1024 * 1024;
+
 pub struct Reader;
 
 impl Reader {
@@ -12,6 +15,25 @@ impl Reader {
             .map_err(|e| format!("Error while deserialziing: {}", e))
     }
 
+    /// Reads the binary payload following `message`, see [`Message::payload_size`].
+    pub fn read_payload(reader: &mut impl Read, message: &Message) -> Result<Vec<u8>, String> {
+        let size = message.payload_size().unwrap_or(0);
+
+        if size > MAX_PAYLOAD_SIZE {
+            return Err(format!(
+                "Payload of {} bytes exceeds the limit of {} bytes",
+                size, MAX_PAYLOAD_SIZE
+            ));
+        }
+
+        let mut payload = vec![0u8; size];
+        reader
+            .read_exact(&mut payload)
+            .map_err(|e| format!("Error while reading the payload: {}", e))?;
+
+        Ok(payload)
+    }
+
     fn read_until_char(stream: &mut TcpStream, target_char: char) -> Result<String, String> {
         let mut buffer = [0u8; 1]; // Read one byte at a time
         let mut result = String::new();
@@ -0,0 +1,364 @@
+use std::{
+    collections::BTreeMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Version of the binary encoding, the first byte of every encoded record
+const ENCODING_VERSION: u8 = 1;
+// Length of an absent key
+const NO_KEY: u32 = u32::MAX;
+
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// A record of a partition's log, as sent by producers and handed out to consumers.
+///
+/// Keys, values and header values are arbitrary bytes. Records travel and are stored in
+/// the binary encoding of [`Record::encode`], in JSON they are base64 encoded.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Record {
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "base64_option"
+    )]
+    pub key: Option<Vec<u8>>,
+    #[serde(with = "base64_bytes")]
+    pub value: Vec<u8>,
+    #[serde(
+        default,
+        skip_serializing_if = "BTreeMap::is_empty",
+        with = "base64_map"
+    )]
+    pub headers: BTreeMap<String, Vec<u8>>,
+    /// Milliseconds since the unix epoch at which the producer created the record.
+    pub timestamp: u64,
+    /// Milliseconds since the unix epoch at which the broker appended the record to the log,
+    /// `None` until the record is stored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_append_timestamp: Option<u64>,
+}
+
+impl Record {
+    /// Record of `value` created now, without a key or headers.
+    pub fn new(value: impl Into<Vec<u8>>) -> Self {
+        Self {
+            key: None,
+            value: value.into(),
+            headers: BTreeMap::new(),
+            timestamp: now_millis(),
+            log_append_timestamp: None,
+        }
+    }
+
+    pub fn with_key(mut self, key: impl Into<Vec<u8>>) -> Self {
+        self.key = Some(key.into());
+        self
+    }
+
+    pub fn with_header(mut self, name: &str, value: impl Into<Vec<u8>>) -> Self {
+        self.headers.insert(name.to_string(), value.into());
+        self
+    }
+
+    /// Record of `value` serialized as JSON, marked with a `content-type` header of `application/json`.
+    pub fn from_json<T: serde::Serialize>(value: &T) -> Result<Self, String> {
+        let value = serde_json::to_vec(value).map_err(|e| e.to_string())?;
+        Ok(Self::new(value).with_header("content-type", JSON_CONTENT_TYPE))
+    }
+
+    /// Deserializes the value of a record holding JSON.
+    pub fn value_json<T: serde::de::DeserializeOwned>(&self) -> Result<T, String> {
+        serde_json::from_slice(&self.value).map_err(|e| format!("Record value is not JSON: {}", e))
+    }
+
+    /// Size of the record's key, value and headers in bytes, checked against the `max_message_size` of the topic.
+    pub fn size(&self) -> u64 {
+        let key_size = self.key.as_ref().map(|k| k.len()).unwrap_or(0);
+        let headers_size: usize = self.headers.iter().map(|(k, v)| k.len() + v.len()).sum();
+
+        (key_size + self.value.len() + headers_size) as u64
+    }
+}
+
+impl Record {
+    /// Appends the binary encoding of the record to `buf`, lengths and timestamps are big endian:
+    ///
+    /// `version: u8 | timestamp: u64 | has_log_append_timestamp: u8 | log_append_timestamp: u64 |
+    /// key_length: u32 (u32::MAX without key) | key | value_length: u32 | value | header_count: u32 |
+    /// (name_length: u32 | name | value_length: u32 | value)...`
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(ENCODING_VERSION);
+        buf.extend_from_slice(&self.timestamp.to_be_bytes());
+        buf.push(u8::from(self.log_append_timestamp.is_some()));
+        buf.extend_from_slice(&self.log_append_timestamp.unwrap_or(0).to_be_bytes());
+
+        match &self.key {
+            Some(key) => put_bytes(buf, key),
+            None => buf.extend_from_slice(&NO_KEY.to_be_bytes()),
+        }
+
+        put_bytes(buf, &self.value);
+
+        buf.extend_from_slice(&(self.headers.len() as u32).to_be_bytes());
+
+        for (name, value) in self.headers.iter() {
+            put_bytes(buf, name.as_bytes());
+            put_bytes(buf, value);
+        }
+    }
+
+    /// Decodes a record encoded by [`Record::encode`] at the start of `bytes`,
+    /// returns the record along with the number of bytes it took.
+    pub fn decode(bytes: &[u8]) -> Result<(Self, usize), String> {
+        let mut decoder = Decoder { bytes, position: 0 };
+
+        let version = decoder.take(1)?[0];
+
+        if version != ENCODING_VERSION {
+            return Err(format!("Unknown record encoding version {}", version));
+        }
+
+        let timestamp = decoder.u64()?;
+        let has_log_append_timestamp = decoder.take(1)?[0] == 1;
+        let log_append_timestamp = decoder.u64()?;
+
+        let key = match decoder.u32()? {
+            NO_KEY => None,
+            length => Some(decoder.take(length as usize)?.to_vec()),
+        };
+
+        let value = decoder.bytes()?.to_vec();
+
+        let mut headers = BTreeMap::new();
+
+        for _ in 0..decoder.u32()? {
+            let name = String::from_utf8(decoder.bytes()?.to_vec())
+                .map_err(|_| "Record header name is not UTF-8".to_string())?;
+            headers.insert(name, decoder.bytes()?.to_vec());
+        }
+
+        let record = Self {
+            key,
+            value,
+            headers,
+            timestamp,
+            log_append_timestamp: has_log_append_timestamp.then_some(log_append_timestamp),
+        };
+
+        Ok((record, decoder.position))
+    }
+}
+
+/// Encodes records along with their offsets, the payload of a fetch response.
+pub fn encode_batch(records: &[(u64, Record)]) -> Vec<u8> {
+    let mut buf = vec![];
+
+    for (offset, record) in records {
+        buf.extend_from_slice(&offset.to_be_bytes());
+        record.encode(&mut buf);
+    }
+
+    buf
+}
+
+/// Decodes the records and offsets encoded by [`encode_batch`].
+pub fn decode_batch(mut bytes: &[u8]) -> Result<Vec<(u64, Record)>, String> {
+    let mut records = vec![];
+
+    while !bytes.is_empty() {
+        let mut decoder = Decoder { bytes, position: 0 };
+        let offset = decoder.u64()?;
+        let (record, length) = Record::decode(&bytes[decoder.position..])?;
+
+        records.push((offset, record));
+        bytes = &bytes[decoder.position + length..];
+    }
+
+    Ok(records)
+}
+
+fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .position
+            .checked_add(length)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or("Record is truncated".to_string())?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    // Length prefixed bytes
+    fn bytes(&mut self) -> Result<&'a [u8], String> {
+        let length = self.u32()? as usize;
+        self.take(length)
+    }
+}
+
+/// Milliseconds since the unix epoch, the unit of the record timestamps.
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+mod base64_bytes {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+mod base64_option {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        bytes: &Option<Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match bytes {
+            Some(bytes) => super::base64_bytes::serialize(bytes, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<u8>>, D::Error> {
+        #[derive(serde::Deserialize)]
+        struct Bytes(#[serde(with = "super::base64_bytes")] Vec<u8>);
+
+        Ok(Option::<Bytes>::deserialize(deserializer)?.map(|b| b.0))
+    }
+}
+
+mod base64_map {
+    use std::collections::BTreeMap;
+
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        map: &BTreeMap<String, Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(map.iter().map(|(k, v)| (k, STANDARD.encode(v))))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<BTreeMap<String, Vec<u8>>, D::Error> {
+        BTreeMap::<String, String>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(k, v)| {
+                STANDARD
+                    .decode(v)
+                    .map(|v| (k, v))
+                    .map_err(serde::de::Error::custom)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_round_trip_through_json() {
+        let record = Record::new(vec![0, 159, 146, 150])
+            .with_key("user-42")
+            .with_header("content-type", "application/octet-stream");
+
+        let json = serde_json::to_value(&record).unwrap();
+
+        assert_eq!(json["key"], "dXNlci00Mg==");
+        assert_eq!(json["value"], "AJ+Slg==");
+        assert_eq!(
+            json["headers"]["content-type"],
+            "YXBwbGljYXRpb24vb2N0ZXQtc3RyZWFt"
+        );
+        // Not stored yet
+        assert!(json.get("log_append_timestamp").is_none());
+
+        assert_eq!(serde_json::from_value::<Record>(json).unwrap(), record);
+        assert_eq!(record.size(), 7 + 4 + 12 + 24);
+    }
+
+    #[test]
+    fn records_round_trip_through_the_binary_encoding() {
+        let mut stored = Record::new(vec![0, 10, 13, 255])
+            .with_key(vec![])
+            .with_header("source", "billing");
+        stored.log_append_timestamp = Some(stored.timestamp + 3);
+
+        let records = vec![(7, stored), (8, Record::new("no key, no headers"))];
+
+        let bytes = encode_batch(&records);
+        assert_eq!(decode_batch(&bytes).unwrap(), records);
+
+        // An empty key is still a key
+        assert_eq!(decode_batch(&bytes).unwrap()[0].1.key, Some(vec![]));
+
+        assert!(decode_batch(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Record::decode(&[2]).is_err());
+    }
+
+    #[test]
+    fn json_values_are_a_convenience_over_bytes() {
+        let record = Record::from_json(&serde_json::json!({ "amount": 42 })).unwrap();
+
+        assert_eq!(record.value, br#"{"amount":42}"#);
+        assert_eq!(record.headers["content-type"], b"application/json");
+        assert_eq!(
+            record.value_json::<serde_json::Value>().unwrap()["amount"],
+            42
+        );
+
+        assert!(Record::new(vec![0xff])
+            .value_json::<serde_json::Value>()
+            .is_err());
+    }
+
+    #[test]
+    fn missing_optional_fields_default() {
+        let record: Record =
+            serde_json::from_str(r#"{"value":"aGVsbG8=","timestamp":1689932467312}"#).unwrap();
+
+        assert_eq!(record.key, None);
+        assert_eq!(record.value, b"hello");
+        assert!(record.headers.is_empty());
+        assert_eq!(record.log_append_timestamp, None);
+
+        assert!(
+            serde_json::from_str::<Record>(r#"{"value":"not base64!","timestamp":0}"#).is_err()
+        );
+    }
+}
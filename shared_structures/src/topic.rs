@@ -1,9 +1,202 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+use crate::{
+    config::{parse_duration, parse_number, parse_size},
+    Config,
+};
+
+/// What happens to the messages of a topic once they're past the retention period.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum CleanupPolicy {
+    #[default]
+    Delete,
+    Compact,
+}
+
+impl CleanupPolicy {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "delete" => Ok(Self::Delete),
+            "compact" => Ok(Self::Compact),
+            _ => Err(format!(
+                "unknown cleanup policy `{}`, supported policies: delete, compact",
+                value
+            )),
+        }
+    }
+}
+
+/// Configuration of a single topic, it starts off with the defaults of the Observer's
+/// configuration and is propagated to the brokers as part of the cluster metadata.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct TopicConfig {
+    pub replica_factor: usize,
+    pub retention_period: Duration,
+    pub max_message_size: u64,
+    pub cleanup_policy: CleanupPolicy,
+    pub min_insync_replicas: usize,
+    // Times a record negatively acknowledged by a consumer is redelivered before it is dead-lettered
+    pub max_redeliveries: usize,
+    // Topic the records are routed to once they run out of redeliveries, they are dropped without one
+    pub dead_letter_topic: Option<String>,
+}
+
+pub const DEFAULT_MAX_REDELIVERIES: usize = 3;
+
+impl Default for TopicConfig {
+    fn default() -> Self {
+        Self::from_config(&Config::default())
+    }
+}
+
+impl TopicConfig {
+    pub const KEYS: [&'static str; 7] = [
+        "replica_factor",
+        "retention_period",
+        "max_message_size",
+        "cleanup_policy",
+        "min_insync_replicas",
+        "max_redeliveries",
+        "dead_letter_topic",
+    ];
+
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            replica_factor: config.replica_factor,
+            retention_period: config.retention_period,
+            max_message_size: config.max_message_size,
+            cleanup_policy: config.cleanup_policy,
+            min_insync_replicas: config.min_insync_replicas,
+            max_redeliveries: DEFAULT_MAX_REDELIVERIES,
+            dead_letter_topic: None,
+        }
+    }
+
+    /// Applies `key=value` changes such as `retention_period=1d`, all the invalid changes are reported together.
+    pub fn apply(&mut self, changes: &[(String, String)]) -> Result<(), String> {
+        let mut errors: Vec<String> = vec![];
+
+        for (key, value) in changes {
+            if let Err(e) = self.set(key, value) {
+                errors.push(format!("`{}`: {}", key, e));
+            }
+        }
+
+        if errors.is_empty() {
+            self.validate()
+        } else {
+            Err(format!(
+                "Invalid topic configuration: {}",
+                errors.join(", ")
+            ))
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "replica_factor" => self.replica_factor = parse_number(value)?,
+            "retention_period" => self.retention_period = parse_duration(value)?,
+            "max_message_size" => self.max_message_size = parse_size(value)?,
+            "cleanup_policy" => self.cleanup_policy = CleanupPolicy::parse(value)?,
+            "min_insync_replicas" => self.min_insync_replicas = parse_number(value)?,
+            "max_redeliveries" => self.max_redeliveries = parse_number(value)?,
+            // An empty value removes the dead-letter topic
+            "dead_letter_topic" => {
+                self.dead_letter_topic = Some(value.to_string()).filter(|v| !v.is_empty())
+            }
+            _ => {
+                return Err(format!(
+                    "unknown key, supported keys: {}",
+                    Self::KEYS.join(", ")
+                ))
+            }
+        };
+
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.replica_factor == 0 {
+            return Err("`replica_factor` should be at least 1.".to_string());
+        }
+
+        if self.min_insync_replicas == 0 || self.min_insync_replicas > self.replica_factor {
+            return Err(format!(
+                "`min_insync_replicas` should be between 1 and the replica factor ({}).",
+                self.replica_factor
+            ));
+        }
+
+        if self.max_message_size == 0 {
+            return Err("`max_message_size` should be greater than 0.".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+// Printed in the same format the values are set with, e.g. `retention_period=7d`
+impl std::fmt::Display for TopicConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "replica_factor={} retention_period={} max_message_size={} cleanup_policy={} min_insync_replicas={} max_redeliveries={}",
+            self.replica_factor,
+            format_duration(self.retention_period),
+            format_size(self.max_message_size),
+            match self.cleanup_policy {
+                CleanupPolicy::Delete => "delete",
+                CleanupPolicy::Compact => "compact",
+            },
+            self.min_insync_replicas,
+            self.max_redeliveries
+        )?;
+
+        match &self.dead_letter_topic {
+            Some(dead_letter_topic) => write!(f, " dead_letter_topic={}", dead_letter_topic),
+            None => Ok(()),
+        }
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+
+    for (unit, unit_millis) in [
+        ("d", 86_400_000),
+        ("h", 3_600_000),
+        ("m", 60_000),
+        ("s", 1000),
+    ] {
+        if millis >= unit_millis && millis.is_multiple_of(unit_millis) {
+            return format!("{}{}", millis / unit_millis, unit);
+        }
+    }
+
+    format!("{}ms", millis)
+}
+
+fn format_size(size: u64) -> String {
+    for (unit, unit_bytes) in [("GiB", 1 << 30), ("MiB", 1 << 20), ("KiB", 1 << 10)] {
+        if size >= unit_bytes && size.is_multiple_of(unit_bytes) {
+            return format!("{}{}", size / unit_bytes, unit);
+        }
+    }
+
+    format!("{}B", size)
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Topic {
     pub name: String,
     pub partition_count: usize,
+    // Topics created before topic configurations existed get the defaults
+    #[serde(default)]
+    pub config: TopicConfig,
 }
 
 impl Topic {
@@ -11,13 +204,102 @@ impl Topic {
         Self {
             name,
             partition_count: 0,
+            config: TopicConfig::default(),
         }
     }
 
     pub fn new_shared(name: String) -> Arc<Mutex<Self>> {
-        Arc::new(Mutex::new(Self {
-            name,
-            partition_count: 0,
-        }))
+        Arc::new(Mutex::new(Self::from(name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changes(changes: &[(&str, &str)]) -> Vec<(String, String)> {
+        changes
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn applies_topic_config_changes() {
+        let mut config = TopicConfig::default();
+
+        config
+            .apply(&changes(&[
+                ("replica_factor", "2"),
+                ("retention_period", "1d"),
+                ("max_message_size", "64KiB"),
+                ("cleanup_policy", "compact"),
+                ("min_insync_replicas", "2"),
+                ("max_redeliveries", "5"),
+                ("dead_letter_topic", "notifications-dlq"),
+            ]))
+            .unwrap();
+
+        assert_eq!(config.replica_factor, 2);
+        assert_eq!(config.retention_period, Duration::from_secs(86400));
+        assert_eq!(config.max_message_size, 64 * 1024);
+        assert_eq!(config.cleanup_policy, CleanupPolicy::Compact);
+        assert_eq!(config.min_insync_replicas, 2);
+        assert_eq!(config.max_redeliveries, 5);
+        assert_eq!(
+            config.dead_letter_topic.as_deref(),
+            Some("notifications-dlq")
+        );
+
+        config
+            .apply(&changes(&[("dead_letter_topic", "")]))
+            .unwrap();
+        assert_eq!(config.dead_letter_topic, None);
+    }
+
+    #[test]
+    fn rejects_invalid_topic_config() {
+        let mut config = TopicConfig::default();
+
+        let error = config
+            .apply(&changes(&[
+                ("cleanup_policy", "shred"),
+                ("retention", "1d"),
+            ]))
+            .unwrap_err();
+        assert!(error.contains("`cleanup_policy`"));
+        assert!(error.contains("`retention`"));
+
+        let error = config
+            .apply(&changes(&[
+                ("replica_factor", "1"),
+                ("min_insync_replicas", "2"),
+            ]))
+            .unwrap_err();
+        assert!(error.contains("min_insync_replicas"));
+    }
+
+    #[test]
+    fn topic_config_is_displayed_as_it_is_set() {
+        let config = TopicConfig::default();
+        assert_eq!(
+            config.to_string(),
+            "replica_factor=3 retention_period=7d max_message_size=1MiB cleanup_policy=delete min_insync_replicas=1 max_redeliveries=3"
+        );
+
+        let config = TopicConfig {
+            dead_letter_topic: Some("notifications-dlq".to_string()),
+            ..config
+        };
+        assert!(config
+            .to_string()
+            .ends_with("max_redeliveries=3 dead_letter_topic=notifications-dlq"));
+    }
+
+    #[test]
+    fn topics_without_config_get_the_defaults() {
+        let topic: Topic =
+            serde_json::from_str(r#"{"name":"notifications","partition_count":1}"#).unwrap();
+        assert_eq!(topic.config, TopicConfig::default());
     }
 }
@@ -0,0 +1,584 @@
+use std::{
+    cell::RefCell,
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    net::{TcpStream, ToSocketAddrs},
+    str::FromStr,
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Mutex, OnceLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use uuid::Uuid;
+
+// Spans are exported in batches, at the latest this long after they have ended
+const EXPORT_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_BATCH_SIZE: usize = 512;
+// A collector that doesn't answer shouldn't hold the spans of the next batches back for long
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(5);
+const OTLP_TRACES_PATH: &str = "/v1/traces";
+
+static TRACER: OnceLock<Tracer> = OnceLock::new();
+
+thread_local! {
+    // Spans started on this thread that haven't ended yet, the last one is the parent of the next span
+    static ACTIVE_SPANS: RefCell<Vec<TraceContext>> = const { RefCell::new(vec![]) };
+}
+
+/// Identifies a span across processes, sent along with messages in the W3C `traceparent`
+/// format, e.g. `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct TraceContext {
+    pub trace_id: u128,
+    pub span_id: u64,
+}
+
+impl Display for TraceContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "00-{:032x}-{:016x}-01", self.trace_id, self.span_id)
+    }
+}
+
+impl FromStr for TraceContext {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("`{}` is not a valid traceparent", s);
+
+        let parts: Vec<&str> = s.split('-').collect();
+
+        let [_, trace_id, span_id, _] = parts.as_slice() else {
+            return Err(error());
+        };
+
+        if trace_id.len() != 32 || span_id.len() != 16 {
+            return Err(error());
+        }
+
+        let trace_id = u128::from_str_radix(trace_id, 16).map_err(|_| error())?;
+        let span_id = u64::from_str_radix(span_id, 16).map_err(|_| error())?;
+
+        if trace_id == 0 || span_id == 0 {
+            return Err(error());
+        }
+
+        Ok(Self { trace_id, span_id })
+    }
+}
+
+impl From<TraceContext> for String {
+    fn from(context: TraceContext) -> Self {
+        context.to_string()
+    }
+}
+
+impl TryFrom<String> for TraceContext {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// Role of a span in a trace, values are the ones of OpenTelemetry.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpanKind {
+    Internal = 1,
+    // Handling a request received over the network
+    Server = 2,
+    // Sending a request over the network
+    Client = 3,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AttributeValue {
+    String(String),
+    Int(i64),
+}
+
+impl From<&str> for AttributeValue {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+
+impl From<String> for AttributeValue {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+impl From<usize> for AttributeValue {
+    fn from(value: usize) -> Self {
+        Self::Int(value as i64)
+    }
+}
+
+impl From<u64> for AttributeValue {
+    fn from(value: u64) -> Self {
+        Self::Int(value as i64)
+    }
+}
+
+#[derive(Debug)]
+struct SpanData {
+    name: String,
+    kind: SpanKind,
+    context: TraceContext,
+    parent_span_id: Option<u64>,
+    start: SystemTime,
+    end: SystemTime,
+    attributes: Vec<(String, AttributeValue)>,
+    error: Option<String>,
+}
+
+/// A timed operation of a trace, the span ends and is exported when dropped.
+/// While tracing is not initialized spans record nothing and have no context.
+#[derive(Debug)]
+pub struct Span {
+    data: Option<SpanData>,
+}
+
+impl Span {
+    /// Starts a span as a child of the span active on this thread, or of a new trace when there is none.
+    pub fn start(name: impl Into<String>, kind: SpanKind) -> Self {
+        let parent = ACTIVE_SPANS.with(|spans| spans.borrow().last().copied());
+        Self::start_with_parent(name, kind, parent)
+    }
+
+    /// Starts a span as a child of `parent`, the context received from another process.
+    /// Without it the span is started like [`Span::start`].
+    pub fn start_remote(
+        name: impl Into<String>,
+        kind: SpanKind,
+        parent: Option<TraceContext>,
+    ) -> Self {
+        match parent {
+            Some(parent) => Self::start_with_parent(name, kind, Some(parent)),
+            None => Self::start(name, kind),
+        }
+    }
+
+    fn start_with_parent(
+        name: impl Into<String>,
+        kind: SpanKind,
+        parent: Option<TraceContext>,
+    ) -> Self {
+        if TRACER.get().is_none() {
+            return Self { data: None };
+        }
+
+        let context = TraceContext {
+            trace_id: parent
+                .map(|p| p.trace_id)
+                .unwrap_or_else(|| Uuid::new_v4().as_u128()),
+            span_id: new_span_id(),
+        };
+
+        ACTIVE_SPANS.with(|spans| spans.borrow_mut().push(context));
+
+        Self {
+            data: Some(SpanData {
+                name: name.into(),
+                kind,
+                context,
+                parent_span_id: parent.map(|p| p.span_id),
+                start: SystemTime::now(),
+                end: SystemTime::now(),
+                attributes: vec![],
+                error: None,
+            }),
+        }
+    }
+
+    /// Context to send along with the requests made within the span.
+    pub fn context(&self) -> Option<TraceContext> {
+        self.data.as_ref().map(|d| d.context)
+    }
+
+    pub fn set_attribute(&mut self, key: &str, value: impl Into<AttributeValue>) {
+        if let Some(data) = self.data.as_mut() {
+            data.attributes.push((key.to_string(), value.into()));
+        }
+    }
+
+    /// Marks the operation of the span as failed.
+    pub fn set_error(&mut self, error: &str) {
+        if let Some(data) = self.data.as_mut() {
+            data.error = Some(error.to_string());
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let Some(mut data) = self.data.take() else {
+            return;
+        };
+
+        ACTIVE_SPANS.with(|spans| {
+            let mut spans = spans.borrow_mut();
+            if let Some(i) = spans.iter().rposition(|s| *s == data.context) {
+                spans.remove(i);
+            }
+        });
+
+        data.end = SystemTime::now();
+
+        if let Some(tracer) = TRACER.get() {
+            // The exporter only stops with the process
+            let _ = tracer.sender.lock().unwrap().send(Export::Span(data));
+        }
+    }
+}
+
+fn new_span_id() -> u64 {
+    // Zero is not a valid span id
+    (Uuid::new_v4().as_u128() as u64).max(1)
+}
+
+/// Where the spans are exported to, parsed from the value of `--trace-exporter`.
+#[derive(Debug, PartialEq)]
+pub enum Destination {
+    // OTLP over HTTP with a JSON body, as accepted by the OpenTelemetry collector on port 4318
+    Otlp { host: String, path: String },
+    // One OTLP JSON request per line, the format of the collector's file exporter
+    File(std::path::PathBuf),
+}
+
+impl FromStr for Destination {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("https://") {
+            return Err(format!(
+                "`{}`: only plain http is supported, export through a local collector instead",
+                s
+            ));
+        }
+
+        let Some(endpoint) = s.strip_prefix("http://") else {
+            return Ok(Self::File(s.into()));
+        };
+
+        let (host, path) = match endpoint.find('/') {
+            Some(i) if endpoint[i..] != *"/" => (&endpoint[..i], endpoint[i..].to_string()),
+            Some(i) => (&endpoint[..i], OTLP_TRACES_PATH.to_string()),
+            None => (endpoint, OTLP_TRACES_PATH.to_string()),
+        };
+
+        if host.is_empty() {
+            return Err(format!("`{}`: the endpoint is missing a host", s));
+        }
+
+        Ok(Self::Otlp {
+            host: host.to_string(),
+            path,
+        })
+    }
+}
+
+enum Export {
+    Span(SpanData),
+    Flush(Sender<()>),
+}
+
+struct Tracer {
+    sender: Mutex<Sender<Export>>,
+}
+
+/// Starts exporting the spans of the process, `exporter` is either an OTLP/HTTP endpoint such
+/// as `http://localhost:4318` or the path of a file. Spans are named after `service_name` in the exports.
+pub fn init(service_name: &str, exporter: &str) -> Result<(), String> {
+    let destination = Destination::from_str(exporter)?;
+
+    let mut file = match &destination {
+        Destination::File(path) => Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("Failed to open the trace file {}: {}", path.display(), e))?,
+        ),
+        Destination::Otlp { .. } => None,
+    };
+
+    let (sender, receiver) = mpsc::channel();
+
+    TRACER
+        .set(Tracer {
+            sender: Mutex::new(sender),
+        })
+        .map_err(|_| "Tracing has already been initialized.".to_string())?;
+
+    let service_name = service_name.to_string();
+
+    std::thread::spawn(move || export_spans(&receiver, &service_name, &destination, &mut file));
+
+    Ok(())
+}
+
+/// Waits until the spans that have ended are exported, called before the process exits.
+pub fn flush() {
+    let Some(tracer) = TRACER.get() else {
+        return;
+    };
+
+    let (sender, receiver) = mpsc::channel();
+
+    if tracer
+        .sender
+        .lock()
+        .unwrap()
+        .send(Export::Flush(sender))
+        .is_ok()
+    {
+        let _ = receiver.recv_timeout(EXPORT_TIMEOUT);
+    }
+}
+
+fn export_spans(
+    receiver: &Receiver<Export>,
+    service_name: &str,
+    destination: &Destination,
+    file: &mut Option<File>,
+) {
+    let mut batch: Vec<SpanData> = vec![];
+    let mut deadline = Instant::now() + EXPORT_INTERVAL;
+
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+
+        let flushed = match receiver.recv_timeout(timeout) {
+            Ok(Export::Span(span)) => {
+                batch.push(span);
+                if batch.len() < MAX_BATCH_SIZE {
+                    continue;
+                }
+                None
+            }
+            Ok(Export::Flush(flushed)) => Some(flushed),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+
+        if !batch.is_empty() {
+            let request = export_request(service_name, &batch).to_string();
+
+            let result = match (destination, file.as_mut()) {
+                (Destination::File(_), Some(file)) => {
+                    writeln!(file, "{}", request).map_err(|e| e.to_string())
+                }
+                (Destination::Otlp { host, path }, _) => post(host, path, &request),
+                _ => Ok(()),
+            };
+
+            if let Err(e) = result {
+                log::warn!("Failed to export {} spans: {}", batch.len(), e);
+            }
+
+            batch.clear();
+        }
+
+        if let Some(flushed) = flushed {
+            let _ = flushed.send(());
+        }
+
+        deadline = Instant::now() + EXPORT_INTERVAL;
+    }
+}
+
+// Body of an OTLP `ExportTraceServiceRequest` in its JSON encoding
+fn export_request(service_name: &str, spans: &[SpanData]) -> serde_json::Value {
+    let spans: Vec<serde_json::Value> = spans
+        .iter()
+        .map(|span| {
+            let mut json = serde_json::json!({
+                "traceId": format!("{:032x}", span.context.trace_id),
+                "spanId": format!("{:016x}", span.context.span_id),
+                "name": span.name,
+                "kind": span.kind as u8,
+                "startTimeUnixNano": unix_nanos(span.start),
+                "endTimeUnixNano": unix_nanos(span.end),
+                "attributes": span
+                    .attributes
+                    .iter()
+                    .map(|(key, value)| attribute(key, value))
+                    .collect::<Vec<_>>(),
+                "status": match &span.error {
+                    Some(error) => serde_json::json!({ "code": 2, "message": error }),
+                    None => serde_json::json!({ "code": 0 }),
+                },
+            });
+
+            if let Some(parent_span_id) = span.parent_span_id {
+                json["parentSpanId"] = format!("{:016x}", parent_span_id).into();
+            }
+
+            json
+        })
+        .collect();
+
+    serde_json::json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [attribute("service.name", &AttributeValue::from(service_name))],
+            },
+            "scopeSpans": [{
+                "scope": { "name": "nyx" },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+fn attribute(key: &str, value: &AttributeValue) -> serde_json::Value {
+    let value = match value {
+        AttributeValue::String(value) => serde_json::json!({ "stringValue": value }),
+        // 64 bit integers are strings in the JSON encoding of OTLP
+        AttributeValue::Int(value) => serde_json::json!({ "intValue": value.to_string() }),
+    };
+
+    serde_json::json!({ "key": key, "value": value })
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+fn post(host: &str, path: &str, body: &str) -> Result<(), String> {
+    let addr = host
+        .to_socket_addrs()
+        .map_err(|e| format!("{}: {}", host, e))?
+        .next()
+        .ok_or(format!("{}: no address found", host))?;
+
+    let mut stream = TcpStream::connect_timeout(&addr, EXPORT_TIMEOUT)
+        .map_err(|e| format!("{}: {}", host, e))?;
+    stream
+        .set_read_timeout(Some(EXPORT_TIMEOUT))
+        .map_err(|e| e.to_string())?;
+
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        host,
+        body.len(),
+        body
+    )
+    .map_err(|e| e.to_string())?;
+
+    let mut status_line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut status_line)
+        .map_err(|e| e.to_string())?;
+
+    match status_line.split_ascii_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        _ => Err(format!("collector answered `{}`", status_line.trim_end())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_traceparents_and_destinations() {
+        let context: TraceContext = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+            .parse()
+            .unwrap();
+
+        assert_eq!(context.trace_id, 0x4bf92f3577b34da6a3ce929d0e0e4736);
+        assert_eq!(context.span_id, 0x00f067aa0ba902b7);
+        assert_eq!(
+            context.to_string(),
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        );
+
+        assert!("00-4bf92f3577b34da6a3ce929d0e0e4736-01"
+            .parse::<TraceContext>()
+            .is_err());
+        assert!("00-00000000000000000000000000000000-00f067aa0ba902b7-01"
+            .parse::<TraceContext>()
+            .is_err());
+
+        assert_eq!(
+            "http://localhost:4318".parse::<Destination>().unwrap(),
+            Destination::Otlp {
+                host: "localhost:4318".to_string(),
+                path: "/v1/traces".to_string()
+            }
+        );
+        assert_eq!(
+            "http://collector:4318/custom/traces"
+                .parse::<Destination>()
+                .unwrap(),
+            Destination::Otlp {
+                host: "collector:4318".to_string(),
+                path: "/custom/traces".to_string()
+            }
+        );
+        assert_eq!(
+            "/tmp/nyx-spans.json".parse::<Destination>().unwrap(),
+            Destination::File("/tmp/nyx-spans.json".into())
+        );
+        assert!("https://collector:4318".parse::<Destination>().is_err());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn exports_nested_spans_to_a_file() {
+        let path = std::env::temp_dir().join(format!("nyx_tests_spans_{}.json", Uuid::new_v4()));
+
+        init("test", path.to_str().unwrap()).unwrap();
+
+        let remote: TraceContext = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+            .parse()
+            .unwrap();
+
+        {
+            let mut request = Span::start_remote("handle produce", SpanKind::Server, Some(remote));
+            request.set_attribute("topic", "notifications");
+
+            let mut put = Span::start("partition.put", SpanKind::Internal);
+            put.set_attribute("bytes", 42u64);
+            put.set_error("disk is full");
+
+            assert_eq!(put.context().unwrap().trace_id, remote.trace_id);
+        }
+
+        flush();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let export: serde_json::Value = serde_json::from_str(content.trim_end()).unwrap();
+        let spans = &export["resourceSpans"][0]["scopeSpans"][0]["spans"];
+
+        // Children end first
+        let (put, request) = (&spans[0], &spans[1]);
+
+        assert_eq!(request["name"], "handle produce");
+        assert_eq!(request["traceId"], "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(request["parentSpanId"], "00f067aa0ba902b7");
+        assert_eq!(request["kind"], 2);
+
+        assert_eq!(put["parentSpanId"], request["spanId"]);
+        assert_eq!(put["attributes"][0]["value"]["intValue"], "42");
+        assert_eq!(put["status"]["code"], 2);
+
+        assert_eq!(
+            export["resourceSpans"][0]["resource"]["attributes"][0]["value"]["stringValue"],
+            "test"
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+}